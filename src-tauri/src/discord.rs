@@ -1,4 +1,5 @@
 use crate::query;
+use crate::resolver::Resolver;
 use discord_rich_presence::{
     activity::{self, Timestamps},
     DiscordIpc, DiscordIpcClient,
@@ -15,7 +16,7 @@ pub fn toggle_drpc(toggle: bool) -> () {
     }
 }
 
-pub fn initialize_drpc() -> () {
+pub fn initialize_drpc(resolver: Resolver) -> () {
     std::thread::spawn(move || {
         #[allow(unused_assignments)]
         let mut connected = false;
@@ -90,24 +91,21 @@ pub fn initialize_drpc() -> () {
                     }
                 }
 
-                let queryserver = query::Query::new(ip.as_str(), port.parse::<i32>().unwrap());
+                // a bad port or an unresolvable host just ends up as "Unable to get server name"
+                let queryserver =
+                    query::Query::new(&resolver, ip.as_str(), port.parse::<i32>().unwrap_or(0));
                 match block_on(queryserver) {
-                    Ok(q) => {
-                        let _ = block_on(q.send('i'));
-                        match block_on(q.recv()) {
-                            Ok(p) => {
-                                let server_info: query::InfoPacket =
-                                    serde_json::from_str(p.as_str()).unwrap();
-                                hostname = server_info.hostname;
-                                players =
-                                    format!("{}/{}", server_info.players, server_info.max_players);
-                            }
-                            Err(_e) => {
-                                // println!("{}", e.to_string());
-                                hostname = "".to_string();
-                            }
+                    Ok(q) => match block_on(q.info()) {
+                        Ok(server_info) => {
+                            hostname = server_info.hostname;
+                            players =
+                                format!("{}/{}", server_info.players, server_info.max_players);
                         }
-                    }
+                        Err(_e) => {
+                            // println!("{}", e.to_string());
+                            hostname = "".to_string();
+                        }
+                    },
                     Err(_e) => {
                         // println!("{}", e.to_string());
                         hostname = "".to_string();
@@ -0,0 +1,698 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::CharIndices;
+
+use crate::helpers;
+use crate::server_store::StoredServer;
+
+const SAVED_FILTERS_FILE: &str = "saved_filters.json";
+// expressions are parsed recursively, whatever a user types or saves can't be
+// allowed to run the parser out of stack
+const MAX_DEPTH: usize = 64;
+
+/// Where and why an expression couldn't be parsed, the position is the
+/// character column starting at 1.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ExprError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at column {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    NotContains,
+}
+
+impl Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Contains => "~",
+            Op::NotContains => "!~",
+        }
+    }
+
+    fn is_ordering(&self) -> bool {
+        matches!(self, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Text(s) => write!(f, "\"{}\"", s),
+            Token::Op(op) => write!(f, "'{}'", op.symbol()),
+            Token::And => write!(f, "'&&'"),
+            Token::Or => write!(f, "'||'"),
+            Token::Not => write!(f, "'!'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Number,
+    Text,
+    Bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Field {
+    Players,
+    MaxPlayers,
+    Ping,
+    Hostname,
+    Mode,
+    Language,
+    Password,
+    Omp,
+    Partner,
+    Discord,
+    Banner,
+    // rules are text, but compare as numbers when they look like one
+    Rule(String),
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        let lower = name.to_lowercase();
+        if let Some(rule) = lower.strip_prefix("rules.") {
+            return (!rule.is_empty()).then(|| Field::Rule(rule.to_string()));
+        }
+
+        Some(match lower.as_str() {
+            "players" => Field::Players,
+            "max_players" => Field::MaxPlayers,
+            "ping" => Field::Ping,
+            "hostname" | "name" => Field::Hostname,
+            "mode" | "gamemode" => Field::Mode,
+            "lang" | "language" => Field::Language,
+            "password" => Field::Password,
+            "omp" => Field::Omp,
+            "partner" => Field::Partner,
+            "discord" => Field::Discord,
+            "banner" => Field::Banner,
+            "version" => Field::Rule("version".to_string()),
+            _ => return None,
+        })
+    }
+
+    fn kind(&self) -> Kind {
+        match self {
+            Field::Players | Field::MaxPlayers | Field::Ping => Kind::Number,
+            Field::Password | Field::Omp | Field::Partner => Kind::Bool,
+            _ => Kind::Text,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Literal {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Literal),
+    // a field on its own, true when set, non zero or not empty
+    Truthy(Field),
+}
+
+fn error(input: &str, offset: usize, message: String) -> ExprError {
+    ExprError {
+        position: input[..offset].chars().count() + 1,
+        message,
+    }
+}
+
+// consumes `c` if it's next
+fn eat(chars: &mut Peekable<CharIndices>, c: char) -> bool {
+    chars.next_if(|&(_, next)| next == c).is_some()
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '~' => Token::Op(Op::Contains),
+            '&' if eat(&mut chars, '&') => Token::And,
+            '|' if eat(&mut chars, '|') => Token::Or,
+            '&' | '|' => return Err(error(input, start, format!("expected '{}{}'", c, c))),
+            '=' if eat(&mut chars, '=') => Token::Op(Op::Eq),
+            '=' => return Err(error(input, start, "use '==' to compare".to_string())),
+            '<' if eat(&mut chars, '=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if eat(&mut chars, '=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '!' if eat(&mut chars, '=') => Token::Op(Op::Ne),
+            '!' if eat(&mut chars, '~') => Token::Op(Op::NotContains),
+            '!' => Token::Not,
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            if let Some((_, escaped)) = chars.next() {
+                                text.push(escaped);
+                            }
+                        }
+                        Some((_, q)) if q == c => break,
+                        Some((_, other)) => text.push(other),
+                        None => return Err(error(input, start, "unterminated string".to_string())),
+                    }
+                }
+                Token::Text(text)
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.') {
+                    end = i + 1;
+                }
+                let number = &input[start..end];
+                match number.parse() {
+                    Ok(n) => Token::Number(n),
+                    Err(_) => {
+                        return Err(error(input, start, format!("invalid number {}", number)))
+                    }
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) =
+                    chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_' || c == '.')
+                {
+                    end = i + c.len_utf8();
+                }
+                Token::Ident(input[start..end].to_string())
+            }
+            other => {
+                return Err(error(
+                    input,
+                    start,
+                    format!("unexpected character '{}'", other),
+                ))
+            }
+        };
+        tokens.push((token, start));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    // '!' and '(' we're inside of
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(t, _)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.input.len(), |(_, offset)| *offset)
+    }
+
+    fn fail<T>(&self, message: String) -> Result<T, ExprError> {
+        Err(error(self.input, self.offset(), message))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ExprError> {
+        match self.peek() {
+            Some(token) => self.fail(format!("expected {}, found {}", expected, token)),
+            None => self.fail(format!("expected {}, found the end", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ExprError> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next += 1;
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        if matches!(self.peek(), Some(Token::Not | Token::Open)) {
+            if self.depth == MAX_DEPTH {
+                return self.fail(format!("nested more than {} levels deep", MAX_DEPTH));
+            }
+            self.depth += 1;
+            let expr = self.nested();
+            self.depth -= 1;
+            return expr;
+        }
+
+        match self.peek() {
+            Some(Token::Ident(_)) => self.comparison(),
+            _ => self.unexpected("a field"),
+        }
+    }
+
+    fn nested(&mut self) -> Result<Expr, ExprError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.next += 1;
+                let expr = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return self.unexpected("')'");
+                }
+                self.next += 1;
+                Ok(expr)
+            }
+            _ => self.unexpected("'!' or '('"),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ExprError> {
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return self.unexpected("a field"),
+        };
+        let field = match Field::from_name(&name) {
+            Some(field) => field,
+            None => return self.fail(format!("unknown field '{}'", name)),
+        };
+        self.next += 1;
+
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return Ok(Expr::Truthy(field)),
+        };
+        let op_offset = self.offset();
+        self.next += 1;
+
+        let value = match self.peek() {
+            Some(Token::Number(n)) => Literal::Number(*n),
+            Some(Token::Text(s)) => Literal::Text(s.clone()),
+            Some(Token::Ident(i)) if i == "true" => Literal::Bool(true),
+            Some(Token::Ident(i)) if i == "false" => Literal::Bool(false),
+            _ => return self.unexpected(&format!("a value after '{}'", op.symbol())),
+        };
+
+        let kind = field.kind();
+        let is_rule = matches!(field, Field::Rule(_));
+        let mismatch = |message: String| Err(error(self.input, op_offset, message));
+        let value = match (&value, op) {
+            (Literal::Number(_), op) if op.is_ordering() && (kind == Kind::Number || is_rule) => {
+                value
+            }
+            (_, op) if op.is_ordering() => {
+                return mismatch(format!("'{}' compares numbers only", op.symbol()))
+            }
+            (Literal::Bool(_), _) if kind == Kind::Bool && matches!(op, Op::Eq | Op::Ne) => value,
+            (_, _) if kind == Kind::Bool => {
+                return mismatch(format!("{} can only be == or != true or false", name))
+            }
+            (Literal::Number(_), Op::Eq | Op::Ne) if kind == Kind::Number => value,
+            (_, _) if kind == Kind::Number => {
+                return mismatch(format!("{} has to be compared with a number", name))
+            }
+            (Literal::Bool(_), _) => {
+                return mismatch(format!("{} can't be compared with true or false", name))
+            }
+            // text fields take numbers as text, `version ~ 0.3`
+            (Literal::Number(n), _) => Literal::Text(n.to_string()),
+            _ => value,
+        };
+
+        self.next += 1;
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+/// A parsed filter expression, ready to be run against servers.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+/// Parses a filter expression like
+/// `players >= 50 && lang ~ "ru" && !password && ping < 120`.
+pub fn parse(input: &str) -> Result<Filter, ExprError> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Err(error(input, input.len(), "the filter is empty".to_string()));
+    }
+
+    let mut parser = Parser {
+        input,
+        tokens,
+        next: 0,
+        depth: 0,
+    };
+    let expr = parser.or()?;
+    if parser.peek().is_some() {
+        return parser.unexpected("'&&', '||' or the end");
+    }
+    Ok(Filter { expr })
+}
+
+enum Value<'a> {
+    Number(f64),
+    Text(&'a str),
+    Bool(bool),
+}
+
+fn value<'a>(server: &'a StoredServer, field: &Field) -> Option<Value<'a>> {
+    let non_empty = |s: &'a str| (!s.is_empty()).then_some(Value::Text(s));
+    match field {
+        Field::Players => Some(Value::Number(server.player_count as f64)),
+        Field::MaxPlayers => Some(Value::Number(server.max_players as f64)),
+        Field::Ping => server.ping.map(|p| Value::Number(p as f64)),
+        Field::Hostname => Some(Value::Text(&server.hostname)),
+        Field::Mode => Some(Value::Text(&server.game_mode)),
+        Field::Language => Some(Value::Text(&server.language)),
+        Field::Password => Some(Value::Bool(server.has_password)),
+        Field::Omp => Some(Value::Bool(server.using_omp)),
+        Field::Partner => Some(Value::Bool(server.partner)),
+        Field::Discord => server
+            .extra_info
+            .as_ref()
+            .and_then(|e| non_empty(&e.discord_link)),
+        Field::Banner => server
+            .extra_info
+            .as_ref()
+            .and_then(|e| non_empty(&e.light_banner_url).or_else(|| non_empty(&e.dark_banner_url))),
+        Field::Rule(name) => server
+            .rules
+            .iter()
+            .find(|(rule, _)| rule.eq_ignore_ascii_case(name))
+            .map(|(_, v)| Value::Text(v)),
+    }
+}
+
+fn compare(value: Value, op: Op, literal: &Literal) -> bool {
+    match (value, literal) {
+        (Value::Text(text), Literal::Number(n)) => match text.trim().parse::<f64>() {
+            Ok(number) => compare(Value::Number(number), op, &Literal::Number(*n)),
+            Err(_) => false,
+        },
+        (Value::Number(a), Literal::Number(b)) => match op {
+            Op::Eq => a == *b,
+            Op::Ne => a != *b,
+            Op::Lt => a < *b,
+            Op::Le => a <= *b,
+            Op::Gt => a > *b,
+            Op::Ge => a >= *b,
+            _ => false,
+        },
+        (Value::Text(text), Literal::Text(wanted)) => {
+            let text = text.to_lowercase();
+            let wanted = wanted.to_lowercase();
+            match op {
+                Op::Eq => text == wanted,
+                Op::Ne => text != wanted,
+                Op::Contains => text.contains(&wanted),
+                Op::NotContains => !text.contains(&wanted),
+                _ => false,
+            }
+        }
+        (Value::Bool(a), Literal::Bool(b)) => match op {
+            Op::Eq => a == *b,
+            Op::Ne => a != *b,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl Filter {
+    /// Whether `server` passes. Fields the server doesn't have, like the ping
+    /// of one that never answered or a rule it doesn't set, fail every
+    /// comparison.
+    pub fn matches(&self, server: &StoredServer) -> bool {
+        self.expr.matches(server)
+    }
+}
+
+impl Expr {
+    fn matches(&self, server: &StoredServer) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(server) && b.matches(server),
+            Expr::Or(a, b) => a.matches(server) || b.matches(server),
+            Expr::Not(expr) => !expr.matches(server),
+            Expr::Compare(field, op, literal) => {
+                value(server, field).is_some_and(|v| compare(v, *op, literal))
+            }
+            Expr::Truthy(field) => match value(server, field) {
+                Some(Value::Number(n)) => n != 0.0,
+                Some(Value::Text(s)) => !s.is_empty(),
+                Some(Value::Bool(b)) => b,
+                None => false,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SavedFilter {
+    pub name: String,
+    pub expression: String,
+}
+
+pub fn load(dir: &Path) -> Vec<SavedFilter> {
+    let data = match fs::read(dir.join(SAVED_FILTERS_FILE)) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    match serde_json::from_slice(&data) {
+        Ok(filters) => filters,
+        Err(e) => {
+            info!("[filter_expr.rs] Ignoring broken saved filters: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Saves `filter` under its name, replacing the one that had it. Expressions
+/// that don't parse aren't saved.
+pub fn save(dir: &Path, mut filter: SavedFilter) -> Result<Vec<SavedFilter>, Error> {
+    filter.name = filter.name.trim().to_string();
+    if filter.name.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "filter name is required",
+        ));
+    }
+    parse(&filter.expression).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+    let mut filters = load(dir);
+    match filters.iter_mut().find(|f| f.name == filter.name) {
+        Some(existing) => *existing = filter,
+        None => filters.push(filter),
+    }
+    helpers::save_json(dir, SAVED_FILTERS_FILE, &filters)?;
+    Ok(filters)
+}
+
+pub fn remove(dir: &Path, name: &str) -> Result<bool, Error> {
+    let mut filters = load(dir);
+    let count = filters.len();
+    filters.retain(|f| f.name != name);
+    if filters.len() == count {
+        return Ok(false);
+    }
+    helpers::save_json(dir, SAVED_FILTERS_FILE, &filters)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::temp_dir;
+    use crate::query::ExtraInfoPacket;
+
+    fn server() -> StoredServer {
+        StoredServer {
+            ip: "127.0.0.1".to_string(),
+            hostname: "Russian Roleplay".to_string(),
+            game_mode: "RP".to_string(),
+            language: "Русский / RU".to_string(),
+            player_count: 75,
+            max_players: 100,
+            ping: Some(90),
+            rules: [
+                ("version".to_string(), "omp 1.2.0".to_string()),
+                ("worldtime".to_string(), "12".to_string()),
+            ]
+            .into(),
+            extra_info: Some(ExtraInfoPacket {
+                discord_link: "https://discord.gg/example".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn matches(input: &str) -> bool {
+        parse(input).unwrap().matches(&server())
+    }
+
+    fn error_of(input: &str) -> ExprError {
+        parse(input).unwrap_err()
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        assert!(matches(
+            r#"players >= 50 && lang ~ "ru" && !password && rules.version ~ "omp" && ping < 120"#
+        ));
+        assert!(!matches("players > 75"));
+        assert!(matches(
+            "players > 100 || (mode == 'rp' && max_players == 100)"
+        ));
+        assert!(matches("!(omp || partner) && discord && !banner"));
+        assert!(matches("rules.worldtime <= 12 && version !~ \"0.3.7\""));
+        assert!(matches("name ~ \"missing\" || hostname ~ \"ROLEPLAY\""));
+        // && binds tighter than ||
+        assert!(matches("ping > 500 && players > 500 || password == false"));
+    }
+
+    #[test]
+    fn missing_fields_fail_comparisons() {
+        let mut server = server();
+        server.ping = None;
+        assert!(!parse("ping < 120").unwrap().matches(&server));
+        assert!(!parse("ping >= 120").unwrap().matches(&server));
+        assert!(!parse("rules.weburl ~ \"\"").unwrap().matches(&server));
+        assert!(parse("!rules.weburl").unwrap().matches(&server));
+    }
+
+    #[test]
+    fn reports_clear_errors() {
+        assert_eq!(
+            error_of("players >= 50 &&"),
+            ExprError {
+                position: 17,
+                message: "expected a field, found the end".to_string(),
+            }
+        );
+        assert_eq!(error_of("player > 5").message, "unknown field 'player'");
+        assert_eq!(error_of("players = 5").position, 9);
+        assert_eq!(error_of("lang ~ \"ru").message, "unterminated string");
+        assert_eq!(
+            error_of("hostname < 5").message,
+            "'<' compares numbers only"
+        );
+        assert_eq!(
+            error_of("password ~ \"x\"").message,
+            "password can only be == or != true or false"
+        );
+        assert_eq!(error_of("(ping < 5").message, "expected ')', found the end");
+        assert_eq!(
+            error_of("ping < 5 ping").message,
+            "expected '&&', '||' or the end, found 'ping'"
+        );
+        assert_eq!(error_of("  ").message, "the filter is empty");
+        // deep nesting is refused instead of running out of stack
+        assert!(matches(&format!(
+            "{}ping < 120{}",
+            "(".repeat(64),
+            ")".repeat(64)
+        )));
+        let nested = format!("{}ping < 120{}", "(".repeat(5000), ")".repeat(5000));
+        assert_eq!(error_of(&nested).message, "nested more than 64 levels deep");
+        assert_eq!(error_of(&nested).position, 65);
+        assert!(error_of(&"!".repeat(5000)).message.starts_with("nested"));
+        // columns count characters, not bytes
+        assert_eq!(error_of("lang ~ \"рус\" & ping").position, 14);
+    }
+
+    #[test]
+    fn saves_filters() {
+        let dir = temp_dir("saved_filters");
+
+        let filter = SavedFilter {
+            name: "big ru".to_string(),
+            expression: "players >= 50 && lang ~ \"ru\"".to_string(),
+        };
+        assert_eq!(save(&dir, filter.clone()).unwrap(), vec![filter.clone()]);
+
+        let broken = SavedFilter {
+            expression: "players >=".to_string(),
+            ..filter.clone()
+        };
+        assert_eq!(
+            save(&dir, broken).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(load(&dir), vec![filter]);
+
+        assert!(remove(&dir, "big ru").unwrap());
+        assert!(load(&dir).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
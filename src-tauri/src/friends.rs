@@ -0,0 +1,346 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::helpers;
+use crate::query_pool::Endpoint;
+use crate::subscriptions::ServerDiff;
+
+const SETTINGS_FILE: &str = "friends.json";
+/// Name of the subscription that keeps the watched servers refreshed.
+pub const SUBSCRIPTION: &str = "friends";
+// every watched server gets a player list query per check, so checks can't
+// come too often and the popular servers taken along are capped
+const MIN_INTERVAL: u64 = 30;
+const MAX_POPULAR: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct FriendSettings {
+    pub friends: Vec<String>,
+    // seconds between two checks of the same server
+    pub interval: u64,
+    // whether favorites are watched
+    pub favorites: bool,
+    // how many of the most populated internet servers are watched as well
+    pub popular: usize,
+    // whether the frontend shows a system notification, events come either way
+    pub notify: bool,
+}
+
+impl Default for FriendSettings {
+    fn default() -> Self {
+        Self {
+            friends: Vec::new(),
+            interval: 60,
+            favorites: true,
+            popular: 20,
+            notify: true,
+        }
+    }
+}
+
+/// A friend showed up on a server or left it.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct FriendStatus {
+    pub name: String,
+    pub ip: String,
+    pub port: i32,
+    pub online: bool,
+}
+
+pub fn load(dir: &Path) -> FriendSettings {
+    let data = match fs::read(dir.join(SETTINGS_FILE)) {
+        Ok(data) => data,
+        Err(_) => return FriendSettings::default(),
+    };
+    match serde_json::from_slice(&data) {
+        // the file may have been edited by hand
+        Ok(settings) => cleaned(settings),
+        Err(e) => {
+            info!("[friends.rs] Ignoring broken friend settings: {}", e);
+            FriendSettings::default()
+        }
+    }
+}
+
+/// Saves `settings` with blank and repeated names dropped and the cadence and
+/// scope kept within limits, and returns what was saved.
+pub fn save(dir: &Path, settings: FriendSettings) -> Result<FriendSettings, Error> {
+    let settings = cleaned(settings);
+    helpers::save_json(dir, SETTINGS_FILE, &settings)?;
+    Ok(settings)
+}
+
+fn cleaned(mut settings: FriendSettings) -> FriendSettings {
+    let mut seen = HashSet::new();
+    settings.friends = settings
+        .friends
+        .iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && seen.insert(name.to_lowercase()))
+        .collect();
+    settings.interval = settings.interval.max(MIN_INTERVAL);
+    settings.popular = settings.popular.min(MAX_POPULAR);
+    settings
+}
+
+/// Servers to watch, `favorites` if the settings ask for them followed by
+/// `popular`, each one once.
+pub fn targets(
+    settings: &FriendSettings,
+    favorites: Vec<Endpoint>,
+    popular: Vec<Endpoint>,
+) -> Vec<Endpoint> {
+    let favorites = if settings.favorites {
+        favorites
+    } else {
+        Vec::new()
+    };
+
+    let mut seen = HashSet::new();
+    favorites
+        .into_iter()
+        .chain(popular)
+        .filter(|e| seen.insert((e.ip.to_lowercase(), e.port)))
+        .collect()
+}
+
+/// Tells from subscription updates when friends join or leave the watched
+/// servers.
+pub struct FriendWatch {
+    // lowercase names
+    friends: Mutex<HashSet<String>>,
+    // friends on each server, lowercase name to the server's spelling
+    online: Mutex<HashMap<(String, i32), HashMap<String, String>>>,
+}
+
+impl FriendWatch {
+    pub fn new() -> Self {
+        Self {
+            friends: Mutex::new(HashSet::new()),
+            online: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts over with `friends`. The subscription is started over along
+    /// with it, so whoever is online gets reported again.
+    pub fn reset(&self, friends: &[String]) {
+        *self.friends.lock().unwrap() = friends.iter().map(|f| f.to_lowercase()).collect();
+        self.online.lock().unwrap().clear();
+    }
+
+    /// Friends that joined or left the server with this update.
+    pub fn apply(&self, ip: &str, port: i32, diff: &ServerDiff) -> Vec<FriendStatus> {
+        let names: Vec<&str> = if let Some(players) = &diff.player_list {
+            players.iter().map(|p| p.name.as_str()).collect()
+        } else if let Some(players) = &diff.detailed_player_list {
+            players.iter().map(|p| p.name.as_str()).collect()
+        } else if diff.online == Some(false) {
+            Vec::new()
+        } else {
+            return Vec::new();
+        };
+
+        let friends = self.friends.lock().unwrap();
+        let present: HashMap<String, String> = names
+            .into_iter()
+            .map(|name| (name.to_lowercase(), name.to_string()))
+            .filter(|(key, _)| friends.contains(key))
+            .collect();
+
+        let server = (ip.to_string(), port);
+        let mut online = self.online.lock().unwrap();
+        let previous = online.remove(&server).unwrap_or_default();
+
+        let status = |name: &String, online| FriendStatus {
+            name: name.clone(),
+            ip: ip.to_string(),
+            port,
+            online,
+        };
+        let mut changes: Vec<FriendStatus> = present
+            .iter()
+            .filter(|(key, _)| !previous.contains_key(*key))
+            .map(|(_, name)| status(name, true))
+            .chain(
+                previous
+                    .iter()
+                    .filter(|(key, _)| !present.contains_key(*key))
+                    .map(|(_, name)| status(name, false)),
+            )
+            .collect();
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if !present.is_empty() {
+            online.insert(server, present);
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::temp_dir;
+    use crate::query::{Player, QueryResponse};
+    use crate::query_pool::BatchResult;
+
+    fn players(names: &[&str]) -> ServerDiff {
+        ServerDiff {
+            player_list: Some(
+                names
+                    .iter()
+                    .map(|name| Player {
+                        name: name.to_string(),
+                        score: 0,
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn changes(watch: &FriendWatch, diff: &ServerDiff) -> Vec<(String, bool)> {
+        watch
+            .apply("10.0.0.1", 7777, diff)
+            .into_iter()
+            .map(|s| (s.name, s.online))
+            .collect()
+    }
+
+    #[test]
+    fn reports_friends_joining_and_leaving() {
+        let watch = FriendWatch::new();
+        watch.reset(&["john_doe".to_string(), "Jane".to_string()]);
+
+        assert_eq!(
+            changes(&watch, &players(&["John_Doe", "Mike"])),
+            vec![("John_Doe".to_string(), true)]
+        );
+        // nothing new about friends
+        assert!(changes(&watch, &players(&["John_Doe"])).is_empty());
+        assert!(changes(
+            &watch,
+            &ServerDiff {
+                players: Some(3),
+                ..Default::default()
+            }
+        )
+        .is_empty());
+
+        assert_eq!(
+            changes(&watch, &players(&["Jane"])),
+            vec![("Jane".to_string(), true), ("John_Doe".to_string(), false)]
+        );
+        assert_eq!(
+            changes(
+                &watch,
+                &ServerDiff {
+                    online: Some(false),
+                    ..Default::default()
+                }
+            ),
+            vec![("Jane".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn reports_friends_again_once_their_server_is_back() {
+        let watch = FriendWatch::new();
+        watch.reset(&["john_doe".to_string()]);
+
+        let result = |query_type, response: Option<QueryResponse>| BatchResult {
+            ip: "10.0.0.1".to_string(),
+            port: 7777,
+            query_type,
+            ping: None,
+            timed_out: response.is_none(),
+            response,
+            error: None,
+            changed: true,
+        };
+        let players = || {
+            Some(QueryResponse::Players(vec![Player {
+                name: "John_Doe".to_string(),
+                score: 0,
+            }]))
+        };
+
+        // the subscription's state, the way it produces diffs
+        let mut state = ServerDiff::default();
+        let diff = state.apply(&result('c', players()));
+        assert_eq!(changes(&watch, &diff), vec![("John_Doe".to_string(), true)]);
+
+        let diff = state.apply(&result('i', None));
+        assert_eq!(
+            changes(&watch, &diff),
+            vec![("John_Doe".to_string(), false)]
+        );
+
+        let diff = state.apply(&result('c', players()));
+        assert_eq!(changes(&watch, &diff), vec![("John_Doe".to_string(), true)]);
+    }
+
+    #[test]
+    fn saves_settings_within_limits() {
+        let dir = temp_dir("friends_save");
+        assert_eq!(load(&dir), FriendSettings::default());
+
+        let saved = save(
+            &dir,
+            FriendSettings {
+                friends: vec![
+                    " John_Doe ".to_string(),
+                    "john_doe".to_string(),
+                    "".to_string(),
+                    "Jane".to_string(),
+                ],
+                interval: 1,
+                popular: 10_000,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(saved.friends, vec!["John_Doe", "Jane"]);
+        assert_eq!(saved.interval, MIN_INTERVAL);
+        assert_eq!(saved.popular, MAX_POPULAR);
+        assert_eq!(load(&dir), saved);
+
+        let endpoint = |ip: &str| Endpoint {
+            ip: ip.to_string(),
+            port: 7777,
+        };
+        let targets = targets(
+            &saved,
+            vec![endpoint("10.0.0.1"), endpoint("10.0.0.2")],
+            vec![endpoint("10.0.0.2"), endpoint("10.0.0.3")],
+        );
+        let ips: Vec<&str> = targets.iter().map(|e| e.ip.as_str()).collect();
+        assert_eq!(ips, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn loads_settings_within_limits() {
+        let dir = temp_dir("friends_load");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(SETTINGS_FILE),
+            r#"{"friends":["Jane","jane"],"interval":0,"popular":10000}"#,
+        )
+        .unwrap();
+
+        let loaded = load(&dir);
+        assert_eq!(loaded.friends, vec!["Jane"]);
+        assert_eq!(loaded.interval, MIN_INTERVAL);
+        assert_eq!(loaded.popular, MAX_POPULAR);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,7 @@
+use serde::Serialize;
 use std::fs;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use chardet::{charset2encoding, detect};
 use charset_normalizer_rs::from_bytes;
@@ -110,3 +112,36 @@ pub fn copy_files(src: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<(), S
         }
     }
 }
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Writes `value` as json to `dir/file`. It goes next to the old file first
+/// and is renamed over it, so a crash halfway through leaves that one intact.
+pub fn save_json<T: Serialize>(dir: &Path, file: &str, value: &T) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!("{}.tmp", file));
+    fs::write(
+        &tmp,
+        serde_json::to_vec(value).map_err(std::io::Error::from)?,
+    )?;
+    fs::rename(tmp, dir.join(file))
+}
+
+/// An empty directory under the system temp dir for a test to write to,
+/// `name` has to be unique among the tests.
+#[cfg(test)]
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "omp_launcher_{}_{}_{}",
+        name,
+        std::process::id(),
+        now()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
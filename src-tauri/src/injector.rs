@@ -1,12 +1,14 @@
 #[cfg(target_os = "windows")]
+use crate::resolver;
+use crate::resolver::Resolver;
+#[cfg(target_os = "windows")]
 use dll_syringe::{process::OwnedProcess, Syringe};
 use log::info;
-use regex::Regex;
 use std::process::Command;
-use tokio::net::lookup_host;
 
 #[cfg(not(target_os = "windows"))]
 pub async fn run_samp(
+    resolver: &Resolver,
     name: &str,
     ip: &str,
     port: i32,
@@ -19,6 +21,7 @@ pub async fn run_samp(
 
 #[cfg(target_os = "windows")]
 pub async fn run_samp(
+    resolver: &Resolver,
     name: &str,
     ip: &str,
     port: i32,
@@ -29,40 +32,18 @@ pub async fn run_samp(
     // Prepare the command to spawn the executable
     let mut cmd = Command::new(format!("{}/gta_sa.exe", executable_dir));
 
-    let regex = Regex::new(r"^(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)$").unwrap();
-    let address = match regex.captures(ip) {
-        Some(_) => {
-            // it's valid ipv4, move on
-            ip.to_string()
-        }
-        None => {
-            info!(
-                "[injector.rs] Address {} is not IPv4, trying to perform host lookup.",
-                ip
-            );
-            let socket_addresses = lookup_host(format!("{}:{}", ip, port)).await;
-            match socket_addresses {
-                Ok(s) => {
-                    let mut ipv4 = "".to_string();
-                    for socket_address in s {
-                        if socket_address.is_ipv4() {
-                            // hostname is resolved to ipv4:port, lets split it by ":" and get ipv4 only
-                            let ip_port = socket_address.to_string();
-                            let vec: Vec<&str> = ip_port.split(':').collect();
-                            ipv4 = vec[0].to_string();
-                        }
-                    }
-                    ipv4
-                }
-                Err(e) => {
-                    info!(
-                        "[injector.rs] Host lookup for {} failed: {}",
-                        ip,
-                        e.to_string()
-                    );
-                    "".to_string()
-                }
+    let address = match resolver.resolve(ip, port).await {
+        Ok(addresses) => {
+            // the game only takes one address, go with the first IPv4 one if there is
+            let address = resolver::preferred(&addresses).unwrap().ip().to_string();
+            if resolver::parse_ip(ip).is_none() {
+                info!("[injector.rs] Host {} resolved to {}", ip, address);
             }
+            address
+        }
+        Err(e) => {
+            info!("[injector.rs] Resolving {} failed: {}", ip, e);
+            return Err(e.to_string());
         }
     };
 
@@ -0,0 +1,244 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{interval, sleep_until, Instant};
+
+use crate::query::{self, InfoPacket, QueryResponse};
+use crate::query_parser;
+
+// subnets wider than this are only swept around our own address, a /16 on
+// every port in the range would be millions of packets
+const MAX_PREFIX: u32 = 24;
+const MAX_PORTS: u16 = 100;
+// queries go out in small bursts, so a sweep doesn't flood the network or
+// overrun the receive buffer with replies
+const BURST: usize = 32;
+const BURST_INTERVAL: Duration = Duration::from_millis(2);
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct DiscoveryOptions {
+    pub first_port: u16,
+    pub last_port: u16,
+    // milliseconds to wait for replies after the last query went out
+    pub timeout: u64,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        Self {
+            first_port: 7777,
+            last_port: 7800,
+            timeout: 1000,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct LanServer {
+    pub ip: String,
+    pub port: i32,
+    pub ping: u32,
+    pub info: InfoPacket,
+}
+
+// every host of the subnet `ip` is on, narrowed down to MAX_PREFIX
+fn subnet_hosts(ip: Ipv4Addr, netmask: Ipv4Addr) -> Vec<Ipv4Addr> {
+    let prefix = u32::from(netmask).leading_ones().max(MAX_PREFIX);
+    if prefix >= 31 {
+        return vec![ip];
+    }
+
+    let mask = u32::MAX << (32 - prefix);
+    let network = u32::from(ip) & mask;
+    let broadcast = network | !mask;
+    (network + 1..broadcast).map(Ipv4Addr::from).collect()
+}
+
+/// Addresses to query for `options`, every port on loopback and on the hosts
+/// of each local IPv4 subnet.
+pub fn targets(options: &DiscoveryOptions) -> Result<Vec<SocketAddr>, Error> {
+    if options.first_port == 0 || options.first_port > options.last_port {
+        return Err(Error::new(ErrorKind::InvalidInput, "invalid port range"));
+    }
+    if options.last_port - options.first_port >= MAX_PORTS {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("port range can't be wider than {} ports", MAX_PORTS),
+        ));
+    }
+
+    // servers on this machine answer on loopback, our own interface
+    // addresses are skipped so they don't show up twice
+    let mut hosts = BTreeSet::from([Ipv4Addr::LOCALHOST]);
+    let mut own = BTreeSet::new();
+    for interface in if_addrs::get_if_addrs()? {
+        if let if_addrs::IfAddr::V4(addr) = interface.addr {
+            if addr.is_loopback() || addr.is_link_local() {
+                continue;
+            }
+            own.insert(addr.ip);
+            hosts.extend(subnet_hosts(addr.ip, addr.netmask));
+        }
+    }
+
+    Ok(hosts
+        .difference(&own)
+        .flat_map(|ip| {
+            (options.first_port..=options.last_port)
+                .map(move |port| SocketAddr::new((*ip).into(), port))
+        })
+        .collect())
+}
+
+/// Sends an info query to every one of `targets` from a single socket and
+/// calls `on_found` for each server that answers, as the answers come in.
+/// Returns how many were found once `wait` has passed since the last query.
+pub async fn discover<F>(
+    targets: Vec<SocketAddr>,
+    wait: Duration,
+    on_found: F,
+) -> Result<usize, Error>
+where
+    F: Fn(LanServer),
+{
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let mut pending = targets.into_iter().peekable();
+    // when each query went out, anything that isn't in here isn't ours
+    let mut sent: HashMap<SocketAddr, Instant> = HashMap::new();
+    let mut found = 0;
+
+    let mut bursts = interval(BURST_INTERVAL);
+    let mut deadline = Instant::now() + wait;
+    let mut buf = vec![0; query::MAX_DATAGRAM_LEN];
+
+    loop {
+        let sending = pending.peek().is_some();
+        tokio::select! {
+            _ = bursts.tick(), if sending => {
+                for target in pending.by_ref().take(BURST) {
+                    let packet = query::build_packet(target, 'i');
+                    match socket.send_to(&packet, target).await {
+                        Ok(_) => {
+                            sent.insert(target, Instant::now());
+                        }
+                        Err(e) => info!("[lan_discovery.rs] Unable to query {}: {}", target, e),
+                    }
+                }
+                if pending.peek().is_none() {
+                    deadline = Instant::now() + wait;
+                }
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (amt, from) = match received {
+                    Ok(r) => r,
+                    // windows reports icmp port unreachable replies to
+                    // earlier queries as a reset, they just mean no server
+                    Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                    Err(e) => return Err(e),
+                };
+                let sent_at = match sent.get(&from) {
+                    Some(at) => *at,
+                    None => continue,
+                };
+
+                let request = query::build_packet(from, 'i');
+                match query_parser::parse_reply(&buf[..amt], &request) {
+                    Ok(QueryResponse::Info(info)) => {
+                        sent.remove(&from);
+                        found += 1;
+                        on_found(LanServer {
+                            ip: from.ip().to_string(),
+                            port: from.port() as i32,
+                            ping: sent_at.elapsed().as_millis() as u32,
+                            info,
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(e) => info!("[lan_discovery.rs] Dropping reply from {}: {}", from, e),
+                }
+            }
+            _ = sleep_until(deadline), if !sending => break,
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockConfig, MockServer};
+    use std::sync::Mutex;
+
+    #[test]
+    fn narrows_wide_subnets() {
+        let hosts = subnet_hosts(
+            Ipv4Addr::new(192, 168, 1, 20),
+            Ipv4Addr::new(255, 255, 0, 0),
+        );
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(192, 168, 1, 254));
+
+        let hosts = subnet_hosts(
+            Ipv4Addr::new(10, 0, 0, 5),
+            Ipv4Addr::new(255, 255, 255, 252),
+        );
+        assert_eq!(
+            hosts,
+            vec![Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::new(10, 0, 0, 6)]
+        );
+
+        assert_eq!(
+            subnet_hosts(Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::BROADCAST),
+            vec![Ipv4Addr::new(10, 0, 0, 5)]
+        );
+    }
+
+    #[test]
+    fn validates_port_range() {
+        let options = |first_port, last_port| DiscoveryOptions {
+            first_port,
+            last_port,
+            ..Default::default()
+        };
+        assert!(targets(&options(7800, 7777)).is_err());
+        assert!(targets(&options(0, 10)).is_err());
+        assert!(targets(&options(7000, 8000)).is_err());
+
+        let targets = targets(&options(7777, 7778)).unwrap();
+        assert!(targets.contains(&"127.0.0.1:7777".parse().unwrap()));
+        assert!(targets.contains(&"127.0.0.1:7778".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn finds_answering_servers() {
+        let first = MockServer::start(MockConfig::default()).await;
+        let second = MockServer::start(MockConfig::default()).await;
+        second.config.lock().unwrap().info.hostname = "second".to_string();
+
+        // a bound socket that never answers stands in for an empty port
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let found = Mutex::new(Vec::new());
+        let count = discover(
+            vec![first.addr, silent.local_addr().unwrap(), second.addr],
+            Duration::from_millis(300),
+            |server| found.lock().unwrap().push(server),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(count, 2);
+        let mut found = found.into_inner().unwrap();
+        found.sort_by_key(|s| s.port == second.port());
+        assert_eq!(found[0].port, first.port());
+        assert_eq!(found[1].info.hostname, "second");
+        assert_eq!(found[1].ip, "127.0.0.1");
+    }
+}
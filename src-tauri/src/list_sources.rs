@@ -0,0 +1,574 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+use crate::helpers;
+use crate::master_list::{self, MasterList, MasterListError, MasterServer};
+use crate::resolver;
+
+/// Name the open.mp list is tagged with, user lists can't take it.
+pub const OPEN_MP: &str = "open.mp";
+const SOURCES_FILE: &str = "list_sources.json";
+// community lists rarely change, no point hammering whoever hosts them
+const MIN_REFRESH: Duration = Duration::from_secs(60);
+const DEFAULT_PORT: i32 = 7777;
+
+/// A server list registered by the user, either hosted somewhere or a local
+/// file. Both the old plain text format and the open.mp api format work.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CustomList {
+    pub name: String,
+    // http(s) url or path to a local file
+    pub location: String,
+    // seconds between downloads
+    pub refresh_interval: u64,
+}
+
+impl CustomList {
+    fn is_remote(&self) -> bool {
+        let location = self.location.trim().to_lowercase();
+        location.starts_with("http://") || location.starts_with("https://")
+    }
+
+    fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_interval).max(MIN_REFRESH)
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ListedServer {
+    #[serde(flatten)]
+    pub server: MasterServer,
+    // names of every list the server is on, in the order they were merged
+    pub sources: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SourceError {
+    pub source: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ServerLists {
+    pub servers: Vec<ListedServer>,
+    // lists that couldn't be fetched, the others are still merged
+    pub errors: Vec<SourceError>,
+}
+
+struct Source {
+    name: String,
+    // plain text lists only know addresses
+    detailed: bool,
+    servers: Vec<MasterServer>,
+}
+
+// stands in for a server we only know the address of, until it's queried
+fn placeholder(ip: &str, port: i32) -> MasterServer {
+    MasterServer {
+        ip: ip.to_string(),
+        port,
+        hostname: format!("{}:{}", ip, port),
+        game_mode: "-".to_string(),
+        language: "-".to_string(),
+        has_password: false,
+        player_count: 0,
+        max_players: 0,
+        version: "-".to_string(),
+        using_omp: false,
+        partner: false,
+        rules: HashMap::new(),
+    }
+}
+
+/// Reads the old hosted list format, one `host:port` per line. The port may
+/// be left out, anything after the address and lines starting with `#` or `;`
+/// are ignored.
+pub fn parse_plain_text(body: &str) -> Vec<MasterServer> {
+    let mut servers = Vec::new();
+    for line in body.lines() {
+        let address = match line.split_whitespace().next() {
+            Some(a) if !a.starts_with('#') && !a.starts_with(';') => a,
+            _ => continue,
+        };
+
+        match parse_address(address) {
+            Some((host, port)) => servers.push(placeholder(&host, port)),
+            None => info!("[list_sources.rs] Skipping invalid address: {}", address),
+        }
+    }
+    servers
+}
+
+// `host:port`, `[ipv6]:port` or any of them without the port. A bare ipv6
+// address is all colons, so ips are tried before splitting off a port
+fn parse_address(address: &str) -> Option<(String, i32)> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return (address.port() != 0).then(|| (address.ip().to_string(), address.port() as i32));
+    }
+    if let Some(ip) = resolver::parse_ip(address) {
+        return Some((ip.to_string(), DEFAULT_PORT));
+    }
+
+    match address.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(p) if p != 0 && !host.is_empty() && !host.contains(':') => {
+                Some((host.to_string(), p as i32))
+            }
+            _ => None,
+        },
+        None => Some((address.to_string(), DEFAULT_PORT)),
+    }
+}
+
+// open.mp lists are json arrays, anything else is taken as plain text. So is
+// a "json" list that doesn't parse, plain lists can start with "[::1]:7777"
+fn parse(body: &str) -> Result<(Vec<MasterServer>, bool), MasterListError> {
+    if !body.trim_start().starts_with('[') {
+        return Ok((parse_plain_text(body), false));
+    }
+
+    match master_list::parse_list(body) {
+        Ok(servers) => Ok((servers, true)),
+        Err(e) => {
+            let servers = parse_plain_text(body);
+            if servers.is_empty() {
+                return Err(e);
+            }
+            Ok((servers, false))
+        }
+    }
+}
+
+async fn read(list: &CustomList) -> Result<String, MasterListError> {
+    if !list.is_remote() {
+        return Ok(tokio::fs::read_to_string(list.location.trim()).await?);
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(master_list::REQUEST_TIMEOUT)
+        .build()?;
+    let response = client.get(list.location.trim()).send().await?;
+    if !response.status().is_success() {
+        return Err(MasterListError::Status(response.status().as_u16()));
+    }
+    Ok(response.text().await?)
+}
+
+/// Merges lists in order. A server on several of them shows up once, tagged
+/// with all their names, and takes its details from the first list that has
+/// more than its address.
+fn merge(sources: Vec<Source>) -> Vec<ListedServer> {
+    let mut merged: Vec<(bool, ListedServer)> = Vec::new();
+    // keyed by lowercase host and port, like the query cache
+    let mut index: HashMap<(String, i32), usize> = HashMap::new();
+
+    for source in sources {
+        for server in source.servers {
+            let key = (server.ip.to_lowercase(), server.port);
+            match index.get(&key) {
+                Some(&i) => {
+                    let (detailed, entry) = &mut merged[i];
+                    if !entry.sources.contains(&source.name) {
+                        entry.sources.push(source.name.clone());
+                    }
+                    if source.detailed && !*detailed {
+                        entry.server = server;
+                        *detailed = true;
+                    }
+                }
+                None => {
+                    index.insert(key, merged.len());
+                    merged.push((
+                        source.detailed,
+                        ListedServer {
+                            server,
+                            sources: vec![source.name.clone()],
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    merged.into_iter().map(|(_, server)| server).collect()
+}
+
+pub fn load(dir: &Path) -> Vec<CustomList> {
+    let data = match fs::read(dir.join(SOURCES_FILE)) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    match serde_json::from_slice(&data) {
+        Ok(lists) => lists,
+        Err(e) => {
+            info!("[list_sources.rs] Ignoring broken list sources: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Registers `list`, replacing the one with the same name if there is one.
+pub fn add(dir: &Path, mut list: CustomList) -> Result<Vec<CustomList>, Error> {
+    list.name = list.name.trim().to_string();
+    list.location = list.location.trim().to_string();
+    if list.name.is_empty() || list.location.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "list name and location are required",
+        ));
+    }
+    if list.name.eq_ignore_ascii_case(OPEN_MP) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is reserved for the open.mp list", OPEN_MP),
+        ));
+    }
+
+    let mut lists = load(dir);
+    match lists.iter_mut().find(|l| l.name == list.name) {
+        Some(existing) => *existing = list,
+        None => lists.push(list),
+    }
+    helpers::save_json(dir, SOURCES_FILE, &lists)?;
+    Ok(lists)
+}
+
+pub fn remove(dir: &Path, name: &str) -> Result<bool, Error> {
+    let mut lists = load(dir);
+    let count = lists.len();
+    lists.retain(|l| l.name != name);
+    if lists.len() == count {
+        return Ok(false);
+    }
+    helpers::save_json(dir, SOURCES_FILE, &lists)?;
+    Ok(true)
+}
+
+struct Fetched {
+    list: CustomList,
+    at: Instant,
+    detailed: bool,
+    servers: Vec<MasterServer>,
+}
+
+/// Keeps what user lists last gave us, so each one is only downloaded again
+/// once its refresh interval is up.
+pub struct ListSources {
+    fetched: Mutex<HashMap<String, Fetched>>,
+}
+
+impl ListSources {
+    pub fn new() -> Self {
+        Self {
+            fetched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fetches the `lists` that are due and merges them after the open.mp
+    /// list. A list that fails keeps its previous servers until it works again.
+    pub async fn fetch(
+        &self,
+        master: Result<MasterList, MasterListError>,
+        lists: Vec<CustomList>,
+    ) -> ServerLists {
+        let mut sources = Vec::new();
+        let mut errors = Vec::new();
+        match master {
+            Ok(master) => sources.push(Source {
+                name: OPEN_MP.to_string(),
+                detailed: true,
+                servers: master.servers,
+            }),
+            Err(e) => errors.push(SourceError {
+                source: OPEN_MP.to_string(),
+                error: e.to_string(),
+            }),
+        }
+
+        let mut due = JoinSet::new();
+        {
+            let fetched = self.fetched.lock().unwrap();
+            for (index, list) in lists.iter().enumerate() {
+                let fresh = fetched
+                    .get(&list.name)
+                    .is_some_and(|f| f.list == *list && f.at.elapsed() < list.refresh_interval());
+                if !fresh {
+                    let list = list.clone();
+                    due.spawn(async move { (index, read(&list).await.and_then(|b| parse(&b))) });
+                }
+            }
+        }
+
+        let mut results = HashMap::new();
+        while let Some(joined) = due.join_next().await {
+            if let Ok((index, result)) = joined {
+                results.insert(index, result);
+            }
+        }
+
+        let mut fetched = self.fetched.lock().unwrap();
+        fetched.retain(|name, _| lists.iter().any(|l| l.name == *name));
+        for (index, list) in lists.into_iter().enumerate() {
+            match results.remove(&index) {
+                Some(Ok((servers, detailed))) => {
+                    fetched.insert(
+                        list.name.clone(),
+                        Fetched {
+                            list: list.clone(),
+                            at: Instant::now(),
+                            detailed,
+                            servers,
+                        },
+                    );
+                }
+                Some(Err(e)) => {
+                    info!("[list_sources.rs] Unable to fetch {}: {}", list.name, e);
+                    errors.push(SourceError {
+                        source: list.name.clone(),
+                        error: e.to_string(),
+                    });
+                }
+                None => {}
+            }
+
+            if let Some(f) = fetched.get(&list.name).filter(|f| f.list == list) {
+                sources.push(Source {
+                    name: list.name,
+                    detailed: f.detailed,
+                    servers: f.servers.clone(),
+                });
+            }
+        }
+
+        ServerLists {
+            servers: merge(sources),
+            errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::temp_dir;
+    use crate::master_list::ListSource;
+
+    const JSON_LIST: &str = r#"[
+        {"core": {"ip": "127.0.0.1:7777", "hn": "detailed", "gm": "dm", "la": "English",
+            "pa": false, "pc": 5, "pm": 50, "vn": "omp 1.0.0", "omp": true, "pr": false}}
+    ]"#;
+
+    fn file_list(dir: &Path, name: &str, body: &str) -> CustomList {
+        let path = dir.join(format!("{}.txt", name));
+        fs::write(&path, body).unwrap();
+        CustomList {
+            name: name.to_string(),
+            location: path.to_string_lossy().to_string(),
+            refresh_interval: 600,
+        }
+    }
+
+    fn master(servers: Vec<MasterServer>) -> Result<MasterList, MasterListError> {
+        Ok(MasterList {
+            source: ListSource::Network,
+            fetched_at: 0,
+            servers,
+        })
+    }
+
+    #[test]
+    fn parses_plain_text() {
+        let servers = parse_plain_text(
+            "# community list\n\
+             127.0.0.1:7777\n\
+             \n\
+             play.example.com\n\
+             ; old entry\n\
+             10.0.0.1:7778 some comment\n\
+             broken:port\n",
+        );
+
+        let addresses: Vec<_> = servers.iter().map(|s| (s.ip.as_str(), s.port)).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("127.0.0.1", 7777),
+                ("play.example.com", DEFAULT_PORT),
+                ("10.0.0.1", 7778)
+            ]
+        );
+        assert_eq!(servers[0].hostname, "127.0.0.1:7777");
+    }
+
+    #[test]
+    fn parses_ipv6_addresses() {
+        let body = "[::1]:7777\n\
+                    2001:db8::1\n\
+                    [2001:db8::2]\n\
+                    [2001:db8::3]:7778\n\
+                    2001:db8::4:0\n\
+                    ::1:";
+        let servers = parse_plain_text(body);
+        let addresses: Vec<_> = servers.iter().map(|s| (s.ip.as_str(), s.port)).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("::1", 7777),
+                ("2001:db8::1", DEFAULT_PORT),
+                ("2001:db8::2", DEFAULT_PORT),
+                ("2001:db8::3", 7778),
+                // a bare address ending in a group that looks like a port
+                ("2001:db8::4:0", DEFAULT_PORT),
+            ]
+        );
+
+        // starts like a json array, but isn't one
+        let (servers, detailed) = parse(body).unwrap();
+        assert!(!detailed);
+        assert_eq!(servers.len(), 5);
+        assert!(parse("[{\"ip\": ").is_err());
+    }
+
+    #[test]
+    fn merges_and_tags_duplicates() {
+        let merged = merge(vec![
+            Source {
+                name: "plain".to_string(),
+                detailed: false,
+                servers: parse_plain_text("127.0.0.1:7777\nLOCALHOST:7777\n127.0.0.1:7777"),
+            },
+            Source {
+                name: "json".to_string(),
+                detailed: true,
+                servers: parse(JSON_LIST).unwrap().0,
+            },
+            Source {
+                name: "other".to_string(),
+                detailed: false,
+                servers: parse_plain_text("localhost:7777"),
+            },
+        ]);
+
+        assert_eq!(merged.len(), 2);
+        // the plain text entry came first, but the json one knows more
+        assert_eq!(merged[0].server.hostname, "detailed");
+        assert_eq!(merged[0].sources, vec!["plain", "json"]);
+        assert_eq!(merged[1].server.ip, "LOCALHOST");
+        assert_eq!(merged[1].sources, vec!["plain", "other"]);
+    }
+
+    #[test]
+    fn merges_ipv6_servers_from_both_formats() {
+        let json = r#"[
+            {"core": {"ip": "[2001:DB8::1]:7777", "hn": "v6", "gm": "dm", "la": "-",
+                "pa": false, "pc": 0, "pm": 50, "vn": "-", "omp": false, "pr": false}}
+        ]"#;
+        let merged = merge(vec![
+            Source {
+                name: "plain".to_string(),
+                detailed: false,
+                servers: parse_plain_text("[2001:db8::1]:7777\n2001:db8:0::1"),
+            },
+            Source {
+                name: "json".to_string(),
+                detailed: true,
+                servers: parse(json).unwrap().0,
+            },
+        ]);
+
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].server.ip, "2001:db8::1");
+        assert_eq!(merged[0].server.hostname, "v6");
+        assert_eq!(merged[0].sources, vec!["plain", "json"]);
+    }
+
+    #[tokio::test]
+    async fn respects_refresh_interval() {
+        let dir = temp_dir("list_sources_refresh");
+        fs::create_dir_all(&dir).unwrap();
+        let list = file_list(&dir, "community", "127.0.0.1:7777");
+        let sources = ListSources::new();
+
+        let lists = sources.fetch(master(vec![]), vec![list.clone()]).await;
+        assert_eq!(lists.servers.len(), 1);
+        assert_eq!(lists.servers[0].sources, vec!["community"]);
+
+        // still within the interval, the file isn't read again
+        fs::write(&list.location, "127.0.0.1:7777\n127.0.0.1:7778").unwrap();
+        let lists = sources.fetch(master(vec![]), vec![list.clone()]).await;
+        assert_eq!(lists.servers.len(), 1);
+
+        // changing the list counts as a new one
+        let changed = CustomList {
+            refresh_interval: 900,
+            ..list.clone()
+        };
+        let lists = sources.fetch(master(vec![]), vec![changed.clone()]).await;
+        assert_eq!(lists.servers.len(), 2);
+
+        // a list that stops working keeps its last servers
+        fs::remove_file(&list.location).unwrap();
+        sources
+            .fetched
+            .lock()
+            .unwrap()
+            .get_mut("community")
+            .unwrap()
+            .at -= Duration::from_secs(900);
+        let lists = sources
+            .fetch(Err(MasterListError::Status(503)), vec![changed])
+            .await;
+        assert_eq!(lists.servers.len(), 2);
+        let failed: Vec<_> = lists.errors.iter().map(|e| e.source.as_str()).collect();
+        assert_eq!(failed, vec![OPEN_MP, "community"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stores_lists() {
+        let dir = temp_dir("list_sources_store");
+        fs::create_dir_all(&dir).unwrap();
+        let list = CustomList {
+            name: " community ".to_string(),
+            location: "https://example.com/list.txt".to_string(),
+            refresh_interval: 600,
+        };
+
+        assert_eq!(add(&dir, list.clone()).unwrap().len(), 1);
+        let lists = add(
+            &dir,
+            CustomList {
+                refresh_interval: 60,
+                ..list.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(lists.len(), 1);
+        assert_eq!(lists[0].name, "community");
+        assert_eq!(load(&dir), lists);
+
+        let reserved = CustomList {
+            name: "Open.mp".to_string(),
+            ..list
+        };
+        assert_eq!(
+            add(&dir, reserved).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        assert!(remove(&dir, "community").unwrap());
+        assert!(!remove(&dir, "community").unwrap());
+        assert!(load(&dir).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -2,95 +2,519 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 // use serde_json::json;
 mod discord;
+mod filter_expr;
+mod friends;
 mod helpers;
 mod injector;
+mod lan_discovery;
+mod list_sources;
+mod master_list;
+#[cfg(test)]
+mod mock_server;
+mod player_index;
+mod population_history;
 mod query;
+mod query_backoff;
+mod query_cache;
+mod query_parser;
+mod query_pool;
+mod resolver;
 mod samp;
+mod search;
+mod server_filter;
+mod server_store;
+mod slot_queue;
+mod subscriptions;
 
 use log::LevelFilter;
 use md5::compute;
 use runas;
 use sevenz_rust::decompress_file;
 use std::fs::File;
-use std::io::Read;
-use std::time::Instant;
+use std::io::{ErrorKind, Read};
+use std::time::Duration;
 use tauri::Manager;
 use tauri::PhysicalSize;
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
-async fn request_server_info(ip: &str, port: i32) -> Result<String, String> {
-    match query::Query::new(ip, port).await {
-        Ok(q) => {
-            let _ = q.send('i').await;
-            match q.recv().await {
-                Ok(p) => Ok(format!("{}", p)),
-                Err(e) => Err(e.to_string()),
-            }
-        }
-        Err(e) => Err(e.to_string()),
+async fn request_server_info(
+    cache: tauri::State<'_, query_cache::QueryCache>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    history: tauri::State<'_, population_history::PopulationHistory>,
+    ip: &str,
+    port: i32,
+) -> Result<query_cache::Cached<query::InfoPacket>, String> {
+    let result = cache
+        .get_or_fetch(ip, port, 'i', || {
+            backoff.guard(ip, port, || async {
+                query::Query::new(&resolver, ip, port).await?.info().await
+            })
+        })
+        .await;
+    match &result {
+        Ok(cached) => history.record_info(ip, port, Some(&cached.data)),
+        // backed off, unresolvable and the like say nothing about the server
+        Err(e) if e.kind() == ErrorKind::TimedOut => history.record_info(ip, port, None),
+        Err(_) => {}
     }
+    result.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn request_server_players(ip: &str, port: i32) -> Result<String, String> {
-    match query::Query::new(ip, port).await {
-        Ok(q) => {
-            let _ = q.send('c').await;
-            match q.recv().await {
-                Ok(p) => Ok(format!("{}", p)),
-                Err(_) => Ok("{\"error\": true}".to_string()),
-            }
-        }
-        Err(_) => Ok("{\"error\": true}".to_string()),
+async fn request_server_players(
+    cache: tauri::State<'_, query_cache::QueryCache>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    players: tauri::State<'_, player_index::PlayerIndex>,
+    ip: &str,
+    port: i32,
+) -> Result<query_cache::Cached<query::PlayerList<query::Player>>, String> {
+    let result = cache
+        .get_or_fetch(ip, port, 'c', || {
+            backoff.guard_player_list(ip, port, || async {
+                query::Query::new(&resolver, ip, port)
+                    .await?
+                    .player_list()
+                    .await
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    if let query::PlayerList::Complete(list) = &result.data {
+        players.record(ip, port, list.iter().map(|p| p.name.as_str()));
     }
+    Ok(result)
 }
 
 #[tauri::command]
-async fn request_server_rules(ip: &str, port: i32) -> Result<String, String> {
-    match query::Query::new(ip, port).await {
-        Ok(q) => {
-            let _ = q.send('r').await;
-            match q.recv().await {
-                Ok(p) => Ok(format!("{}", p)),
-                Err(e) => Err(e.to_string()),
-            }
-        }
-        Err(e) => Err(e.to_string()),
+async fn request_server_detailed_players(
+    cache: tauri::State<'_, query_cache::QueryCache>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    players: tauri::State<'_, player_index::PlayerIndex>,
+    ip: &str,
+    port: i32,
+) -> Result<query_cache::Cached<query::PlayerList<query::DetailedPlayer>>, String> {
+    let result = cache
+        .get_or_fetch(ip, port, 'd', || {
+            backoff.guard_player_list(ip, port, || async {
+                query::Query::new(&resolver, ip, port)
+                    .await?
+                    .detailed_player_list()
+                    .await
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    if let query::PlayerList::Complete(list) = &result.data {
+        players.record(ip, port, list.iter().map(|p| p.name.as_str()));
+    }
+    Ok(result)
+}
+
+#[tauri::command]
+async fn request_server_rules(
+    cache: tauri::State<'_, query_cache::QueryCache>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    ip: &str,
+    port: i32,
+) -> Result<query_cache::Cached<Vec<(String, String)>>, String> {
+    cache
+        .get_or_fetch(ip, port, 'r', || {
+            backoff.guard(ip, port, || async {
+                query::Query::new(&resolver, ip, port).await?.rules().await
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn request_server_omp_extra_info(
+    cache: tauri::State<'_, query_cache::QueryCache>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    ip: &str,
+    port: i32,
+) -> Result<query_cache::Cached<query::ExtraInfoPacket>, String> {
+    cache
+        .get_or_fetch(ip, port, 'o', || {
+            backoff.guard(ip, port, || async {
+                query::Query::new(&resolver, ip, port)
+                    .await?
+                    .extra_info()
+                    .await
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn ping_server(
+    cache: tauri::State<'_, query_cache::QueryCache>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    history: tauri::State<'_, population_history::PopulationHistory>,
+    ip: &str,
+    port: i32,
+    probes: Option<u32>,
+    spacing: Option<u64>,
+    timeout: Option<u64>,
+) -> Result<query_cache::Cached<query::PingStats>, String> {
+    let probes = probes.unwrap_or(3);
+    let spacing = spacing.unwrap_or(100);
+    let timeout = timeout.unwrap_or(1000);
+    let fetch = || async {
+        backoff.check(ip, port)?;
+        let stats = query::Query::new(&resolver, ip, port)
+            .await?
+            .ping_stats(
+                probes,
+                Duration::from_millis(spacing),
+                Duration::from_millis(timeout),
+            )
+            .await;
+        backoff.report(ip, port, stats.received > 0);
+        Ok::<_, std::io::Error>(stats)
+    };
+
+    // the cache only knows the opcode, so it only holds pings made the default
+    // way. Others always go out and aren't cached either
+    let result = if (probes, spacing, timeout) == (3, 100, 1000) {
+        cache.get_or_fetch(ip, port, 'p', fetch).await
+    } else {
+        fetch().await.map(|data| query_cache::Cached {
+            data,
+            changed: true,
+        })
+    };
+    if let Some(ping) = result.as_ref().ok().and_then(|cached| cached.data.median) {
+        history.record_ping(ip, port, ping);
     }
+    result.map_err(|e| e.to_string())
 }
 
+// returns a handle for cancel_query right away, results come in as events
 #[tauri::command]
-async fn request_server_omp_extra_info(ip: &str, port: i32) -> Result<String, String> {
-    match query::Query::new(ip, port).await {
-        Ok(q) => {
-            let _ = q.send('o').await;
-            match q.recv().await {
-                Ok(p) => Ok(format!("{}", p)),
-                Err(e) => Err(e.to_string()),
+async fn request_servers_batch(
+    app: tauri::AppHandle,
+    pool: tauri::State<'_, query_pool::QueryPool>,
+    servers: Vec<query_pool::Endpoint>,
+    query_types: Vec<char>,
+    priority: Option<query_pool::Priority>,
+) -> Result<u64, String> {
+    Ok(pool.spawn_batch(
+        servers,
+        query_types,
+        priority.unwrap_or_default(),
+        move |mut result| {
+            if let Some(response) = &result.response {
+                let cache = app.state::<query_cache::QueryCache>();
+                result.changed = cache
+                    .store_response(&result.ip, result.port, result.query_type, response)
+                    .unwrap_or(true);
+                app.state::<player_index::PlayerIndex>().record_response(
+                    &result.ip,
+                    result.port,
+                    response,
+                );
             }
-        }
-        Err(e) => Err(e.to_string()),
+            app.state::<population_history::PopulationHistory>()
+                .record_result(&result);
+            let _ = app.emit_all("server-query-result", result);
+        },
+    ))
+}
+
+#[tauri::command]
+fn cancel_query(pool: tauri::State<'_, query_pool::QueryPool>, handle: u64) -> bool {
+    pool.cancel(handle)
+}
+
+#[tauri::command]
+async fn subscribe_servers(
+    app: tauri::AppHandle,
+    pool: tauri::State<'_, query_pool::QueryPool>,
+    subscriptions: tauri::State<'_, subscriptions::Subscriptions>,
+    name: &str,
+    servers: Vec<query_pool::Endpoint>,
+    query_types: Vec<char>,
+    priority: Option<query_pool::Priority>,
+    interval: u64,
+) -> Result<(), String> {
+    subscriptions.subscribe(
+        name,
+        pool.inner().clone(),
+        servers,
+        query_types,
+        priority.unwrap_or_default(),
+        Duration::from_millis(interval),
+        move |update| publish_update(&app, update),
+    );
+    Ok(())
+}
+
+// what every subscription does with an update, whatever else it's for.
+// Refreshes that changed nothing only count for the history
+fn publish_update(app: &tauri::AppHandle, update: subscriptions::ServerUpdate) {
+    app.state::<population_history::PopulationHistory>().apply(
+        &update.ip,
+        update.port,
+        &update.diff,
+    );
+    if update.diff.is_empty() {
+        return;
     }
+    app.state::<server_store::ServerStore>()
+        .apply(&update.ip, update.port, &update.diff);
+    app.state::<player_index::PlayerIndex>()
+        .apply(&update.ip, update.port, &update.diff);
+    let _ = app.emit_all("server-updated", update);
+}
+
+#[tauri::command]
+fn get_friend_settings(app: tauri::AppHandle) -> Result<friends::FriendSettings, String> {
+    Ok(friends::load(&app_data_dir(&app)?))
+}
+
+#[tauri::command]
+fn save_friend_settings(
+    app: tauri::AppHandle,
+    settings: friends::FriendSettings,
+) -> Result<friends::FriendSettings, String> {
+    friends::save(&app_data_dir(&app)?, settings).map_err(|e| e.to_string())
 }
 
+// (re)starts checking favorites and the most populated servers for friends,
+// returns how many servers are watched. Changes come in as "friend-status"
+// events
 #[tauri::command]
-async fn ping_server(ip: &str, port: i32) -> Result<u32, String> {
-    match query::Query::new(ip, port).await {
-        Ok(q) => {
-            let _ = q.send('p').await;
-            let before = Instant::now();
-            match q.recv().await {
-                Ok(_p) => Ok(before.elapsed().as_millis() as u32),
-                Err(_) => Ok(9999),
+async fn watch_friends(
+    app: tauri::AppHandle,
+    pool: tauri::State<'_, query_pool::QueryPool>,
+    subscriptions: tauri::State<'_, subscriptions::Subscriptions>,
+    watch: tauri::State<'_, friends::FriendWatch>,
+    store: tauri::State<'_, server_store::ServerStore>,
+    favorites: Vec<query_pool::Endpoint>,
+) -> Result<usize, String> {
+    let settings = friends::load(&app_data_dir(&app)?);
+    watch.reset(&settings.friends);
+    if settings.friends.is_empty() {
+        subscriptions.unsubscribe(friends::SUBSCRIPTION);
+        return Ok(0);
+    }
+
+    let servers = friends::targets(&settings, favorites, store.most_populated(settings.popular));
+    let count = servers.len();
+    subscriptions.subscribe(
+        friends::SUBSCRIPTION,
+        pool.inner().clone(),
+        servers,
+        vec!['i', 'c'],
+        query_pool::Priority::Background,
+        Duration::from_secs(settings.interval),
+        move |update| {
+            for status in
+                app.state::<friends::FriendWatch>()
+                    .apply(&update.ip, update.port, &update.diff)
+            {
+                let _ = app.emit_all("friend-status", status);
             }
-        }
-        Err(_) => Ok(9999),
+            publish_update(&app, update);
+        },
+    );
+    Ok(count)
+}
+
+#[tauri::command]
+fn unwatch_friends(subscriptions: tauri::State<'_, subscriptions::Subscriptions>) -> bool {
+    subscriptions.unsubscribe(friends::SUBSCRIPTION)
+}
+
+#[tauri::command]
+fn unsubscribe_servers(
+    subscriptions: tauri::State<'_, subscriptions::Subscriptions>,
+    name: &str,
+) -> bool {
+    subscriptions.unsubscribe(name)
+}
+
+#[tauri::command]
+fn retry_server_now(backoff: tauri::State<'_, query_backoff::QueryBackoff>, ip: &str, port: i32) {
+    backoff.retry_now(ip, port);
+}
+
+#[tauri::command]
+fn get_server_health(
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    ip: &str,
+    port: i32,
+) -> query_backoff::Health {
+    backoff.health(ip, port)
+}
+
+#[tauri::command]
+async fn discover_lan_servers(
+    app: tauri::AppHandle,
+    options: Option<lan_discovery::DiscoveryOptions>,
+) -> Result<usize, String> {
+    let options = options.unwrap_or_default();
+    let targets = lan_discovery::targets(&options).map_err(|e| e.to_string())?;
+    lan_discovery::discover(
+        targets,
+        Duration::from_millis(options.timeout),
+        move |server| {
+            let _ = app.emit_all("lan-server-found", server);
+        },
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn send_rcon_command(
+    resolver: tauri::State<'_, resolver::Resolver>,
+    ip: &str,
+    port: i32,
+    password: Option<String>,
+    command: &str,
+) -> Result<Vec<String>, String> {
+    let password = match password.or_else(|| samp::get_rcon_password(ip, port)) {
+        Some(p) => p,
+        None => return Err("no_rcon_password".to_string()),
+    };
+
+    match query::Query::new(&resolver, ip, port).await {
+        Ok(q) => match q.rcon(&password, command).await {
+            Ok(lines) => Ok(lines),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                Err("invalid_rcon_password".to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        },
+        Err(e) => Err(e.to_string()),
     }
 }
 
+#[tauri::command]
+fn get_stored_rcon_password(ip: &str, port: i32) -> Option<String> {
+    samp::get_rcon_password(ip, port)
+}
+
+#[tauri::command]
+fn clear_dns_cache(resolver: tauri::State<'_, resolver::Resolver>, host: Option<&str>) {
+    resolver.invalidate(host);
+}
+
+fn app_data_dir(app: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
+    app.path_resolver()
+        .app_data_dir()
+        .ok_or_else(|| "app data directory is not available".to_string())
+}
+
+#[tauri::command]
+async fn fetch_server_lists(
+    app: tauri::AppHandle,
+    sources: tauri::State<'_, list_sources::ListSources>,
+    store: tauri::State<'_, server_store::ServerStore>,
+) -> Result<list_sources::ServerLists, String> {
+    let dir = app_data_dir(&app)?;
+    let master = master_list::fetch(master_list::LIST_URL, &dir).await;
+    let lists = sources.fetch(master, list_sources::load(&dir)).await;
+    store.replace(&lists.servers);
+    Ok(lists)
+}
+
+#[tauri::command]
+fn filter_servers(
+    store: tauri::State<'_, server_store::ServerStore>,
+    criteria: server_filter::Criteria,
+) -> Result<Vec<String>, String> {
+    store.filter(&criteria).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn search_players(
+    players: tauri::State<'_, player_index::PlayerIndex>,
+    query: &str,
+    mode: Option<player_index::SearchMode>,
+    limit: Option<usize>,
+) -> Vec<player_index::PlayerHit> {
+    players.search(query, mode.unwrap_or_default(), limit)
+}
+
+// `since` is unix millis, the whole history without
+#[tauri::command]
+fn get_population_history(
+    history: tauri::State<'_, population_history::PopulationHistory>,
+    ip: &str,
+    port: i32,
+    since: Option<u64>,
+    resolution: Option<population_history::Resolution>,
+) -> Vec<population_history::HistoryPoint> {
+    history.history(ip, port, since, resolution.unwrap_or_default())
+}
+
+// `utc_offset` is in minutes, positive ahead of UTC
+#[tauri::command]
+fn get_busiest_hours(
+    history: tauri::State<'_, population_history::PopulationHistory>,
+    ip: &str,
+    port: i32,
+    utc_offset: i32,
+    days: Option<u64>,
+) -> Vec<population_history::HourOfDay> {
+    history.busiest_hours(ip, port, utc_offset, days)
+}
+
+#[tauri::command]
+fn check_filter_expression(expression: &str) -> Result<(), filter_expr::ExprError> {
+    filter_expr::parse(expression).map(|_| ())
+}
+
+#[tauri::command]
+fn get_saved_filters(app: tauri::AppHandle) -> Result<Vec<filter_expr::SavedFilter>, String> {
+    Ok(filter_expr::load(&app_data_dir(&app)?))
+}
+
+#[tauri::command]
+fn save_filter(
+    app: tauri::AppHandle,
+    filter: filter_expr::SavedFilter,
+) -> Result<Vec<filter_expr::SavedFilter>, String> {
+    filter_expr::save(&app_data_dir(&app)?, filter).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_saved_filter(app: tauri::AppHandle, name: &str) -> Result<bool, String> {
+    filter_expr::remove(&app_data_dir(&app)?, name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_list_sources(app: tauri::AppHandle) -> Result<Vec<list_sources::CustomList>, String> {
+    Ok(list_sources::load(&app_data_dir(&app)?))
+}
+
+#[tauri::command]
+fn add_list_source(
+    app: tauri::AppHandle,
+    list: list_sources::CustomList,
+) -> Result<Vec<list_sources::CustomList>, String> {
+    list_sources::add(&app_data_dir(&app)?, list).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_list_source(app: tauri::AppHandle, name: &str) -> Result<bool, String> {
+    list_sources::remove(&app_data_dir(&app)?, name).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn inject(
+    resolver: tauri::State<'_, resolver::Resolver>,
     name: &str,
     ip: &str,
     port: i32,
@@ -98,7 +522,38 @@ async fn inject(
     dll: &str,
     password: &str,
 ) -> Result<(), String> {
-    injector::run_samp(name, ip, port, exe, dll, password).await
+    injector::run_samp(&resolver, name, ip, port, exe, dll, password).await
+}
+
+// waits for a free slot in the background, every poll comes in as a
+// "slot-queue" event
+#[tauri::command]
+async fn queue_for_slot(
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, slot_queue::SlotQueue>,
+    backoff: tauri::State<'_, query_backoff::QueryBackoff>,
+    resolver: tauri::State<'_, resolver::Resolver>,
+    request: slot_queue::QueueRequest,
+) -> Result<(), String> {
+    queue.start(
+        backoff.inner().clone(),
+        resolver.inner().clone(),
+        request,
+        move |event| {
+            let _ = app.emit_all("slot-queue", event);
+        },
+    );
+    Ok(())
+}
+
+#[tauri::command]
+fn cancel_slot_queue(queue: tauri::State<'_, slot_queue::SlotQueue>, ip: &str, port: i32) -> bool {
+    queue.cancel(ip, port)
+}
+
+#[tauri::command]
+fn get_slot_queue(queue: tauri::State<'_, slot_queue::SlotQueue>) -> Vec<query_pool::Endpoint> {
+    queue.queued()
 }
 
 #[tauri::command]
@@ -166,23 +621,83 @@ fn copy_files_to_gtasa(src: &str, gtasa_dir: &str) -> Result<(), String> {
 fn main() {
     simple_logging::log_to_file("omp-launcher.log", LevelFilter::Info).unwrap();
 
-    discord::initialize_drpc();
+    // shared by everything that queries servers, the pool holds its own copies
+    let backoff = query_backoff::QueryBackoff::new();
+    let resolver = resolver::Resolver::new();
+
+    discord::initialize_drpc(resolver.clone());
     tauri::Builder::default()
         .plugin(tauri_plugin_upload::init())
+        .manage(query_pool::QueryPool::new(
+            backoff.clone(),
+            resolver.clone(),
+        ))
+        .manage(backoff)
+        .manage(resolver)
+        .manage(query_cache::QueryCache::new())
+        .manage(subscriptions::Subscriptions::new())
+        .manage(list_sources::ListSources::new())
+        .manage(server_store::ServerStore::new())
+        .manage(player_index::PlayerIndex::new())
+        .manage(friends::FriendWatch::new())
+        .manage(slot_queue::SlotQueue::new())
         .setup(|app| {
             let main_window = app.get_window("main").unwrap();
             main_window
                 .set_min_size(Some(PhysicalSize::new(1000, 700)))
                 .unwrap();
+
+            app.manage(population_history::PopulationHistory::new(app_data_dir(
+                &app.handle(),
+            )?));
+            let handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    tokio::time::sleep(population_history::FLUSH_INTERVAL).await;
+                    handle
+                        .state::<population_history::PopulationHistory>()
+                        .flush();
+                }
+            });
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             request_server_info,
             request_server_players,
+            request_server_detailed_players,
             request_server_rules,
             request_server_omp_extra_info,
             ping_server,
+            request_servers_batch,
+            cancel_query,
+            subscribe_servers,
+            unsubscribe_servers,
+            get_friend_settings,
+            save_friend_settings,
+            watch_friends,
+            unwatch_friends,
+            retry_server_now,
+            get_server_health,
+            discover_lan_servers,
+            send_rcon_command,
+            get_stored_rcon_password,
+            clear_dns_cache,
+            fetch_server_lists,
+            filter_servers,
+            search_players,
+            get_population_history,
+            get_busiest_hours,
+            check_filter_expression,
+            get_saved_filters,
+            save_filter,
+            remove_saved_filter,
+            get_list_sources,
+            add_list_source,
+            remove_list_source,
             inject,
+            queue_for_slot,
+            cancel_slot_queue,
+            get_slot_queue,
             get_gtasa_path_from_samp,
             get_nickname_from_samp,
             rerun_as_admin,
@@ -192,6 +707,12 @@ fn main() {
             extract_7z,
             copy_files_to_gtasa,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // whatever came in since the last flush
+            if let tauri::RunEvent::Exit = event {
+                app.state::<population_history::PopulationHistory>().flush();
+            }
+        });
 }
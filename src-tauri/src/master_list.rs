@@ -0,0 +1,439 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::helpers;
+use crate::resolver;
+
+pub const LIST_URL: &str = "https://api.open.mp/servers/full";
+const SNAPSHOT_FILE: &str = "master_list.json";
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub enum MasterListError {
+    Http(String),
+    Status(u16),
+    Schema(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for MasterListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MasterListError::Http(e) => write!(f, "request failed: {}", e),
+            MasterListError::Status(status) => write!(f, "server responded with {}", status),
+            MasterListError::Schema(e) => write!(f, "unexpected list format: {}", e),
+            MasterListError::Io(e) => write!(f, "snapshot error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MasterListError {}
+
+impl From<reqwest::Error> for MasterListError {
+    fn from(e: reqwest::Error) -> Self {
+        MasterListError::Http(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for MasterListError {
+    fn from(e: std::io::Error) -> Self {
+        MasterListError::Io(e)
+    }
+}
+
+// what the api sends, short names and all
+#[derive(Deserialize)]
+struct ApiServer {
+    core: ApiCore,
+    #[serde(default)]
+    ru: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ApiCore {
+    ip: String,
+    hn: String,
+    gm: String,
+    la: String,
+    pa: bool,
+    pc: u16,
+    pm: u16,
+    vn: String,
+    omp: bool,
+    pr: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MasterServer {
+    pub ip: String,
+    pub port: i32,
+    pub hostname: String,
+    pub game_mode: String,
+    pub language: String,
+    pub has_password: bool,
+    pub player_count: u16,
+    pub max_players: u16,
+    pub version: String,
+    pub using_omp: bool,
+    pub partner: bool,
+    pub rules: HashMap<String, String>,
+}
+
+impl TryFrom<ApiServer> for MasterServer {
+    type Error = String;
+
+    fn try_from(server: ApiServer) -> Result<Self, Self::Error> {
+        let core = server.core;
+        // "host:port", the host itself may be an IPv6 address with colons
+        let (ip, port) = core
+            .ip
+            .rsplit_once(':')
+            .ok_or_else(|| format!("{} has no port", core.ip))?;
+        let port = match port.parse::<u16>() {
+            Ok(p) if p != 0 => p as i32,
+            _ => return Err(format!("{} has an invalid port", core.ip)),
+        };
+        if ip.is_empty() {
+            return Err(format!("{} has no host", core.ip));
+        }
+        // ips are kept the way they print, "[::1]" becomes "::1" like it does
+        // in plain text lists
+        let ip = resolver::parse_ip(ip).map_or_else(|| ip.to_string(), |ip| ip.to_string());
+
+        Ok(MasterServer {
+            ip,
+            port,
+            hostname: core.hn,
+            game_mode: core.gm,
+            language: core.la,
+            has_password: core.pa,
+            player_count: core.pc,
+            max_players: core.pm,
+            version: core.vn,
+            using_omp: core.omp,
+            partner: core.pr,
+            rules: server.ru,
+        })
+    }
+}
+
+/// Last list we got from the api, kept on disk so the launcher has something
+/// to show when it starts offline.
+#[derive(Serialize, Deserialize, Clone)]
+struct Snapshot {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: u64,
+    servers: Vec<MasterServer>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ListSource {
+    // a new list was downloaded
+    Network,
+    // the api said the snapshot is still current
+    NotModified,
+    // the api couldn't be reached, this is the last good list
+    Snapshot,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MasterList {
+    pub source: ListSource,
+    // unix time in milliseconds of when the list was downloaded
+    pub fetched_at: u64,
+    pub servers: Vec<MasterServer>,
+}
+
+/// Validates the api response. A body that isn't a list fails as a whole,
+/// single broken entries are skipped so one bad server can't hide the rest.
+pub fn parse_list(body: &str) -> Result<Vec<MasterServer>, MasterListError> {
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(body).map_err(|e| MasterListError::Schema(e.to_string()))?;
+
+    let mut servers = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let parsed = serde_json::from_value::<ApiServer>(entry)
+            .map_err(|e| e.to_string())
+            .and_then(MasterServer::try_from);
+        match parsed {
+            Ok(server) => servers.push(server),
+            Err(e) => info!("[master_list.rs] Skipping server: {}", e),
+        }
+    }
+    Ok(servers)
+}
+
+fn load_snapshot(dir: &Path) -> Option<Snapshot> {
+    let data = fs::read(dir.join(SNAPSHOT_FILE)).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            info!("[master_list.rs] Ignoring broken snapshot: {}", e);
+            None
+        }
+    }
+}
+
+fn save_snapshot(dir: &Path, snapshot: &Snapshot) -> Result<(), MasterListError> {
+    Ok(helpers::save_json(dir, SNAPSHOT_FILE, snapshot)?)
+}
+
+async fn download(
+    url: &str,
+    snapshot: Option<&Snapshot>,
+) -> Result<Option<(Snapshot, Vec<MasterServer>)>, MasterListError> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+
+    let mut request = client.get(url);
+    if let Some(snapshot) = snapshot {
+        if let Some(etag) = &snapshot.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &snapshot.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(MasterListError::Status(response.status().as_u16()));
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let etag = header(reqwest::header::ETAG);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+
+    let servers = parse_list(&response.text().await?)?;
+    let snapshot = Snapshot {
+        etag,
+        last_modified,
+        fetched_at: helpers::now(),
+        servers: servers.clone(),
+    };
+    Ok(Some((snapshot, servers)))
+}
+
+/// Gets the server list from `url`, only downloading it again if it changed
+/// since the snapshot in `dir` was taken. Falls back to that snapshot when the
+/// api can't be reached or sends something we can't use.
+pub async fn fetch(url: &str, dir: &Path) -> Result<MasterList, MasterListError> {
+    let snapshot = load_snapshot(dir);
+
+    let error = match download(url, snapshot.as_ref()).await {
+        Ok(Some((new_snapshot, servers))) => {
+            if let Err(e) = save_snapshot(dir, &new_snapshot) {
+                info!("[master_list.rs] Unable to save snapshot: {}", e);
+            }
+            return Ok(MasterList {
+                source: ListSource::Network,
+                fetched_at: new_snapshot.fetched_at,
+                servers,
+            });
+        }
+        Ok(None) => match snapshot {
+            Some(snapshot) => {
+                return Ok(MasterList {
+                    source: ListSource::NotModified,
+                    fetched_at: snapshot.fetched_at,
+                    servers: snapshot.servers,
+                })
+            }
+            // only happens if the snapshot vanished since we read it
+            None => MasterListError::Status(304),
+        },
+        Err(e) => e,
+    };
+
+    info!("[master_list.rs] Unable to fetch server list: {}", error);
+    match snapshot {
+        Some(snapshot) => Ok(MasterList {
+            source: ListSource::Snapshot,
+            fetched_at: snapshot.fetched_at,
+            servers: snapshot.servers,
+        }),
+        None => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::temp_dir;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const LIST: &str = r#"[
+        {"core": {"ip": "127.0.0.1:7777", "hn": "first", "gm": "dm", "la": "English",
+            "pa": false, "pc": 5, "pm": 50, "vn": "omp 1.0.0", "omp": true, "pr": true},
+         "ru": {"weburl": "open.mp"}},
+        {"core": {"ip": "[::1]:7778", "hn": "second", "gm": "rp", "la": "-",
+            "pa": true, "pc": 0, "pm": 100, "vn": "0.3.7", "omp": false, "pr": false}},
+        {"core": {"ip": "no-port", "hn": "broken", "gm": "", "la": "",
+            "pa": false, "pc": 0, "pm": 0, "vn": "", "omp": false, "pr": false}},
+        {"core": {"hn": "missing ip"}}
+    ]"#;
+
+    struct Reply {
+        status: &'static str,
+        headers: Vec<(&'static str, &'static str)>,
+        body: &'static str,
+    }
+
+    // bare bones HTTP/1.1 server answering each connection with the next
+    // reply, hands back the requests it got
+    async fn http_stand_in(replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/servers/full", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for reply in replies {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let amt = stream.read(&mut buf).await.unwrap();
+                    if amt == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..amt]);
+                }
+                seen.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_lowercase());
+
+                let mut response = format!("HTTP/1.1 {}\r\n", reply.status);
+                for (name, value) in reply.headers {
+                    response.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                response.push_str(&format!(
+                    "content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    reply.body.len(),
+                    reply.body
+                ));
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[test]
+    fn parses_and_validates_entries() {
+        let servers = parse_list(LIST).unwrap();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].ip, "127.0.0.1");
+        assert_eq!(servers[0].port, 7777);
+        assert_eq!(servers[0].rules["weburl"], "open.mp");
+        assert!(servers[0].partner);
+        assert_eq!(servers[1].ip, "::1");
+        assert!(servers[1].rules.is_empty());
+
+        assert!(parse_list("{\"servers\": []}").is_err());
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_snapshot_headers() {
+        let dir = temp_dir("master_list_revalidate");
+        let (url, requests) = http_stand_in(vec![
+            Reply {
+                status: "200 OK",
+                headers: vec![
+                    ("etag", "\"v1\""),
+                    ("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                ],
+                body: LIST,
+            },
+            Reply {
+                status: "304 Not Modified",
+                headers: vec![],
+                body: "",
+            },
+        ])
+        .await;
+
+        let first = fetch(&url, &dir).await.unwrap();
+        assert_eq!(first.source, ListSource::Network);
+        assert_eq!(first.servers.len(), 2);
+
+        let second = fetch(&url, &dir).await.unwrap();
+        assert_eq!(second.source, ListSource::NotModified);
+        assert_eq!(second.servers, first.servers);
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert!(requests[1].contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_snapshot() {
+        let dir = temp_dir("master_list_fallback");
+        let (url, _) = http_stand_in(vec![
+            Reply {
+                status: "200 OK",
+                headers: vec![],
+                body: LIST,
+            },
+            Reply {
+                status: "500 Internal Server Error",
+                headers: vec![],
+                body: "",
+            },
+            Reply {
+                status: "200 OK",
+                headers: vec![],
+                body: "<html>maintenance</html>",
+            },
+        ])
+        .await;
+
+        fetch(&url, &dir).await.unwrap();
+        for _ in 0..2 {
+            let list = fetch(&url, &dir).await.unwrap();
+            assert_eq!(list.source, ListSource::Snapshot);
+            assert_eq!(list.servers.len(), 2);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn fails_without_snapshot() {
+        let dir = temp_dir("master_list_offline");
+        let (url, _) = http_stand_in(vec![Reply {
+            status: "503 Service Unavailable",
+            headers: vec![],
+            body: "",
+        }])
+        .await;
+
+        assert!(matches!(
+            fetch(&url, &dir).await,
+            Err(MasterListError::Status(503))
+        ));
+    }
+}
@@ -0,0 +1,301 @@
+//! Fake SA-MP/open.mp server answering query packets on localhost, so the query
+//! code can be tested without a real game server.
+
+use encoding::all::WINDOWS_1251;
+use encoding::{EncoderTrap, Encoding};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use crate::query::{DetailedPlayer, ExtraInfoPacket, InfoPacket, PLAYER_LIST_LIMIT};
+use crate::query_parser::HEADER_LEN;
+
+/// How the server treats requests with a given opcode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Behaviour {
+    Answer,
+    Drop,
+    // valid header, payload cut in half
+    Truncate,
+    // "SAMP" replaced with garbage
+    BadMagic,
+    // answers with a different opcode than the one asked for
+    WrongOpcode,
+}
+
+pub struct MockConfig {
+    pub info: InfoPacket,
+    // 'c' is answered with the names and scores of these
+    pub players: Vec<DetailedPlayer>,
+    pub rules: Vec<(String, String)>,
+    // servers without open.mp extensions don't answer 'o'
+    pub extra_info: Option<ExtraInfoPacket>,
+    pub rcon_password: Option<String>,
+    pub rcon_output: Vec<String>,
+    // how strings are encoded on the wire
+    pub encode: fn(&str) -> Vec<u8>,
+    pub delay: Duration,
+    // drops every nth request, counting all opcodes
+    pub drop_every: Option<u32>,
+    pub behaviours: HashMap<char, Behaviour>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            info: InfoPacket {
+                password: false,
+                players: 2,
+                max_players: 50,
+                hostname: "open.mp mock server".to_string(),
+                gamemode: "freeroam".to_string(),
+                language: "English".to_string(),
+            },
+            players: vec![
+                DetailedPlayer {
+                    id: 0,
+                    name: "Player1".to_string(),
+                    score: 10,
+                    ping: 40,
+                },
+                DetailedPlayer {
+                    id: 3,
+                    name: "Player2".to_string(),
+                    score: -2,
+                    ping: 120,
+                },
+            ],
+            rules: vec![
+                ("version".to_string(), "omp 1.0.0".to_string()),
+                ("weburl".to_string(), "open.mp".to_string()),
+            ],
+            extra_info: Some(ExtraInfoPacket {
+                discord_link: "https://discord.gg/samp".to_string(),
+                light_banner_url: String::new(),
+                dark_banner_url: String::new(),
+            }),
+            rcon_password: Some("changeme".to_string()),
+            rcon_output: vec!["hostname = open.mp mock server".to_string()],
+            encode: utf8,
+            delay: Duration::ZERO,
+            drop_every: None,
+            behaviours: HashMap::new(),
+        }
+    }
+}
+
+pub fn utf8(s: &str) -> Vec<u8> {
+    s.as_bytes().to_vec()
+}
+
+pub fn cp1251(s: &str) -> Vec<u8> {
+    WINDOWS_1251.encode(s, EncoderTrap::Replace).unwrap()
+}
+
+pub struct MockServer {
+    pub addr: SocketAddr,
+    pub config: Arc<Mutex<MockConfig>>,
+    requests: Arc<AtomicU32>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start(config: MockConfig) -> Self {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = socket.local_addr().unwrap();
+        let config = Arc::new(Mutex::new(config));
+        let requests = Arc::new(AtomicU32::new(0));
+
+        let task = tokio::spawn(serve(socket, config.clone(), requests.clone()));
+        Self {
+            addr,
+            config,
+            requests,
+            task,
+        }
+    }
+
+    pub fn port(&self) -> i32 {
+        self.addr.port() as i32
+    }
+
+    /// Number of requests received so far, dropped ones included.
+    pub fn requests(&self) -> u32 {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(socket: Arc<UdpSocket>, config: Arc<Mutex<MockConfig>>, requests: Arc<AtomicU32>) {
+    let mut buf = vec![0; 2048];
+    loop {
+        let (amt, from) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        let count = requests.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if amt < HEADER_LEN || &buf[..4] != b"SAMP" {
+            continue;
+        }
+
+        let (replies, delay) = {
+            let config = config.lock().unwrap();
+            if config.drop_every.is_some_and(|n| count.is_multiple_of(n)) {
+                continue;
+            }
+            (respond(&config, &buf[..amt]), config.delay)
+        };
+
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            for reply in replies {
+                let _ = socket.send_to(&reply, from).await;
+            }
+        });
+    }
+}
+
+fn respond(config: &MockConfig, request: &[u8]) -> Vec<Vec<u8>> {
+    let opcode = request[10] as char;
+    let behaviour = config
+        .behaviours
+        .get(&opcode)
+        .copied()
+        .unwrap_or(Behaviour::Answer);
+    if behaviour == Behaviour::Drop {
+        return Vec::new();
+    }
+
+    let payloads = match payloads(config, opcode, &request[HEADER_LEN..]) {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+
+    payloads
+        .into_iter()
+        .map(|payload| {
+            let mut reply = request[..HEADER_LEN].to_vec();
+            reply.extend_from_slice(&payload);
+            match behaviour {
+                Behaviour::Truncate => {
+                    let len = HEADER_LEN + payload.len() / 2;
+                    reply.truncate(len);
+                }
+                Behaviour::BadMagic => reply[..4].copy_from_slice(b"XXXX"),
+                Behaviour::WrongOpcode => reply[10] = if opcode == 'i' { b'r' } else { b'i' },
+                _ => {}
+            }
+            reply
+        })
+        .collect()
+}
+
+// everything that goes after the header, one entry per datagram
+fn payloads(config: &MockConfig, opcode: char, body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let encode = config.encode;
+    let mut payload = Vec::new();
+
+    match opcode {
+        'i' => {
+            let info = &config.info;
+            payload.push(info.password as u8);
+            payload.extend_from_slice(&info.players.to_le_bytes());
+            payload.extend_from_slice(&info.max_players.to_le_bytes());
+            long_string(&mut payload, &encode(&info.hostname));
+            long_string(&mut payload, &encode(&info.gamemode));
+            long_string(&mut payload, &encode(&info.language));
+        }
+        'c' | 'd' => {
+            // just like the real thing, big servers keep their list to themselves
+            if config.info.players > PLAYER_LIST_LIMIT {
+                return None;
+            }
+            payload.extend_from_slice(&(config.players.len() as u16).to_le_bytes());
+            for player in &config.players {
+                if opcode == 'd' {
+                    payload.push(player.id);
+                }
+                short_string(&mut payload, &encode(&player.name));
+                payload.extend_from_slice(&player.score.to_le_bytes());
+                if opcode == 'd' {
+                    payload.extend_from_slice(&player.ping.to_le_bytes());
+                }
+            }
+        }
+        'r' => {
+            payload.extend_from_slice(&(config.rules.len() as u16).to_le_bytes());
+            for (name, value) in &config.rules {
+                short_string(&mut payload, &encode(name));
+                short_string(&mut payload, &encode(value));
+            }
+        }
+        'o' => {
+            let extra = config.extra_info.as_ref()?;
+            long_string(&mut payload, &encode(&extra.discord_link));
+            long_string(&mut payload, &encode(&extra.light_banner_url));
+            long_string(&mut payload, &encode(&extra.dark_banner_url));
+        }
+        'p' => {
+            payload.extend_from_slice(body.get(..4)?);
+        }
+        'x' => {
+            let rcon_password = config.rcon_password.as_ref()?;
+            let (password, command) = rcon_request(body)?;
+            if command.is_empty() {
+                return None;
+            }
+
+            let lines = if password == rcon_password.as_bytes() {
+                config.rcon_output.clone()
+            } else {
+                vec!["Invalid RCON password.".to_string()]
+            };
+            return Some(
+                lines
+                    .iter()
+                    .map(|line| {
+                        let line = encode(line);
+                        let mut payload = (line.len() as u16).to_le_bytes().to_vec();
+                        payload.extend_from_slice(&line);
+                        payload
+                    })
+                    .collect(),
+            );
+        }
+        _ => return None,
+    }
+
+    Some(vec![payload])
+}
+
+fn rcon_request(body: &[u8]) -> Option<(&[u8], &[u8])> {
+    let password_len = u16::from_le_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let password = body.get(2..2 + password_len)?;
+    let rest = &body[2 + password_len..];
+    let command_len = u16::from_le_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+    let command = rest.get(2..2 + command_len)?;
+    Some((password, command))
+}
+
+fn long_string(payload: &mut Vec<u8>, s: &[u8]) {
+    payload.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    payload.extend_from_slice(s);
+}
+
+fn short_string(payload: &mut Vec<u8>, s: &[u8]) {
+    payload.push(s.len() as u8);
+    payload.extend_from_slice(s);
+}
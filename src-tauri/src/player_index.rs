@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::helpers;
+use crate::query::QueryResponse;
+use crate::subscriptions::ServerDiff;
+
+const DEFAULT_LIMIT: usize = 50;
+// players nobody has seen for this long are dropped, the index would grow
+// forever with every name that ever joined a queried server otherwise
+const FORGET_AFTER: u64 = 7 * 24 * 60 * 60 * 1000;
+const PRUNE_INTERVAL: u64 = 60 * 1000;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    Exact,
+    Prefix,
+    // within a couple of typos, or anywhere in the name
+    #[default]
+    Fuzzy,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct PlayerHit {
+    pub name: String,
+    pub ip: String,
+    pub port: i32,
+    // still on the server's last player list
+    pub online: bool,
+    // unix millis, when the player was last on the list or left it; now for
+    // players that are still online
+    pub last_seen: u64,
+}
+
+struct Sighting {
+    // the way the server spells it, the index itself goes by lowercase
+    name: String,
+    last_seen: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    // lowercase name, then server
+    players: HashMap<String, HashMap<(String, i32), Sighting>>,
+    // lowercase names on each server's last player list
+    online: HashMap<(String, i32), HashSet<String>>,
+    pruned: u64,
+}
+
+impl Inner {
+    fn leave(&mut self, server: &(String, i32), names: HashSet<String>, now: u64) {
+        for name in names {
+            if let Some(sighting) = self
+                .players
+                .get_mut(&name)
+                .and_then(|servers| servers.get_mut(server))
+            {
+                sighting.last_seen = now;
+            }
+        }
+    }
+
+    fn prune(&mut self, now: u64) {
+        if now.saturating_sub(self.pruned) < PRUNE_INTERVAL {
+            return;
+        }
+        self.pruned = now;
+
+        let online = &self.online;
+        self.players.retain(|name, servers| {
+            servers.retain(|server, sighting| {
+                now.saturating_sub(sighting.last_seen) < FORGET_AFTER
+                    || online.get(server).is_some_and(|names| names.contains(name))
+            });
+            !servers.is_empty()
+        });
+    }
+}
+
+/// Which players were seen on which servers, built from the player lists of
+/// every server we query, so anyone can be looked up across the whole list.
+pub struct PlayerIndex {
+    inner: Mutex<Inner>,
+}
+
+impl PlayerIndex {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Replaces the player list of a server with `names`. Players that aren't
+    /// on it anymore are taken as having left just now.
+    pub fn record<'a, I>(&self, ip: &str, port: i32, names: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        self.record_at(ip, port, names, helpers::now())
+    }
+
+    fn record_at<'a, I>(&self, ip: &str, port: i32, names: I, now: u64)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let server = (ip.to_string(), port);
+        let mut inner = self.inner.lock().unwrap();
+
+        let mut online = HashSet::new();
+        for name in names {
+            let key = name.to_lowercase();
+            inner.players.entry(key.clone()).or_default().insert(
+                server.clone(),
+                Sighting {
+                    name: name.to_string(),
+                    last_seen: now,
+                },
+            );
+            online.insert(key);
+        }
+
+        let left = inner
+            .online
+            .get(&server)
+            .map(|previous| previous.difference(&online).cloned().collect())
+            .unwrap_or_default();
+        inner.leave(&server, left, now);
+        inner.online.insert(server, online);
+        inner.prune(now);
+    }
+
+    /// Records the player list in a response to a 'c' or 'd' query, anything
+    /// else is ignored.
+    pub fn record_response(&self, ip: &str, port: i32, response: &QueryResponse) {
+        match response {
+            QueryResponse::Players(players) => {
+                self.record(ip, port, players.iter().map(|p| p.name.as_str()))
+            }
+            QueryResponse::DetailedPlayers(players) => {
+                self.record(ip, port, players.iter().map(|p| p.name.as_str()))
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a subscription update, diffs only carry player lists that
+    /// changed.
+    pub fn apply(&self, ip: &str, port: i32, diff: &ServerDiff) {
+        if let Some(players) = &diff.player_list {
+            self.record(ip, port, players.iter().map(|p| p.name.as_str()));
+        }
+        if let Some(players) = &diff.detailed_player_list {
+            self.record(ip, port, players.iter().map(|p| p.name.as_str()));
+        }
+        if diff.online == Some(false) {
+            self.forget_server(ip, port);
+        }
+    }
+
+    /// Everyone on a server that stopped answering has left it.
+    pub fn forget_server(&self, ip: &str, port: i32) {
+        let server = (ip.to_string(), port);
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(left) = inner.online.remove(&server) {
+            inner.leave(&server, left, helpers::now());
+        }
+    }
+
+    /// Players whose name matches `query` the way `mode` asks, closest names
+    /// first, then online players, then the most recently seen.
+    pub fn search(&self, query: &str, mode: SearchMode, limit: Option<usize>) -> Vec<PlayerHit> {
+        self.search_at(query, mode, limit, helpers::now())
+    }
+
+    fn search_at(
+        &self,
+        query: &str,
+        mode: SearchMode,
+        limit: Option<usize>,
+        now: u64,
+    ) -> Vec<PlayerHit> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let inner = self.inner.lock().unwrap();
+        let mut hits: Vec<(usize, PlayerHit)> = Vec::new();
+        for (name, servers) in &inner.players {
+            let distance = match distance(name, &query, mode) {
+                Some(distance) => distance,
+                None => continue,
+            };
+            for (server, sighting) in servers {
+                let online = inner
+                    .online
+                    .get(server)
+                    .is_some_and(|names| names.contains(name));
+                hits.push((
+                    distance,
+                    PlayerHit {
+                        name: sighting.name.clone(),
+                        ip: server.0.clone(),
+                        port: server.1,
+                        online,
+                        last_seen: if online { now } else { sighting.last_seen },
+                    },
+                ));
+            }
+        }
+
+        hits.sort_by(|(a_distance, a), (b_distance, b)| {
+            a_distance
+                .cmp(b_distance)
+                .then_with(|| b.online.cmp(&a.online))
+                .then_with(|| b.last_seen.cmp(&a.last_seen))
+                .then_with(|| compare_hits(a, b))
+        });
+        hits.into_iter()
+            .map(|(_, hit)| hit)
+            .take(limit.unwrap_or(DEFAULT_LIMIT))
+            .collect()
+    }
+}
+
+fn compare_hits(a: &PlayerHit, b: &PlayerHit) -> Ordering {
+    a.name
+        .cmp(&b.name)
+        .then_with(|| a.ip.cmp(&b.ip))
+        .then_with(|| a.port.cmp(&b.port))
+}
+
+// how far `name` is from `query`, None when it doesn't match at all. Both
+// are lowercase already
+fn distance(name: &str, query: &str, mode: SearchMode) -> Option<usize> {
+    if name == query {
+        return Some(0);
+    }
+
+    // a prefix is closer the more of the name it covers
+    let prefix = name
+        .starts_with(query)
+        .then(|| 1 + name.len() - query.len());
+    match mode {
+        SearchMode::Exact => None,
+        SearchMode::Prefix => prefix,
+        SearchMode::Fuzzy => {
+            if prefix.is_some() {
+                return prefix;
+            }
+            // behind every prefix, whatever the typo count
+            const FUZZY: usize = 1000;
+            if name.contains(query) {
+                return Some(FUZZY + name.len() - query.len());
+            }
+            let max_distance = if query.len() >= 6 { 2 } else { 1 };
+            if query.len() < 3 || name.len().abs_diff(query.len()) > max_distance {
+                return None;
+            }
+            let distance = strsim::damerau_levenshtein(name, query);
+            (distance <= max_distance).then_some(FUZZY + distance)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(hits: &[PlayerHit]) -> Vec<(&str, i32)> {
+        hits.iter().map(|h| (h.name.as_str(), h.port)).collect()
+    }
+
+    #[test]
+    fn finds_players_by_name() {
+        let index = PlayerIndex::new();
+        index.record_at("10.0.0.1", 7777, ["John_Doe", "Johnny", "Jane"], 100);
+        index.record_at("10.0.0.2", 7777, ["john_doe", "Mike_Johnson"], 200);
+
+        let hits = index.search_at("JOHN_DOE", SearchMode::Exact, None, 300);
+        // both are online, so seen just now
+        assert_eq!(names(&hits), vec![("John_Doe", 7777), ("john_doe", 7777)]);
+        assert_eq!(hits[0].ip, "10.0.0.1");
+        assert_eq!(hits[1].last_seen, 300);
+
+        let hits = index.search_at("john", SearchMode::Prefix, None, 300);
+        assert_eq!(
+            names(&hits),
+            vec![("Johnny", 7777), ("John_Doe", 7777), ("john_doe", 7777)]
+        );
+
+        let hits = index.search_at("jon_doe", SearchMode::Fuzzy, None, 300);
+        assert_eq!(hits.len(), 2);
+        let hits = index.search_at("john", SearchMode::Fuzzy, Some(4), 300);
+        assert_eq!(hits.len(), 4);
+        assert_eq!(hits[3].name, "Mike_Johnson");
+
+        assert!(index
+            .search_at("jo", SearchMode::Exact, None, 300)
+            .is_empty());
+        assert!(index
+            .search_at(" ", SearchMode::Fuzzy, None, 300)
+            .is_empty());
+    }
+
+    #[test]
+    fn remembers_when_players_left() {
+        let index = PlayerIndex::new();
+        index.record_at("10.0.0.1", 7777, ["John_Doe", "Jane"], 100);
+
+        let hits = index.search_at("jane", SearchMode::Exact, None, 150);
+        assert!(hits[0].online);
+        assert_eq!(hits[0].last_seen, 150);
+
+        index.record_at("10.0.0.1", 7777, ["John_Doe"], 200);
+        let hits = index.search_at("jane", SearchMode::Exact, None, 300);
+        assert!(!hits[0].online);
+        assert_eq!(hits[0].last_seen, 200);
+
+        index.forget_server("10.0.0.1", 7777);
+        let hits = index.search_at("john_doe", SearchMode::Exact, None, 300);
+        assert!(!hits[0].online);
+
+        // long gone players are dropped on the next record
+        index.record_at("10.0.0.2", 7777, ["Mike"], 200 + FORGET_AFTER);
+        assert!(index
+            .search_at("jane", SearchMode::Exact, None, 300 + FORGET_AFTER)
+            .is_empty());
+    }
+}
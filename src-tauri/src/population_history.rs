@@ -0,0 +1,636 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::helpers;
+use crate::query::{InfoPacket, QueryResponse};
+use crate::query_pool::BatchResult;
+use crate::subscriptions::ServerDiff;
+
+const HISTORY_DIR: &str = "history";
+/// How often samples in memory get written out.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+// in seconds, like everything stored. Servers refreshed more often than this
+// still get only one sample per spacing
+const SAMPLE_SPACING: u64 = 5 * 60;
+// samples are kept as they came for a day, then folded into hourly buckets
+// that are kept for three months
+const RAW_RETENTION: u64 = 24 * HOUR;
+const HOURLY_RETENTION: u64 = 90 * 24 * HOUR;
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    // samples as they came where we still have them, hourly before that
+    Raw,
+    #[default]
+    Hourly,
+}
+
+/// Averages over a stretch of time.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Stats {
+    // average while the server was online
+    pub players: f32,
+    pub peak: u16,
+    pub ping: Option<u32>,
+    // share of samples the server was online for, 0 to 1
+    pub uptime: f32,
+    pub samples: u32,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct HistoryPoint {
+    // unix millis, the start of the hour for hourly points
+    pub time: u64,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct HourOfDay {
+    // 0 to 23, in the time zone the hours were asked for
+    pub hour: u8,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+// a file has up to a day of these per server, so they're stored as
+// [time, players, ping, online]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(
+    from = "(u64, u16, Option<u32>, bool)",
+    into = "(u64, u16, Option<u32>, bool)"
+)]
+struct Sample {
+    time: u64,
+    players: u16,
+    ping: Option<u32>,
+    online: bool,
+}
+
+impl From<(u64, u16, Option<u32>, bool)> for Sample {
+    fn from((time, players, ping, online): (u64, u16, Option<u32>, bool)) -> Self {
+        Self {
+            time,
+            players,
+            ping,
+            online,
+        }
+    }
+}
+
+impl From<Sample> for (u64, u16, Option<u32>, bool) {
+    fn from(sample: Sample) -> Self {
+        (sample.time, sample.players, sample.ping, sample.online)
+    }
+}
+
+// sums over the samples of a stretch of time, stored as
+// [start, samples, online, players, peak, ping, pings]
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(
+    from = "(u64, u32, u32, u32, u16, u64, u32)",
+    into = "(u64, u32, u32, u32, u16, u64, u32)"
+)]
+struct Bucket {
+    start: u64,
+    samples: u32,
+    // samples the server was online in
+    online: u32,
+    // players over the online samples
+    players: u32,
+    peak: u16,
+    // ping over the samples that had one
+    ping: u64,
+    pings: u32,
+}
+
+impl From<(u64, u32, u32, u32, u16, u64, u32)> for Bucket {
+    fn from(
+        (start, samples, online, players, peak, ping, pings): (u64, u32, u32, u32, u16, u64, u32),
+    ) -> Self {
+        Self {
+            start,
+            samples,
+            online,
+            players,
+            peak,
+            ping,
+            pings,
+        }
+    }
+}
+
+impl From<Bucket> for (u64, u32, u32, u32, u16, u64, u32) {
+    fn from(b: Bucket) -> Self {
+        (
+            b.start, b.samples, b.online, b.players, b.peak, b.ping, b.pings,
+        )
+    }
+}
+
+impl Bucket {
+    fn add(&mut self, sample: &Sample) {
+        self.samples += 1;
+        if sample.online {
+            self.online += 1;
+            self.players += sample.players as u32;
+            self.peak = self.peak.max(sample.players);
+        }
+        if let Some(ping) = sample.ping {
+            self.ping += ping as u64;
+            self.pings += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &Bucket) {
+        self.samples += other.samples;
+        self.online += other.online;
+        self.players += other.players;
+        self.peak = self.peak.max(other.peak);
+        self.ping += other.ping;
+        self.pings += other.pings;
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            players: if self.online > 0 {
+                self.players as f32 / self.online as f32
+            } else {
+                0.0
+            },
+            peak: self.peak,
+            ping: (self.pings > 0).then(|| (self.ping / self.pings as u64) as u32),
+            uptime: if self.samples > 0 {
+                self.online as f32 / self.samples as f32
+            } else {
+                0.0
+            },
+            samples: self.samples,
+        }
+    }
+}
+
+// adds `sample` to the bucket of its hour, samples come in time order
+fn fold(hours: &mut Vec<Bucket>, sample: &Sample) {
+    let start = sample.time - sample.time % HOUR;
+    match hours.last_mut() {
+        Some(bucket) if bucket.start == start => bucket.add(sample),
+        _ => {
+            let mut bucket = Bucket {
+                start,
+                ..Default::default()
+            };
+            bucket.add(sample);
+            hours.push(bucket);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+struct Series {
+    hours: Vec<Bucket>,
+    samples: Vec<Sample>,
+}
+
+impl Series {
+    fn load(dir: &Path, ip: &str, port: i32) -> Self {
+        let data = match fs::read(dir.join(HISTORY_DIR).join(file_name(ip, port))) {
+            Ok(data) => data,
+            Err(_) => return Series::default(),
+        };
+        match serde_json::from_slice(&data) {
+            Ok(series) => series,
+            Err(e) => {
+                info!(
+                    "[population_history.rs] Ignoring broken history of {}:{}: {}",
+                    ip, port, e
+                );
+                Series::default()
+            }
+        }
+    }
+
+    // folds samples past the raw retention into hours and drops hours past
+    // the hourly one
+    fn compact(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(RAW_RETENTION);
+        let old = self.samples.iter().take_while(|s| s.time < cutoff).count();
+        for sample in self.samples.drain(..old) {
+            fold(&mut self.hours, &sample);
+        }
+
+        let cutoff = now.saturating_sub(HOURLY_RETENTION);
+        self.hours.retain(|bucket| bucket.start + HOUR > cutoff);
+    }
+
+    // everything since `since` as buckets of the resolution, oldest first
+    fn buckets(&self, since: u64, resolution: Resolution) -> Vec<Bucket> {
+        let mut buckets: Vec<Bucket> = self
+            .hours
+            .iter()
+            .filter(|bucket| bucket.start + HOUR > since)
+            .copied()
+            .collect();
+        for sample in self.samples.iter().filter(|s| s.time >= since) {
+            match resolution {
+                Resolution::Hourly => fold(&mut buckets, sample),
+                Resolution::Raw => {
+                    let mut bucket = Bucket {
+                        start: sample.time,
+                        ..Default::default()
+                    };
+                    bucket.add(sample);
+                    buckets.push(bucket);
+                }
+            }
+        }
+        buckets
+    }
+}
+
+// servers can be host names or ipv6 addresses, neither of which makes a
+// good file name as is. hex keeps two different hosts from ever sharing a file
+fn file_name(ip: &str, port: i32) -> String {
+    let ip: String = ip.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("{}_{}.json", ip, port)
+}
+
+// the last known state of a server, put together from whatever query told
+// us about it
+#[derive(Default)]
+struct Current {
+    online: Option<bool>,
+    players: Option<u16>,
+    ping: Option<u32>,
+}
+
+impl Current {
+    fn sample(&self, time: u64) -> Option<Sample> {
+        match (self.online, self.players) {
+            (Some(false), _) => Some(Sample {
+                time,
+                players: 0,
+                ping: None,
+                online: false,
+            }),
+            (_, Some(players)) => Some(Sample {
+                time,
+                players,
+                ping: self.ping,
+                online: true,
+            }),
+            _ => None,
+        }
+    }
+}
+
+struct Tracked {
+    series: Series,
+    current: Current,
+    // has samples that aren't written out yet
+    dirty: bool,
+    // got something since the last flush
+    touched: bool,
+}
+
+/// How many players the servers we query had over time, sampled from the
+/// query results that come in anyway and kept in one file per server.
+pub struct PopulationHistory {
+    dir: PathBuf,
+    servers: Mutex<HashMap<(String, i32), Tracked>>,
+}
+
+impl PopulationHistory {
+    /// Keeps the history in `dir`. Files nobody wrote to for longer than
+    /// anything in them is kept are removed right away.
+    pub fn new(dir: PathBuf) -> Self {
+        let expired = SystemTime::now() - Duration::from_secs(HOURLY_RETENTION);
+        if let Ok(entries) = fs::read_dir(dir.join(HISTORY_DIR)) {
+            for entry in entries.flatten() {
+                let modified = entry.metadata().and_then(|m| m.modified());
+                if modified.is_ok_and(|modified| modified < expired) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        Self {
+            dir,
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Applies a subscription update. An empty diff is a refresh that
+    /// changed nothing, which still makes a sample.
+    pub fn apply(&self, ip: &str, port: i32, diff: &ServerDiff) {
+        self.observe(ip, port, now(), |current| {
+            if diff.online.is_some() {
+                current.online = diff.online;
+            }
+            if diff.players.is_some() {
+                current.players = diff.players;
+            }
+            if diff.ping.is_some() {
+                current.ping = diff.ping;
+            }
+        });
+    }
+
+    /// Records the answer to an info query, None for a server that timed
+    /// out.
+    pub fn record_info(&self, ip: &str, port: i32, info: Option<&InfoPacket>) {
+        self.observe(ip, port, now(), |current| match info {
+            Some(info) => {
+                current.online = Some(true);
+                current.players = Some(info.players);
+            }
+            None => current.online = Some(false),
+        });
+    }
+
+    pub fn record_ping(&self, ip: &str, port: i32, ping: u32) {
+        self.observe(ip, port, now(), |current| current.ping = Some(ping));
+    }
+
+    /// Records the info and ping results of a batch, anything else is
+    /// ignored.
+    pub fn record_result(&self, result: &BatchResult) {
+        match (&result.response, result.query_type) {
+            (Some(QueryResponse::Info(info)), _) => {
+                self.record_info(&result.ip, result.port, Some(info))
+            }
+            (None, 'i') if result.timed_out => self.record_info(&result.ip, result.port, None),
+            (Some(QueryResponse::Pong), _) => {
+                if let Some(ping) = result.ping {
+                    self.record_ping(&result.ip, result.port, ping);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn observe<F>(&self, ip: &str, port: i32, now: u64, update: F)
+    where
+        F: FnOnce(&mut Current),
+    {
+        let key = (ip.to_string(), port);
+        let mut loaded = None;
+        let mut servers = loop {
+            let servers = self.servers.lock().unwrap();
+            if loaded.is_some() || servers.contains_key(&key) {
+                break servers;
+            }
+            // reading the file shouldn't hold up every other server
+            drop(servers);
+            loaded = Some(Series::load(&self.dir, ip, port));
+        };
+        // someone else may have loaded it in the meantime, theirs wins
+        let tracked = servers.entry(key).or_insert_with(|| Tracked {
+            series: loaded.unwrap_or_default(),
+            current: Current::default(),
+            dirty: false,
+            touched: false,
+        });
+        tracked.touched = true;
+        update(&mut tracked.current);
+
+        let too_soon = tracked
+            .series
+            .samples
+            .last()
+            .is_some_and(|last| now < last.time + SAMPLE_SPACING);
+        if let Some(sample) = tracked.current.sample(now).filter(|_| !too_soon) {
+            tracked.series.samples.push(sample);
+            tracked.series.compact(now);
+            tracked.dirty = true;
+        }
+    }
+
+    /// Writes out every server with new samples. Servers nothing came in for
+    /// since the last flush are let go of, they're loaded again once they do.
+    pub fn flush(&self) {
+        let dir = self.dir.join(HISTORY_DIR);
+        let mut servers = self.servers.lock().unwrap();
+        servers.retain(|(ip, port), tracked| {
+            if tracked.dirty {
+                match helpers::save_json(&dir, &file_name(ip, *port), &tracked.series) {
+                    Ok(_) => tracked.dirty = false,
+                    // kept around to try again next time
+                    Err(e) => info!(
+                        "[population_history.rs] Failed to save history of {}:{}: {}",
+                        ip, port, e
+                    ),
+                }
+            }
+            let keep = tracked.touched || tracked.dirty;
+            tracked.touched = false;
+            keep
+        });
+    }
+
+    // a copy of what we have on a server, without keeping it in memory if it
+    // wasn't already
+    fn series(&self, ip: &str, port: i32) -> Series {
+        let tracked = self
+            .servers
+            .lock()
+            .unwrap()
+            .get(&(ip.to_string(), port))
+            .map(|tracked| tracked.series.clone());
+        tracked.unwrap_or_else(|| Series::load(&self.dir, ip, port))
+    }
+
+    /// The server's history since `since` (unix millis, everything we have
+    /// without), oldest first.
+    pub fn history(
+        &self,
+        ip: &str,
+        port: i32,
+        since: Option<u64>,
+        resolution: Resolution,
+    ) -> Vec<HistoryPoint> {
+        let since = since.unwrap_or(0) / 1000;
+        self.series(ip, port)
+            .buckets(since, resolution)
+            .iter()
+            .map(|bucket| HistoryPoint {
+                time: bucket.start * 1000,
+                stats: bucket.stats(),
+            })
+            .collect()
+    }
+
+    /// Averages for each hour of the day over the last `days` (everything we
+    /// have without), ordered by hour. `utc_offset` is in minutes, the way
+    /// the hours should be read in, ahead of UTC being positive.
+    pub fn busiest_hours(
+        &self,
+        ip: &str,
+        port: i32,
+        utc_offset: i32,
+        days: Option<u64>,
+    ) -> Vec<HourOfDay> {
+        self.busiest_hours_at(ip, port, utc_offset, days, now())
+    }
+
+    fn busiest_hours_at(
+        &self,
+        ip: &str,
+        port: i32,
+        utc_offset: i32,
+        days: Option<u64>,
+        now: u64,
+    ) -> Vec<HourOfDay> {
+        let since = days.map_or(0, |days| now.saturating_sub(days * DAY));
+        let mut hours = [Bucket::default(); 24];
+        for bucket in self.series(ip, port).buckets(since, Resolution::Hourly) {
+            let local = bucket.start as i64 + utc_offset as i64 * 60;
+            let hour = local.rem_euclid(DAY as i64) / HOUR as i64;
+            hours[hour as usize].merge(&bucket);
+        }
+
+        hours
+            .iter()
+            .enumerate()
+            .map(|(hour, bucket)| HourOfDay {
+                hour: hour as u8,
+                stats: bucket.stats(),
+            })
+            .collect()
+    }
+}
+
+fn now() -> u64 {
+    helpers::now() / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::temp_dir;
+
+    fn players(history: &PopulationHistory, players: u16, now: u64) {
+        history.observe("10.0.0.1", 7777, now, |current| {
+            current.online = Some(true);
+            current.players = Some(players);
+        });
+    }
+
+    #[test]
+    fn samples_and_downsamples() {
+        let dir = temp_dir("history_samples");
+        let history = PopulationHistory::new(dir.clone());
+        let start = 1_700_000_000 - 1_700_000_000 % DAY;
+
+        players(&history, 10, start);
+        // too soon after the first one
+        players(&history, 50, start + 60);
+        players(&history, 20, start + SAMPLE_SPACING);
+        history.observe("10.0.0.1", 7777, start + HOUR, |current| {
+            current.online = Some(false)
+        });
+
+        let series = history.series("10.0.0.1", 7777);
+        assert_eq!(series.samples.len(), 3);
+        assert!(series.hours.is_empty());
+
+        // a day later the first hour gets folded
+        players(&history, 30, start + RAW_RETENTION + HOUR);
+        let series = history.series("10.0.0.1", 7777);
+        assert_eq!(series.hours.len(), 1);
+        let stats = series.hours[0].stats();
+        assert_eq!(stats.players, 15.0);
+        assert_eq!(stats.peak, 20);
+        assert_eq!(stats.uptime, 1.0);
+        assert_eq!(series.samples.len(), 2);
+
+        // and survives a restart
+        history.flush();
+        let history = PopulationHistory::new(dir.clone());
+        let hourly = history
+            .series("10.0.0.1", 7777)
+            .buckets(0, Resolution::Hourly);
+        assert_eq!(hourly.len(), 3);
+        assert_eq!(hourly[1].stats().uptime, 0.0);
+        assert_eq!(hourly[2].stats().players, 30.0);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_timeouts_count_as_offline() {
+        let dir = temp_dir("history_offline");
+        let history = PopulationHistory::new(dir.clone());
+        let failed = |timed_out| BatchResult {
+            ip: "10.0.0.1".to_string(),
+            port: 7777,
+            query_type: 'i',
+            ping: None,
+            response: None,
+            error: Some("error".to_string()),
+            timed_out,
+            changed: true,
+        };
+
+        // backed off or never resolved, the server wasn't even asked
+        history.record_result(&failed(false));
+        assert!(history.series("10.0.0.1", 7777).samples.is_empty());
+
+        history.record_result(&failed(true));
+        let series = history.series("10.0.0.1", 7777);
+        assert_eq!(series.samples.len(), 1);
+        assert!(!series.samples[0].online);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn file_names_tell_hosts_apart() {
+        assert_ne!(file_name("a:b", 7777), file_name("a_b", 7777));
+        assert_ne!(file_name("::1", 7777), file_name("__1", 7777));
+        assert_eq!(file_name("::1", 7777), "3a3a31_7777.json");
+    }
+
+    #[test]
+    fn averages_hours_of_the_day() {
+        let dir = temp_dir("history_hours");
+        let history = PopulationHistory::new(dir.clone());
+        let start = 1_700_000_000 - 1_700_000_000 % DAY;
+
+        // busy at 20:00 UTC on two days, quiet in the morning
+        for day in 0..2 {
+            players(&history, 2, start + day * DAY + 8 * HOUR);
+            players(
+                &history,
+                40 + day as u16 * 20,
+                start + day * DAY + 20 * HOUR,
+            );
+        }
+
+        let now = start + 2 * DAY;
+        let hours = history.busiest_hours_at("10.0.0.1", 7777, 0, None, now);
+        assert_eq!(hours.len(), 24);
+        assert_eq!(hours[20].stats.players, 50.0);
+        assert_eq!(hours[20].stats.peak, 60);
+        assert_eq!(hours[8].stats.players, 2.0);
+        assert_eq!(hours[12].stats.samples, 0);
+
+        // read two hours ahead of UTC
+        let hours = history.busiest_hours_at("10.0.0.1", 7777, 120, None, now);
+        assert_eq!(hours[22].stats.players, 50.0);
+        // only the last day
+        let hours = history.busiest_hours_at("10.0.0.1", 7777, 0, Some(1), now);
+        assert_eq!(hours[20].stats.players, 60.0);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,21 +1,42 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use regex::Regex;
+use log::info;
 use serde::{Deserialize, Serialize};
-use std::io::{Cursor, Read};
-use std::{net::Ipv4Addr, time::Duration};
-use tokio::net::{lookup_host, UdpSocket};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::UdpSocket;
 use tokio::time::timeout_at;
 use tokio::time::Instant;
 
-use crate::helpers;
+use crate::query_parser;
+use crate::resolver::Resolver;
 
-pub struct Query {
-    address: Ipv4Addr,
-    port: i32,
+// biggest payload a udp datagram can carry, replies are never cut by our buffer
+pub const MAX_DATAGRAM_LEN: usize = 65535;
+// servers with more players than this don't answer player list queries at all
+pub const PLAYER_LIST_LIMIT: u16 = 100;
+
+// rcon output comes back one line per datagram, we stop listening once
+// nothing has arrived for this long
+const RCON_QUIET_PERIOD: Duration = Duration::from_millis(500);
+const RCON_INVALID_PASSWORD: &str = "Invalid RCON password.";
+
+struct Target {
+    address: SocketAddr,
     socket: UdpSocket,
 }
 
-#[derive(Serialize, Deserialize)]
+pub struct Query {
+    // one connected socket per resolved address, in resolver order
+    targets: Vec<Target>,
+    // target that answered last, send and recv go through it
+    active: AtomicUsize,
+    // last packet we've sent, replies are validated against it
+    last_request: Mutex<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct InfoPacket {
     pub password: bool,
     pub players: u16,
@@ -38,7 +59,7 @@ impl Default for InfoPacket {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Player {
     pub name: String,
     pub score: i32,
@@ -53,7 +74,15 @@ impl Default for Player {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct DetailedPlayer {
+    pub id: u8,
+    pub name: String,
+    pub score: i32,
+    pub ping: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct ExtraInfoPacket {
     pub discord_link: String,
     pub light_banner_url: String,
@@ -70,194 +99,501 @@ impl Default for ExtraInfoPacket {
     }
 }
 
-impl Query {
-    pub async fn new(addr: &str, port: i32) -> Result<Self, std::io::Error> {
-        let regex = Regex::new(r"^(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)$").unwrap();
+/// Outcome of a player list query, so the UI can explain why a list is empty.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "status", content = "data", rename_all = "snake_case")]
+pub enum PlayerList<T> {
+    Complete(Vec<T>),
+    // no reply because the server has more than PLAYER_LIST_LIMIT players
+    Withheld { players: u16 },
+    // a reply came back but ended before the whole list did
+    Truncated,
+    // no reply, with nothing to suggest it's because of the list size
+    TimedOut,
+}
 
-        let address = match regex.captures(addr) {
-            Some(_) => {
-                // it's valid ipv4, move on
-                addr.to_string()
-            }
-            None => {
-                let socket_addresses = lookup_host(format!("{}:{}", addr, port)).await;
-                match socket_addresses {
-                    Ok(s) => {
-                        let mut ipv4 = "".to_string();
-                        for socket_address in s {
-                            if socket_address.is_ipv4() {
-                                // hostname is resolved to ipv4:port, lets split it by ":" and get ipv4 only
-                                let ip_port = socket_address.to_string();
-                                let vec: Vec<&str> = ip_port.split(':').collect();
-                                ipv4 = vec[0].to_string();
-                            }
-                        }
-                        ipv4
-                    }
-                    Err(e) => {
-                        println!("{}", e.to_string());
-                        "".to_string()
-                    }
-                }
-            }
+/// Round trip times are in milliseconds, they're `None` when every probe got lost.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct PingStats {
+    pub sent: u32,
+    pub received: u32,
+    pub loss: f32,
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+    pub median: Option<u32>,
+    pub jitter: Option<f32>,
+}
+
+impl PingStats {
+    pub fn from_samples(sent: u32, samples: &[Duration]) -> Self {
+        let received = samples.len() as u32;
+        let mut stats = Self {
+            sent,
+            received,
+            loss: if sent > 0 {
+                (sent - received.min(sent)) as f32 * 100.0 / sent as f32
+            } else {
+                0.0
+            },
+            ..Default::default()
         };
 
-        let data = Self {
-            address: address.parse::<Ipv4Addr>().unwrap(),
-            port,
-            socket: UdpSocket::bind("0.0.0.0:0").await.unwrap(),
+        if samples.is_empty() {
+            return stats;
+        }
+
+        let ms: Vec<f32> = samples.iter().map(|s| s.as_secs_f32() * 1000.0).collect();
+        let mut sorted = ms.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let middle = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
         };
 
-        data.socket
-            .connect(format!("{}:{}", addr, port))
-            .await
-            .unwrap();
-        Ok(data)
+        // mean difference between consecutive samples, in the order they were taken
+        let jitter = if ms.len() > 1 {
+            ms.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f32>() / (ms.len() - 1) as f32
+        } else {
+            0.0
+        };
+
+        stats.min = Some(sorted[0].round() as u32);
+        stats.max = Some(sorted[sorted.len() - 1].round() as u32);
+        stats.median = Some(median.round() as u32);
+        stats.jitter = Some(jitter);
+        stats
     }
+}
 
-    pub async fn send(&self, query_type: char) -> Result<usize, std::io::Error> {
-        let mut packet: Vec<u8> = Vec::new();
-        packet.append(&mut "SAMP".to_owned().into_bytes());
-        for i in 0..4 {
-            packet.push(self.address.octets()[i]);
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum QueryResponse {
+    Info(InfoPacket),
+    Players(Vec<Player>),
+    DetailedPlayers(Vec<DetailedPlayer>),
+    Rules(Vec<(String, String)>),
+    ExtraInfo(ExtraInfoPacket),
+    Pong,
+    RconOutput(String),
+}
+
+impl Query {
+    pub async fn new(resolver: &Resolver, addr: &str, port: i32) -> Result<Self, std::io::Error> {
+        let addresses = resolver.resolve(addr, port).await?;
+
+        let mut targets = Vec::new();
+        for address in addresses {
+            match Self::connect(address).await {
+                Ok(socket) => targets.push(Target { address, socket }),
+                Err(e) => info!("[query.rs] Unable to use address {}: {}", address, e),
+            }
         }
-        packet.push((self.port & 0xFF) as u8);
-        packet.push((self.port >> 8 & 0xFF) as u8);
-        packet.push(query_type as u8);
-
-        if query_type == 'p' {
-            packet.push(0);
-            packet.push(0);
-            packet.push(0);
-            packet.push(0);
+
+        if targets.is_empty() {
+            return Err(Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("none of the addresses of {} can be used", addr),
+            ));
         }
 
-        let amt = self.socket.send(&packet).await.unwrap();
+        Ok(Self {
+            targets,
+            active: AtomicUsize::new(0),
+            last_request: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn connect(address: SocketAddr) -> Result<UdpSocket, std::io::Error> {
+        let socket = if address.is_ipv4() {
+            UdpSocket::bind("0.0.0.0:0").await?
+        } else {
+            UdpSocket::bind("[::]:0").await?
+        };
+        socket.connect(address).await?;
+        Ok(socket)
+    }
+
+    fn target(&self) -> &Target {
+        &self.targets[self.active.load(Ordering::Relaxed)]
+    }
+
+    pub async fn send(&self, query_type: char) -> Result<usize, std::io::Error> {
+        let target = self.target();
+        let packet = build_packet(target.address, query_type);
+        let amt = target.socket.send(&packet).await?;
+        *self.last_request.lock().unwrap() = packet;
         Ok(amt)
     }
 
-    pub async fn recv(&self) -> Result<String, std::io::Error> {
-        let mut buf = [0; 1500];
-        let amt;
-        match timeout_at(
-            Instant::now() + Duration::from_secs(2),
-            self.socket.recv(&mut buf),
-        )
-        .await?
-        {
-            Ok(n) => amt = n,
-            Err(e) => return Err(e),
-        }
+    pub async fn recv(&self) -> Result<QueryResponse, std::io::Error> {
+        self.recv_with_timeout(Duration::from_secs(2)).await
+    }
 
-        if amt == 0 {
-            return Ok(String::from("no_data"));
+    pub async fn recv_with_timeout(&self, wait: Duration) -> Result<QueryResponse, std::io::Error> {
+        let request = self.last_request.lock().unwrap().clone();
+        if request.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                "no query has been sent",
+            ));
         }
 
-        let query_type = buf[10] as char;
-        let packet = Cursor::new(buf[11..amt].to_vec());
-        if query_type == 'i' {
-            self.build_info_packet(packet)
-        } else if query_type == 'c' {
-            self.build_players_packet(packet)
-        } else if query_type == 'r' {
-            self.build_rules_packet(packet)
-        } else if query_type == 'o' {
-            self.build_extra_info_packet(packet)
-        } else if query_type == 'p' {
-            Ok(String::from("pong"))
-        } else {
-            Ok(String::from("no_data"))
+        let deadline = Instant::now() + wait;
+        let mut buf = vec![0; MAX_DATAGRAM_LEN];
+        loop {
+            let amt = timeout_at(deadline, self.target().socket.recv(&mut buf)).await??;
+
+            // late replies to an earlier query or anything else that isn't ours
+            // are dropped, we keep waiting for the right one until the deadline
+            match query_parser::validate_header(&buf[..amt], &request) {
+                Ok(_) => return Ok(query_parser::parse_response(&buf[..amt])?),
+                Err(e) => info!("[query.rs] Dropping unexpected datagram: {}", e),
+            }
         }
     }
 
-    fn build_info_packet(&self, mut packet: Cursor<Vec<u8>>) -> Result<String, std::io::Error> {
-        let mut data = InfoPacket::default();
+    // tries every address in order until one of them answers, and sticks
+    // with that one for the following queries
+    async fn request(&self, query_type: char) -> Result<QueryResponse, std::io::Error> {
+        let first = self.active.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for i in 0..self.targets.len() {
+            self.active
+                .store((first + i) % self.targets.len(), Ordering::Relaxed);
+
+            let result = match self.send(query_type).await {
+                Ok(_) => self.recv().await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                // the server answered, just not with something we could use
+                Err(e) if e.kind() == ErrorKind::InvalidData => return Err(e),
+                Err(e) => last_error = Some(e),
+            }
+        }
 
-        data.password = packet.read_i8().unwrap() != 0;
-        data.players = packet.read_u16::<LittleEndian>().unwrap();
-        data.max_players = packet.read_u16::<LittleEndian>().unwrap();
+        Err(last_error.unwrap())
+    }
 
-        let hostname_len = packet.read_u32::<LittleEndian>().unwrap();
-        let mut hostname_buf = vec![0u8; hostname_len as usize];
-        packet.read_exact(&mut hostname_buf).unwrap();
-        data.hostname = helpers::decode_buffer(hostname_buf).0;
+    pub async fn info(&self) -> Result<InfoPacket, std::io::Error> {
+        match self.request('i').await? {
+            QueryResponse::Info(info) => Ok(info),
+            _ => Err(unexpected_response('i')),
+        }
+    }
 
-        let gamemode_len = packet.read_u32::<LittleEndian>().unwrap();
-        let mut gamemode_buf = vec![0u8; gamemode_len as usize];
-        packet.read_exact(&mut gamemode_buf).unwrap();
-        data.gamemode = helpers::decode_buffer(gamemode_buf).0;
+    pub async fn players(&self) -> Result<Vec<Player>, std::io::Error> {
+        match self.request('c').await? {
+            QueryResponse::Players(players) => Ok(players),
+            _ => Err(unexpected_response('c')),
+        }
+    }
 
-        let language_len = packet.read_u32::<LittleEndian>().unwrap();
-        let mut language_buf = vec![0u8; language_len as usize];
-        packet.read_exact(&mut language_buf).unwrap();
-        data.language = helpers::decode_buffer(language_buf).0;
+    pub async fn detailed_players(&self) -> Result<Vec<DetailedPlayer>, std::io::Error> {
+        match self.request('d').await? {
+            QueryResponse::DetailedPlayers(players) => Ok(players),
+            _ => Err(unexpected_response('d')),
+        }
+    }
+
+    pub async fn player_list(&self) -> Result<PlayerList<Player>, std::io::Error> {
+        let result = self.players().await;
+        self.classify_player_list(result).await
+    }
 
-        Ok(serde_json::to_string(&data).unwrap())
+    pub async fn detailed_player_list(&self) -> Result<PlayerList<DetailedPlayer>, std::io::Error> {
+        let result = self.detailed_players().await;
+        self.classify_player_list(result).await
     }
 
-    fn build_extra_info_packet(
+    async fn classify_player_list<T>(
         &self,
-        mut packet: Cursor<Vec<u8>>,
-    ) -> Result<String, std::io::Error> {
-        let mut data = ExtraInfoPacket::default();
+        result: Result<Vec<T>, std::io::Error>,
+    ) -> Result<PlayerList<T>, std::io::Error> {
+        match result {
+            Ok(players) => Ok(PlayerList::Complete(players)),
+            Err(e) if e.kind() == ErrorKind::TimedOut => match self.info().await {
+                Ok(info) if info.players > PLAYER_LIST_LIMIT => Ok(PlayerList::Withheld {
+                    players: info.players,
+                }),
+                _ => Ok(PlayerList::TimedOut),
+            },
+            Err(e) if is_truncated(&e) => Ok(PlayerList::Truncated),
+            Err(e) => Err(e),
+        }
+    }
 
-        let discord_link_len = packet.read_u32::<LittleEndian>().unwrap();
-        let mut discord_link_buf = vec![0u8; discord_link_len as usize];
-        packet.read_exact(&mut discord_link_buf).unwrap();
-        data.discord_link = helpers::decode_buffer(discord_link_buf).0;
+    pub async fn rules(&self) -> Result<Vec<(String, String)>, std::io::Error> {
+        match self.request('r').await? {
+            QueryResponse::Rules(rules) => Ok(rules),
+            _ => Err(unexpected_response('r')),
+        }
+    }
 
-        let mut banner_url_len = packet.read_u32::<LittleEndian>().unwrap();
-        let mut banner_url_buf = vec![0u8; banner_url_len as usize];
-        packet.read_exact(&mut banner_url_buf).unwrap();
-        data.light_banner_url = helpers::decode_buffer(banner_url_buf).0;
+    pub async fn extra_info(&self) -> Result<ExtraInfoPacket, std::io::Error> {
+        match self.request('o').await? {
+            QueryResponse::ExtraInfo(extra_info) => Ok(extra_info),
+            _ => Err(unexpected_response('o')),
+        }
+    }
 
-        banner_url_len = packet.read_u32::<LittleEndian>().unwrap();
-        banner_url_buf = vec![0u8; banner_url_len as usize];
-        packet.read_exact(&mut banner_url_buf).unwrap();
-        data.dark_banner_url = helpers::decode_buffer(banner_url_buf).0;
+    pub async fn ping(&self) -> Result<Duration, std::io::Error> {
+        self.ping_with_timeout(Duration::from_secs(2)).await
+    }
 
-        Ok(serde_json::to_string(&data).unwrap())
+    pub async fn ping_with_timeout(&self, wait: Duration) -> Result<Duration, std::io::Error> {
+        let before = std::time::Instant::now();
+        self.send('p').await?;
+        match self.recv_with_timeout(wait).await? {
+            QueryResponse::Pong => Ok(before.elapsed()),
+            _ => Err(unexpected_response('p')),
+        }
     }
 
-    fn build_players_packet(&self, mut packet: Cursor<Vec<u8>>) -> Result<String, std::io::Error> {
-        let player_count = packet.read_u16::<LittleEndian>().unwrap();
-        let default_player = Player::default();
-        let mut players = vec![default_player; player_count as usize];
+    /// Runs an RCON command and collects every line of its output. Commands
+    /// that print nothing, and servers with RCON disabled, give an empty list.
+    pub async fn rcon(&self, password: &str, command: &str) -> Result<Vec<String>, std::io::Error> {
+        let target = self.target();
+        let mut packet = build_packet(target.address, 'x');
+        for field in [password, command] {
+            let len = u16::try_from(field.len())
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "rcon input is too long"))?;
+            packet.extend_from_slice(&len.to_le_bytes());
+            packet.extend_from_slice(field.as_bytes());
+        }
 
-        for i in 0..player_count {
-            let player = &mut players[i as usize];
+        target.socket.send(&packet).await?;
+        *self.last_request.lock().unwrap() = packet;
+
+        let mut lines = Vec::new();
+        loop {
+            let wait = if lines.is_empty() {
+                Duration::from_secs(2)
+            } else {
+                RCON_QUIET_PERIOD
+            };
+
+            match self.recv_with_timeout(wait).await {
+                Ok(QueryResponse::RconOutput(line)) => {
+                    if lines.is_empty() && line == RCON_INVALID_PASSWORD {
+                        return Err(Error::new(ErrorKind::PermissionDenied, line));
+                    }
+                    lines.push(line);
+                }
+                Ok(_) => return Err(unexpected_response('x')),
+                Err(e) if e.kind() == ErrorKind::TimedOut => return Ok(lines),
+                Err(e) => return Err(e),
+            }
+        }
+    }
 
-            let player_name_len = packet.read_u8().unwrap();
-            let mut player_name_buf = vec![0u8; player_name_len as usize];
-            packet.read_exact(&mut player_name_buf).unwrap();
-            player.name = helpers::decode_buffer(player_name_buf).0;
+    /// Sends `probes` pings one after another, `spacing` apart, and gives each
+    /// of them `probe_timeout` to come back before it's counted as lost.
+    pub async fn ping_stats(
+        &self,
+        probes: u32,
+        spacing: Duration,
+        probe_timeout: Duration,
+    ) -> PingStats {
+        let mut samples = Vec::new();
+        for i in 0..probes {
+            if i > 0 {
+                tokio::time::sleep(spacing).await;
+            }
 
-            player.score = packet.read_i32::<LittleEndian>().unwrap();
+            if let Ok(rtt) = self.ping_with_timeout(probe_timeout).await {
+                samples.push(rtt);
+            }
         }
+        PingStats::from_samples(probes, &samples)
+    }
+}
+
+pub fn build_packet(address: SocketAddr, query_type: char) -> Vec<u8> {
+    // the header only has room for IPv4, servers just echo it back anyway
+    let ip = match address.ip() {
+        IpAddr::V4(ip) => ip.octets(),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or([0; 4], |ip| ip.octets()),
+    };
+
+    let mut packet: Vec<u8> = Vec::new();
+    packet.append(&mut "SAMP".to_owned().into_bytes());
+    packet.extend_from_slice(&ip);
+    packet.extend_from_slice(&address.port().to_le_bytes());
+    packet.push(query_type as u8);
+
+    if query_type == 'p' {
+        // random nonce the server has to echo back, so a late pong can't be
+        // mistaken for the answer to this ping
+        packet.extend_from_slice(&rand::random::<[u8; 4]>());
+    }
 
-        Ok(serde_json::to_string(&players).unwrap())
+    packet
+}
+
+fn is_truncated(e: &std::io::Error) -> bool {
+    matches!(
+        e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<query_parser::ParseError>()),
+        Some(query_parser::ParseError::Truncated { .. })
+    )
+}
+
+fn unexpected_response(query_type: char) -> std::io::Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected response to '{}' query", query_type),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{self, Behaviour, MockConfig, MockServer};
+    use std::collections::HashMap;
+
+    #[test]
+    fn ping_stats_from_samples() {
+        let samples = [40, 60, 50, 70].map(Duration::from_millis);
+        let stats = PingStats::from_samples(5, &samples);
+        assert_eq!(stats.received, 4);
+        assert_eq!(stats.loss, 20.0);
+        assert_eq!(stats.min, Some(40));
+        assert_eq!(stats.max, Some(70));
+        assert_eq!(stats.median, Some(55));
+        assert_eq!(stats.jitter, Some(50.0 / 3.0));
+    }
+
+    #[test]
+    fn ping_stats_when_everything_is_lost() {
+        let stats = PingStats::from_samples(3, &[]);
+        assert_eq!(stats.loss, 100.0);
+        assert_eq!(stats.median, None);
+        assert_eq!(stats.jitter, None);
+    }
+
+    async fn query(server: &MockServer) -> Query {
+        Query::new(&Resolver::new(), "127.0.0.1", server.port())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn queries_against_mock_server() {
+        let server = MockServer::start(MockConfig::default()).await;
+        let q = query(&server).await;
+
+        let info = q.info().await.unwrap();
+        assert_eq!(info.hostname, "open.mp mock server");
+        assert_eq!(info.players, 2);
+
+        let players = q.players().await.unwrap();
+        assert_eq!(players[1].name, "Player2");
+        assert_eq!(players[1].score, -2);
+
+        let players = q.detailed_players().await.unwrap();
+        assert_eq!(players[1].id, 3);
+        assert_eq!(players[1].ping, 120);
+
+        let rules = q.rules().await.unwrap();
+        assert_eq!(rules[0], ("version".to_string(), "omp 1.0.0".to_string()));
+
+        let extra_info = q.extra_info().await.unwrap();
+        assert_eq!(extra_info.discord_link, "https://discord.gg/samp");
+    }
+
+    #[tokio::test]
+    async fn rcon_against_mock_server() {
+        let server = MockServer::start(MockConfig {
+            rcon_output: vec!["first".to_string(), "second".to_string()],
+            ..Default::default()
+        })
+        .await;
+        let q = query(&server).await;
+
+        let lines = q.rcon("changeme", "varlist").await.unwrap();
+        assert_eq!(lines, vec!["first", "second"]);
+
+        let e = q.rcon("wrong", "varlist").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
     }
 
-    fn build_rules_packet(&self, mut packet: Cursor<Vec<u8>>) -> Result<String, std::io::Error> {
-        let rule_count = packet.read_u16::<LittleEndian>().unwrap();
-        let mut rules: Vec<Vec<String>> = Vec::new();
+    #[tokio::test]
+    async fn cp1251_strings_are_decoded() {
+        let mut config = MockConfig {
+            encode: mock_server::cp1251,
+            ..Default::default()
+        };
+        config.info.hostname = "Русский сервер".to_string();
+        let server = MockServer::start(config).await;
+        let q = query(&server).await;
 
-        for _ in 0..rule_count {
-            let mut rule: Vec<String> = Vec::new();
+        let info = q.info().await.unwrap();
+        assert_eq!(info.hostname, "Русский сервер");
+    }
 
-            let rule_name_len = packet.read_u8().unwrap();
-            let mut rule_name_buf = vec![0u8; rule_name_len as usize];
-            packet.read_exact(&mut rule_name_buf).unwrap();
-            rule.push(helpers::decode_buffer(rule_name_buf).0);
+    #[tokio::test]
+    async fn big_servers_withhold_player_list() {
+        let mut config = MockConfig::default();
+        config.info.players = 150;
+        let server = MockServer::start(config).await;
+        let q = query(&server).await;
 
-            let rule_value_len = packet.read_u8().unwrap();
-            let mut rule_value_buf = vec![0u8; rule_value_len as usize];
-            packet.read_exact(&mut rule_value_buf).unwrap();
-            rule.push(helpers::decode_buffer(rule_value_buf).0);
+        let list = q.player_list().await.unwrap();
+        assert!(matches!(list, PlayerList::Withheld { players: 150 }));
+    }
 
-            rules.push(rule);
+    #[tokio::test]
+    async fn truncated_player_list() {
+        let server = MockServer::start(MockConfig {
+            behaviours: HashMap::from([('d', Behaviour::Truncate)]),
+            ..Default::default()
+        })
+        .await;
+        let q = query(&server).await;
+
+        let list = q.detailed_player_list().await.unwrap();
+        assert!(matches!(list, PlayerList::Truncated));
+    }
+
+    #[tokio::test]
+    async fn foreign_replies_are_ignored() {
+        for behaviour in [Behaviour::BadMagic, Behaviour::WrongOpcode] {
+            let server = MockServer::start(MockConfig {
+                behaviours: HashMap::from([('i', behaviour)]),
+                ..Default::default()
+            })
+            .await;
+            let q = query(&server).await;
+
+            q.send('i').await.unwrap();
+            let result = q.recv_with_timeout(Duration::from_millis(300)).await;
+            assert!(matches!(result, Err(e) if e.kind() == ErrorKind::TimedOut));
         }
+    }
 
-        Ok(serde_json::to_string(&rules).unwrap())
+    #[tokio::test]
+    async fn ping_stats_count_lost_probes() {
+        let server = MockServer::start(MockConfig {
+            delay: Duration::from_millis(20),
+            drop_every: Some(2),
+            ..Default::default()
+        })
+        .await;
+        let q = query(&server).await;
+
+        let stats = q
+            .ping_stats(4, Duration::from_millis(10), Duration::from_millis(300))
+            .await;
+        assert_eq!(server.requests(), 4);
+        assert_eq!(stats.received, 2);
+        assert_eq!(stats.loss, 50.0);
+        assert!(stats.min.unwrap() >= 20);
     }
 }
@@ -0,0 +1,256 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::query::PlayerList;
+
+// first failure keeps a server quiet for 10 seconds, every following one
+// doubles that up to 10 minutes
+const BASE_DELAY: Duration = Duration::from_secs(10);
+const MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+// delays are spread by up to this much in both directions, so servers that
+// went down together don't all get retried in the same refresh
+const JITTER: f64 = 0.25;
+
+pub const BACKED_OFF: &str = "backed_off";
+
+#[derive(Default)]
+struct Endpoint {
+    failures: u32,
+    retry_at: Option<Instant>,
+    last_success: Option<SystemTime>,
+}
+
+/// How every server has been answering, so ones that stopped get left alone
+/// for a while. Clones share the same state.
+#[derive(Clone)]
+pub struct QueryBackoff {
+    // keyed by lowercase host and port, like the query cache
+    endpoints: Arc<Mutex<HashMap<(String, i32), Endpoint>>>,
+}
+
+fn key(host: &str, port: i32) -> (String, i32) {
+    (host.trim().to_lowercase(), port)
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Health {
+    pub failures: u32,
+    // unix time in milliseconds
+    pub last_success: Option<u64>,
+    // milliseconds until the server gets queried again
+    pub retry_in: Option<u64>,
+}
+
+pub fn delay(failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+
+    let exponent = (failures - 1).min(16);
+    let delay = BASE_DELAY.saturating_mul(1 << exponent).min(MAX_DELAY);
+    let spread = 1.0 + JITTER * (rand::random::<f64>() * 2.0 - 1.0);
+    delay.mul_f64(spread)
+}
+
+impl QueryBackoff {
+    pub fn new() -> Self {
+        Self {
+            endpoints: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Fails with `BACKED_OFF` while the server is still waiting out its delay.
+    pub fn check(&self, host: &str, port: i32) -> Result<(), Error> {
+        let endpoints = self.endpoints.lock().unwrap();
+        match endpoints.get(&key(host, port)).and_then(|e| e.retry_at) {
+            Some(retry_at) if retry_at > Instant::now() => {
+                Err(Error::new(ErrorKind::WouldBlock, BACKED_OFF))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records whether the server answered. Any answer resets its backoff.
+    pub fn report(&self, host: &str, port: i32, answered: bool) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = endpoints.entry(key(host, port)).or_default();
+
+        if answered {
+            endpoint.failures = 0;
+            endpoint.retry_at = None;
+            endpoint.last_success = Some(SystemTime::now());
+        } else {
+            endpoint.failures += 1;
+            endpoint.retry_at = Some(Instant::now() + delay(endpoint.failures));
+        }
+    }
+
+    /// Same as `report`, but tells from the query result. Replies we couldn't
+    /// parse still mean the server is up, and backed off queries don't count.
+    pub fn record<T>(&self, host: &str, port: i32, result: &Result<T, Error>) {
+        match result {
+            Ok(_) => self.report(host, port, true),
+            Err(e) if e.kind() == ErrorKind::InvalidData => self.report(host, port, true),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => self.report(host, port, false),
+        }
+    }
+
+    /// Runs `fetch` unless the server is backed off, and records how it went.
+    pub async fn guard<T, F, Fut>(&self, host: &str, port: i32, fetch: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.check(host, port)?;
+        let result = fetch().await;
+        self.record(host, port, &result);
+        result
+    }
+
+    /// Same as `guard`, for player lists. One that timed out comes back as
+    /// `Ok(PlayerList::TimedOut)`, but the server still didn't answer.
+    pub async fn guard_player_list<T, F, Fut>(
+        &self,
+        host: &str,
+        port: i32,
+        fetch: F,
+    ) -> Result<PlayerList<T>, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<PlayerList<T>, Error>>,
+    {
+        self.check(host, port)?;
+        let result = fetch().await;
+        match &result {
+            Ok(PlayerList::TimedOut) => self.report(host, port, false),
+            _ => self.record(host, port, &result),
+        }
+        result
+    }
+
+    /// Lets the next query through no matter how many times the server failed.
+    /// The failure count is kept, so another failure backs off even longer.
+    pub fn retry_now(&self, host: &str, port: i32) {
+        if let Some(endpoint) = self.endpoints.lock().unwrap().get_mut(&key(host, port)) {
+            endpoint.retry_at = None;
+        }
+    }
+
+    pub fn health(&self, host: &str, port: i32) -> Health {
+        let endpoints = self.endpoints.lock().unwrap();
+        let endpoint = match endpoints.get(&key(host, port)) {
+            Some(e) => e,
+            None => {
+                return Health {
+                    failures: 0,
+                    last_success: None,
+                    retry_in: None,
+                }
+            }
+        };
+
+        let now = Instant::now();
+        Health {
+            failures: endpoint.failures,
+            last_success: endpoint.last_success.and_then(|t| {
+                t.duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_millis() as u64)
+            }),
+            retry_in: endpoint
+                .retry_at
+                .filter(|t| *t > now)
+                .map(|t| (t - now).as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{Behaviour, MockConfig, MockServer};
+    use crate::query::Query;
+    use crate::resolver::Resolver;
+
+    #[test]
+    fn delay_grows_and_caps() {
+        assert_eq!(delay(0), Duration::ZERO);
+
+        let first = delay(1);
+        assert!(first >= BASE_DELAY.mul_f64(1.0 - JITTER));
+        assert!(first <= BASE_DELAY.mul_f64(1.0 + JITTER));
+
+        let third = delay(3);
+        assert!(third >= (BASE_DELAY * 4).mul_f64(1.0 - JITTER));
+
+        assert!(delay(100) <= MAX_DELAY.mul_f64(1.0 + JITTER));
+    }
+
+    #[test]
+    fn failures_back_off_until_retried() {
+        let backoff = QueryBackoff::new();
+        let host = "backoff.test";
+        assert!(backoff.check(host, 7777).is_ok());
+
+        backoff.report(host, 7777, false);
+        let e = backoff.check(host, 7777).unwrap_err();
+        assert_eq!(e.to_string(), BACKED_OFF);
+        assert_eq!(backoff.health(host, 7777).failures, 1);
+        assert!(backoff.health(host, 7777).retry_in.is_some());
+        // other ports of the same host are separate servers
+        assert!(backoff.check(host, 7778).is_ok());
+
+        backoff.retry_now(host, 7777);
+        assert!(backoff.check(host, 7777).is_ok());
+        assert_eq!(backoff.health(host, 7777).failures, 1);
+
+        backoff.report(host, 7777, true);
+        let health = backoff.health(host, 7777);
+        assert_eq!(health.failures, 0);
+        assert!(health.last_success.is_some());
+    }
+
+    #[test]
+    fn unparsable_replies_count_as_answers() {
+        let backoff = QueryBackoff::new();
+        let host = "backoff.test";
+        backoff.record::<()>(host, 7777, &Err(Error::new(ErrorKind::TimedOut, "")));
+        assert_eq!(backoff.health(host, 7777).failures, 1);
+
+        backoff.retry_now(host, 7777);
+        backoff.record::<()>(host, 7777, &Err(Error::new(ErrorKind::InvalidData, "")));
+        assert_eq!(backoff.health(host, 7777).failures, 0);
+    }
+
+    #[tokio::test]
+    async fn timed_out_player_lists_back_off() {
+        let server = MockServer::start(MockConfig {
+            behaviours: HashMap::from([('i', Behaviour::Drop), ('c', Behaviour::Drop)]),
+            ..Default::default()
+        })
+        .await;
+        let port = server.port();
+        let backoff = QueryBackoff::new();
+        let resolver = Resolver::new();
+
+        let list = backoff
+            .guard_player_list("127.0.0.1", port, || async {
+                Query::new(&resolver, "127.0.0.1", port)
+                    .await?
+                    .player_list()
+                    .await
+            })
+            .await;
+        assert!(matches!(list, Ok(PlayerList::TimedOut)));
+
+        let health = backoff.health("127.0.0.1", port);
+        assert_eq!(health.failures, 1);
+        assert!(health.last_success.is_none());
+        assert!(backoff.check("127.0.0.1", port).is_err());
+    }
+}
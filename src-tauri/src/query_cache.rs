@@ -0,0 +1,172 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::query::QueryResponse;
+
+// endpoint as it was given to us, plus the opcode
+type CacheKey = (String, i32, char);
+
+struct Entry {
+    value: serde_json::Value,
+    fetched: Instant,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Cached<T> {
+    pub data: T,
+    // false when this is the same data the previous fetch returned
+    pub changed: bool,
+}
+
+/// Keeps the last response of every query type for every server, so repeated
+/// requests within a short time don't hit the network again.
+pub struct QueryCache {
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+}
+
+/// How long a response to `opcode` stays fresh. Rules and open.mp extra info
+/// hardly ever change, player counts and pings do all the time.
+pub fn ttl(opcode: char) -> Duration {
+    match opcode {
+        'r' | 'o' => Duration::from_secs(5 * 60),
+        _ => Duration::from_secs(5),
+    }
+}
+
+impl QueryCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(host: &str, port: i32, opcode: char) -> CacheKey {
+        (host.trim().to_lowercase(), port, opcode)
+    }
+
+    /// Returns the cached response if it's still fresh, otherwise runs `fetch`
+    /// and stores what it returns. Errors are never cached.
+    pub async fn get_or_fetch<T, F, Fut>(
+        &self,
+        host: &str,
+        port: i32,
+        opcode: char,
+        fetch: F,
+    ) -> Result<Cached<T>, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let key = Self::key(host, port, opcode);
+        let fresh = match self.entries.lock().unwrap().get(&key) {
+            Some(entry) if entry.fetched.elapsed() < ttl(opcode) => Some(entry.value.clone()),
+            _ => None,
+        };
+
+        if let Some(value) = fresh {
+            return Ok(Cached {
+                data: serde_json::from_value(value)?,
+                changed: false,
+            });
+        }
+
+        let data = fetch().await?;
+        let changed = self.store(host, port, opcode, &data)?;
+        Ok(Cached { data, changed })
+    }
+
+    /// Saves a response that was fetched elsewhere, like in a batch, and tells
+    /// whether it differs from the one stored before.
+    pub fn store<T: Serialize>(
+        &self,
+        host: &str,
+        port: i32,
+        opcode: char,
+        data: &T,
+    ) -> Result<bool, Error> {
+        let value = serde_json::to_value(data)?;
+        let mut entries = self.entries.lock().unwrap();
+        let previous = entries.insert(
+            Self::key(host, port, opcode),
+            Entry {
+                value: value.clone(),
+                fetched: Instant::now(),
+            },
+        );
+        Ok(previous.is_none_or(|entry| entry.value != value))
+    }
+
+    /// Same as `store` for responses coming from the query pool. They're stored
+    /// in the same shape the single query commands use, so either path can tell
+    /// if the other one saw the same data. Anything else always counts as changed.
+    pub fn store_response(
+        &self,
+        host: &str,
+        port: i32,
+        opcode: char,
+        response: &QueryResponse,
+    ) -> Result<bool, Error> {
+        match response {
+            QueryResponse::Info(info) => self.store(host, port, opcode, info),
+            QueryResponse::Rules(rules) => self.store(host, port, opcode, rules),
+            QueryResponse::ExtraInfo(extra_info) => self.store(host, port, opcode, extra_info),
+            _ => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_fresh_entries_from_cache() {
+        let cache = QueryCache::new();
+
+        let first = cache
+            .get_or_fetch("Example.com", 7777, 'r', || async { Ok(1) })
+            .await
+            .unwrap();
+        assert!(first.changed);
+
+        let second = cache
+            .get_or_fetch("example.com", 7777, 'r', || async { Ok(2) })
+            .await
+            .unwrap();
+        assert_eq!(second.data, 1);
+        assert!(!second.changed);
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let cache = QueryCache::new();
+
+        let result = cache
+            .get_or_fetch::<i32, _, _>("example.com", 7777, 'i', || async {
+                Err(Error::other("timed out"))
+            })
+            .await;
+        assert!(result.is_err());
+
+        let result = cache
+            .get_or_fetch("example.com", 7777, 'i', || async { Ok(1) })
+            .await
+            .unwrap();
+        assert!(result.changed);
+    }
+
+    #[test]
+    fn store_detects_changes() {
+        let cache = QueryCache::new();
+        assert!(cache.store("example.com", 7777, 'i', &"a").unwrap());
+        assert!(!cache.store("example.com", 7777, 'i', &"a").unwrap());
+        assert!(cache.store("example.com", 7777, 'i', &"b").unwrap());
+        assert!(cache.store("example.com", 7778, 'i', &"b").unwrap());
+    }
+}
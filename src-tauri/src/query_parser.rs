@@ -0,0 +1,624 @@
+use std::fmt;
+
+use crate::helpers;
+use crate::query::{DetailedPlayer, ExtraInfoPacket, InfoPacket, Player, QueryResponse};
+
+// "SAMP" + 4 bytes of ip + 2 bytes of port + 1 byte of opcode
+pub const HEADER_LEN: usize = 11;
+pub const MAGIC: &[u8; 4] = b"SAMP";
+
+// hostname, gamemode, language and omp urls are sent with a u32 length prefix,
+// nothing legit comes close to this, so anything above it is treated as garbage
+pub const MAX_STRING_LEN: usize = 1024;
+
+// smallest possible entries, used to reject counts that can't fit in the datagram
+// before allocating anything for them
+const MIN_PLAYER_LEN: usize = 1 + 4;
+const MIN_DETAILED_PLAYER_LEN: usize = 1 + 1 + 4 + 4;
+const MIN_RULE_LEN: usize = 1 + 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Truncated {
+        field: &'static str,
+    },
+    LengthOverflow {
+        field: &'static str,
+        len: usize,
+        max: usize,
+    },
+    UnknownOpcode(u8),
+    BadMagic,
+    AddressMismatch,
+    OpcodeMismatch {
+        expected: u8,
+        got: u8,
+    },
+    NonceMismatch,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated { field } => {
+                write!(f, "response truncated while reading {}", field)
+            }
+            ParseError::LengthOverflow { field, len, max } => write!(
+                f,
+                "length of {} is {} which exceeds the limit of {}",
+                field, len, max
+            ),
+            ParseError::UnknownOpcode(opcode) => {
+                write!(f, "unknown opcode 0x{:02x}", opcode)
+            }
+            ParseError::BadMagic => write!(f, "response does not start with \"SAMP\""),
+            ParseError::AddressMismatch => {
+                write!(f, "response echoes a different address than requested")
+            }
+            ParseError::OpcodeMismatch { expected, got } => write!(
+                f,
+                "expected a response to '{}' but got '{}'",
+                *expected as char, *got as char
+            ),
+            ParseError::NonceMismatch => write!(f, "ping response has a stale nonce"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(e: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize, field: &'static str) -> Result<&'a [u8], ParseError> {
+        if len > self.remaining() {
+            return Err(ParseError::Truncated { field });
+        }
+        let out = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(out)
+    }
+
+    fn u8(&mut self, field: &'static str) -> Result<u8, ParseError> {
+        Ok(self.bytes(1, field)?[0])
+    }
+
+    fn u16(&mut self, field: &'static str) -> Result<u16, ParseError> {
+        let b = self.bytes(2, field)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self, field: &'static str) -> Result<u32, ParseError> {
+        let b = self.bytes(4, field)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self, field: &'static str) -> Result<i32, ParseError> {
+        Ok(self.u32(field)? as i32)
+    }
+
+    // u32 length prefixed string, limited to MAX_STRING_LEN
+    fn long_string(&mut self, field: &'static str) -> Result<String, ParseError> {
+        let len = self.u32(field)? as usize;
+        if len > MAX_STRING_LEN {
+            return Err(ParseError::LengthOverflow {
+                field,
+                len,
+                max: MAX_STRING_LEN,
+            });
+        }
+        let buf = self.bytes(len, field)?;
+        Ok(helpers::decode_buffer(buf.to_vec()).0)
+    }
+
+    // u8 length prefixed string, can't be longer than 255 bytes by design
+    fn short_string(&mut self, field: &'static str) -> Result<String, ParseError> {
+        let len = self.u8(field)? as usize;
+        let buf = self.bytes(len, field)?;
+        Ok(helpers::decode_buffer(buf.to_vec()).0)
+    }
+
+    // u16 length prefixed string, only used by rcon output
+    fn medium_string(&mut self, field: &'static str) -> Result<String, ParseError> {
+        let len = self.u16(field)? as usize;
+        let buf = self.bytes(len, field)?;
+        Ok(helpers::decode_buffer(buf.to_vec()).0)
+    }
+
+    fn count(&mut self, field: &'static str, min_entry_len: usize) -> Result<usize, ParseError> {
+        let count = self.u16(field)? as usize;
+        if count * min_entry_len > self.remaining() {
+            return Err(ParseError::Truncated { field });
+        }
+        Ok(count)
+    }
+}
+
+/// Parses a whole query datagram, header included.
+pub fn parse_response(buf: &[u8]) -> Result<QueryResponse, ParseError> {
+    if buf.len() < HEADER_LEN {
+        if buf.len() >= MAGIC.len() && &buf[..MAGIC.len()] != MAGIC {
+            return Err(ParseError::BadMagic);
+        }
+        return Err(ParseError::Truncated { field: "header" });
+    }
+
+    if &buf[..MAGIC.len()] != MAGIC {
+        return Err(ParseError::BadMagic);
+    }
+
+    parse_payload(buf[10], &buf[HEADER_LEN..])
+}
+
+/// Checks that `reply` is an answer to `request`: servers echo the whole request
+/// header back, and ping replies also echo the 4 byte nonce.
+pub fn validate_header(reply: &[u8], request: &[u8]) -> Result<(), ParseError> {
+    if reply.len() < HEADER_LEN {
+        return Err(ParseError::Truncated { field: "header" });
+    }
+
+    if &reply[..MAGIC.len()] != MAGIC {
+        return Err(ParseError::BadMagic);
+    }
+
+    if reply[MAGIC.len()..10] != request[MAGIC.len()..10] {
+        return Err(ParseError::AddressMismatch);
+    }
+
+    if reply[10] != request[10] {
+        return Err(ParseError::OpcodeMismatch {
+            expected: request[10],
+            got: reply[10],
+        });
+    }
+
+    if request[10] == b'p' {
+        let nonce = &request[HEADER_LEN..];
+        if reply.len() < HEADER_LEN + nonce.len() {
+            return Err(ParseError::Truncated { field: "nonce" });
+        }
+        if &reply[HEADER_LEN..HEADER_LEN + nonce.len()] != nonce {
+            return Err(ParseError::NonceMismatch);
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates `reply` against `request` and parses it.
+pub fn parse_reply(reply: &[u8], request: &[u8]) -> Result<QueryResponse, ParseError> {
+    validate_header(reply, request)?;
+    parse_response(reply)
+}
+
+/// Parses the part of a datagram that comes after the header.
+pub fn parse_payload(opcode: u8, payload: &[u8]) -> Result<QueryResponse, ParseError> {
+    let mut reader = Reader::new(payload);
+    match opcode {
+        b'i' => Ok(QueryResponse::Info(parse_info(&mut reader)?)),
+        b'c' => Ok(QueryResponse::Players(parse_players(&mut reader)?)),
+        b'd' => Ok(QueryResponse::DetailedPlayers(parse_detailed_players(
+            &mut reader,
+        )?)),
+        b'r' => Ok(QueryResponse::Rules(parse_rules(&mut reader)?)),
+        b'o' => Ok(QueryResponse::ExtraInfo(parse_extra_info(&mut reader)?)),
+        b'p' => Ok(QueryResponse::Pong),
+        b'x' => Ok(QueryResponse::RconOutput(
+            reader.medium_string("rcon_output")?,
+        )),
+        _ => Err(ParseError::UnknownOpcode(opcode)),
+    }
+}
+
+fn parse_info(reader: &mut Reader) -> Result<InfoPacket, ParseError> {
+    Ok(InfoPacket {
+        password: reader.u8("password")? != 0,
+        players: reader.u16("players")?,
+        max_players: reader.u16("max_players")?,
+        hostname: reader.long_string("hostname")?,
+        gamemode: reader.long_string("gamemode")?,
+        language: reader.long_string("language")?,
+    })
+}
+
+fn parse_extra_info(reader: &mut Reader) -> Result<ExtraInfoPacket, ParseError> {
+    Ok(ExtraInfoPacket {
+        discord_link: reader.long_string("discord_link")?,
+        light_banner_url: reader.long_string("light_banner_url")?,
+        dark_banner_url: reader.long_string("dark_banner_url")?,
+    })
+}
+
+fn parse_players(reader: &mut Reader) -> Result<Vec<Player>, ParseError> {
+    let count = reader.count("player_count", MIN_PLAYER_LEN)?;
+    let mut players = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        players.push(Player {
+            name: reader.short_string("player_name")?,
+            score: reader.i32("player_score")?,
+        });
+    }
+
+    Ok(players)
+}
+
+fn parse_detailed_players(reader: &mut Reader) -> Result<Vec<DetailedPlayer>, ParseError> {
+    let count = reader.count("player_count", MIN_DETAILED_PLAYER_LEN)?;
+    let mut players = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        players.push(DetailedPlayer {
+            id: reader.u8("player_id")?,
+            name: reader.short_string("player_name")?,
+            score: reader.i32("player_score")?,
+            ping: reader.u32("player_ping")?,
+        });
+    }
+
+    Ok(players)
+}
+
+fn parse_rules(reader: &mut Reader) -> Result<Vec<(String, String)>, ParseError> {
+    let count = reader.count("rule_count", MIN_RULE_LEN)?;
+    let mut rules = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let name = reader.short_string("rule_name")?;
+        let value = reader.short_string("rule_value")?;
+        rules.push((name, value));
+    }
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(opcode: u8) -> Vec<u8> {
+        let mut packet = MAGIC.to_vec();
+        packet.extend_from_slice(&[127, 0, 0, 1, 0x61, 0x1e, opcode]);
+        packet
+    }
+
+    fn long_str(packet: &mut Vec<u8>, s: &str) {
+        packet.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        packet.extend_from_slice(s.as_bytes());
+    }
+
+    fn short_str(packet: &mut Vec<u8>, s: &str) {
+        packet.push(s.len() as u8);
+        packet.extend_from_slice(s.as_bytes());
+    }
+
+    fn info_packet() -> Vec<u8> {
+        let mut packet = header(b'i');
+        packet.push(1);
+        packet.extend_from_slice(&12u16.to_le_bytes());
+        packet.extend_from_slice(&50u16.to_le_bytes());
+        long_str(&mut packet, "open.mp server");
+        long_str(&mut packet, "freeroam");
+        long_str(&mut packet, "English");
+        packet
+    }
+
+    fn players_packet() -> Vec<u8> {
+        let mut packet = header(b'c');
+        packet.extend_from_slice(&2u16.to_le_bytes());
+        short_str(&mut packet, "Player1");
+        packet.extend_from_slice(&10i32.to_le_bytes());
+        short_str(&mut packet, "Player2");
+        packet.extend_from_slice(&(-5i32).to_le_bytes());
+        packet
+    }
+
+    fn detailed_players_packet() -> Vec<u8> {
+        let mut packet = header(b'd');
+        packet.extend_from_slice(&1u16.to_le_bytes());
+        packet.push(7);
+        short_str(&mut packet, "Player1");
+        packet.extend_from_slice(&10i32.to_le_bytes());
+        packet.extend_from_slice(&85u32.to_le_bytes());
+        packet
+    }
+
+    fn rules_packet() -> Vec<u8> {
+        let mut packet = header(b'r');
+        packet.extend_from_slice(&2u16.to_le_bytes());
+        short_str(&mut packet, "version");
+        short_str(&mut packet, "omp 1.0.0");
+        short_str(&mut packet, "weburl");
+        short_str(&mut packet, "open.mp");
+        packet
+    }
+
+    fn extra_info_packet() -> Vec<u8> {
+        let mut packet = header(b'o');
+        long_str(&mut packet, "https://discord.gg/samp");
+        long_str(&mut packet, "https://example.com/light.png");
+        long_str(&mut packet, "");
+        packet
+    }
+
+    #[test]
+    fn parses_info() {
+        match parse_response(&info_packet()) {
+            Ok(QueryResponse::Info(info)) => {
+                assert!(info.password);
+                assert_eq!(info.players, 12);
+                assert_eq!(info.max_players, 50);
+                assert_eq!(info.hostname, "open.mp server");
+                assert_eq!(info.gamemode, "freeroam");
+                assert_eq!(info.language, "English");
+            }
+            _ => panic!("expected info"),
+        }
+    }
+
+    #[test]
+    fn parses_players() {
+        match parse_response(&players_packet()) {
+            Ok(QueryResponse::Players(players)) => {
+                assert_eq!(players.len(), 2);
+                assert_eq!(players[0].name, "Player1");
+                assert_eq!(players[0].score, 10);
+                assert_eq!(players[1].score, -5);
+            }
+            _ => panic!("expected players"),
+        }
+    }
+
+    #[test]
+    fn parses_detailed_players() {
+        match parse_response(&detailed_players_packet()) {
+            Ok(QueryResponse::DetailedPlayers(players)) => {
+                assert_eq!(players.len(), 1);
+                assert_eq!(players[0].id, 7);
+                assert_eq!(players[0].name, "Player1");
+                assert_eq!(players[0].score, 10);
+                assert_eq!(players[0].ping, 85);
+            }
+            _ => panic!("expected detailed players"),
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        match parse_response(&rules_packet()) {
+            Ok(QueryResponse::Rules(rules)) => {
+                assert_eq!(rules[0], ("version".to_string(), "omp 1.0.0".to_string()));
+                assert_eq!(rules[1], ("weburl".to_string(), "open.mp".to_string()));
+            }
+            _ => panic!("expected rules"),
+        }
+    }
+
+    #[test]
+    fn parses_extra_info() {
+        match parse_response(&extra_info_packet()) {
+            Ok(QueryResponse::ExtraInfo(extra)) => {
+                assert_eq!(extra.discord_link, "https://discord.gg/samp");
+                assert_eq!(extra.light_banner_url, "https://example.com/light.png");
+                assert_eq!(extra.dark_banner_url, "");
+            }
+            _ => panic!("expected extra info"),
+        }
+    }
+
+    #[test]
+    fn parses_pong() {
+        let mut packet = header(b'p');
+        packet.extend_from_slice(&[1, 2, 3, 4]);
+        assert!(matches!(parse_response(&packet), Ok(QueryResponse::Pong)));
+    }
+
+    #[test]
+    fn parses_rcon_output() {
+        let mut packet = header(b'x');
+        packet.extend_from_slice(&11u16.to_le_bytes());
+        packet.extend_from_slice(b"hostname = ");
+        assert!(matches!(
+            parse_response(&packet),
+            Ok(QueryResponse::RconOutput(line)) if line == "hostname = "
+        ));
+
+        packet.truncate(packet.len() - 1);
+        assert_eq!(
+            parse_response(&packet).err(),
+            Some(ParseError::Truncated {
+                field: "rcon_output"
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_empty_datagram() {
+        assert_eq!(
+            parse_response(&[]).err(),
+            Some(ParseError::Truncated { field: "header" })
+        );
+    }
+
+    #[test]
+    fn rejects_short_header() {
+        assert_eq!(
+            parse_response(b"SAMP\x7f\x00").err(),
+            Some(ParseError::Truncated { field: "header" })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut packet = info_packet();
+        packet[0] = b'X';
+        assert_eq!(parse_response(&packet).err(), Some(ParseError::BadMagic));
+        assert_eq!(
+            parse_response(b"XAMP\x7f").err(),
+            Some(ParseError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_opcode() {
+        assert_eq!(
+            parse_response(&header(b'z')).err(),
+            Some(ParseError::UnknownOpcode(b'z'))
+        );
+    }
+
+    #[test]
+    fn rejects_every_truncation_of_valid_packets() {
+        for packet in [
+            info_packet(),
+            players_packet(),
+            detailed_players_packet(),
+            rules_packet(),
+            extra_info_packet(),
+        ] {
+            for len in 0..packet.len() {
+                assert!(
+                    parse_response(&packet[..len]).is_err(),
+                    "prefix of length {} parsed",
+                    len
+                );
+            }
+            assert!(parse_response(&packet).is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_huge_hostname_length() {
+        let mut packet = header(b'i');
+        packet.push(0);
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            parse_response(&packet).err(),
+            Some(ParseError::LengthOverflow {
+                field: "hostname",
+                len: u32::MAX as usize,
+                max: MAX_STRING_LEN,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_hostname_longer_than_datagram() {
+        let mut packet = header(b'i');
+        packet.push(0);
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(&100u32.to_le_bytes());
+        packet.extend_from_slice(b"short");
+        assert_eq!(
+            parse_response(&packet).err(),
+            Some(ParseError::Truncated { field: "hostname" })
+        );
+    }
+
+    #[test]
+    fn rejects_player_count_larger_than_datagram() {
+        let mut packet = header(b'c');
+        packet.extend_from_slice(&u16::MAX.to_le_bytes());
+        short_str(&mut packet, "Player1");
+        packet.extend_from_slice(&0i32.to_le_bytes());
+        assert_eq!(
+            parse_response(&packet).err(),
+            Some(ParseError::Truncated {
+                field: "player_count"
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_rule_value_past_end() {
+        let mut packet = header(b'r');
+        packet.extend_from_slice(&1u16.to_le_bytes());
+        short_str(&mut packet, "version");
+        packet.push(200);
+        packet.extend_from_slice(b"omp");
+        assert_eq!(
+            parse_response(&packet).err(),
+            Some(ParseError::Truncated {
+                field: "rule_value"
+            })
+        );
+    }
+
+    #[test]
+    fn accepts_matching_header() {
+        let request = header(b'i');
+        assert!(parse_reply(&info_packet(), &request).is_ok());
+    }
+
+    #[test]
+    fn rejects_reply_for_other_address() {
+        let mut request = header(b'i');
+        request[7] = 2;
+        assert_eq!(
+            validate_header(&info_packet(), &request).err(),
+            Some(ParseError::AddressMismatch)
+        );
+
+        let mut request = header(b'i');
+        request[8] = 0;
+        assert_eq!(
+            validate_header(&info_packet(), &request).err(),
+            Some(ParseError::AddressMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_reply_for_other_opcode() {
+        assert_eq!(
+            validate_header(&info_packet(), &header(b'r')).err(),
+            Some(ParseError::OpcodeMismatch {
+                expected: b'r',
+                got: b'i'
+            })
+        );
+    }
+
+    #[test]
+    fn checks_ping_nonce() {
+        let mut request = header(b'p');
+        request.extend_from_slice(&[9, 8, 7, 6]);
+
+        let mut reply = request.clone();
+        assert!(validate_header(&reply, &request).is_ok());
+
+        reply[HEADER_LEN] = 0;
+        assert_eq!(
+            validate_header(&reply, &request).err(),
+            Some(ParseError::NonceMismatch)
+        );
+
+        assert_eq!(
+            validate_header(&reply[..HEADER_LEN + 2], &request).err(),
+            Some(ParseError::Truncated { field: "nonce" })
+        );
+    }
+
+    #[test]
+    fn converts_to_io_error() {
+        let e: std::io::Error = ParseError::BadMagic.into();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,672 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, OnceCell};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+
+use crate::query::{self, QueryResponse};
+use crate::query_backoff::QueryBackoff;
+use crate::query_parser::{self, ParseError};
+use crate::resolver::{self, ResolveError, Resolver};
+
+// how many queries can wait for a reply at the same time
+const MAX_IN_FLIGHT: usize = 256;
+// minimum gap between two outgoing datagrams, 2ms is ~500 packets per second
+const SEND_INTERVAL: Duration = Duration::from_millis(2);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const RECV_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+type Reply = (Result<QueryResponse, ParseError>, Instant);
+type Pending = Arc<Mutex<HashMap<(SocketAddr, u8), VecDeque<Waiter>>>>;
+
+struct Waiter {
+    id: u64,
+    request: Vec<u8>,
+    tx: oneshot::Sender<Reply>,
+}
+
+/// Queries waiting for a free slot go out in this order, most urgent first.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Selected,
+    Visible,
+    Favorite,
+    #[default]
+    Background,
+}
+
+struct Queued {
+    priority: Priority,
+    // keeps queries of the same priority first come, first served
+    seq: u64,
+    tx: oneshot::Sender<()>,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    // BinaryHeap pops the greatest element, which should be the most urgent
+    // priority and then the oldest query
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Default)]
+struct Scheduler {
+    in_flight: usize,
+    queue: BinaryHeap<Queued>,
+    next_seq: u64,
+}
+
+// gives its slot to the next queued query, or frees it, when dropped
+struct Permit<'a> {
+    inner: &'a Inner,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.inner.release();
+    }
+}
+
+// a query that gets cancelled while waiting for its reply mustn't stay in
+// the pending list forever
+struct PendingGuard<'a> {
+    inner: &'a Inner,
+    key: (SocketAddr, u8),
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.inner.pending.lock().unwrap();
+        if let Some(waiters) = pending.get_mut(&self.key) {
+            waiters.retain(|w| w.id != self.id);
+            if waiters.is_empty() {
+                pending.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Endpoint {
+    pub ip: String,
+    pub port: i32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct BatchResult {
+    pub ip: String,
+    pub port: i32,
+    pub query_type: char,
+    pub ping: Option<u32>,
+    pub response: Option<QueryResponse>,
+    pub error: Option<String>,
+    // the server didn't answer, as opposed to never being asked
+    #[serde(skip)]
+    pub timed_out: bool,
+    // filled in by whoever keeps track of previous responses
+    pub changed: bool,
+}
+
+struct Inner {
+    socket_v4: OnceCell<Arc<UdpSocket>>,
+    socket_v6: OnceCell<Arc<UdpSocket>>,
+    pending: Pending,
+    scheduler: Mutex<Scheduler>,
+    next_send: tokio::sync::Mutex<Instant>,
+    next_id: AtomicU64,
+    batches: Mutex<HashMap<u64, AbortHandle>>,
+    backoff: QueryBackoff,
+    resolver: Resolver,
+}
+
+impl Inner {
+    async fn acquire(&self, priority: Priority) -> Permit<'_> {
+        let rx = {
+            let mut scheduler = self.scheduler.lock().unwrap();
+            if scheduler.in_flight < MAX_IN_FLIGHT {
+                scheduler.in_flight += 1;
+                return Permit { inner: self };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            let seq = scheduler.next_seq;
+            scheduler.next_seq += 1;
+            scheduler.queue.push(Queued { priority, seq, tx });
+            rx
+        };
+
+        let mut queued = QueuedGuard {
+            inner: self,
+            rx: Some(rx),
+        };
+        // whoever releases a slot hands it straight over, in_flight stays the same
+        let _ = queued.rx.as_mut().unwrap().await;
+        queued.rx = None;
+        Permit { inner: self }
+    }
+
+    fn release(&self) {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        while let Some(next) = scheduler.queue.pop() {
+            // cancelled queries have dropped their receiver, skip them
+            if next.tx.send(()).is_ok() {
+                return;
+            }
+        }
+        scheduler.in_flight -= 1;
+    }
+}
+
+// covers a query cancelled right after it was handed a slot, but before it
+// got to see that, so the slot isn't lost
+struct QueuedGuard<'a> {
+    inner: &'a Inner,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.inner.release();
+            }
+        }
+    }
+}
+
+/// Sends every query through one shared socket per address family and hands replies back to
+/// whoever is waiting for that address and opcode.
+#[derive(Clone)]
+pub struct QueryPool {
+    inner: Arc<Inner>,
+}
+
+impl QueryPool {
+    pub fn new(backoff: QueryBackoff, resolver: Resolver) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                socket_v4: OnceCell::new(),
+                socket_v6: OnceCell::new(),
+                pending: Arc::new(Mutex::new(HashMap::new())),
+                scheduler: Mutex::new(Scheduler::default()),
+                next_send: tokio::sync::Mutex::new(Instant::now()),
+                next_id: AtomicU64::new(0),
+                batches: Mutex::new(HashMap::new()),
+                backoff,
+                resolver,
+            }),
+        }
+    }
+
+    async fn socket(&self, addr: SocketAddr) -> Result<Arc<UdpSocket>, Error> {
+        let (cell, bind_addr) = if addr.is_ipv4() {
+            (&self.inner.socket_v4, "0.0.0.0:0")
+        } else {
+            (&self.inner.socket_v6, "[::]:0")
+        };
+
+        cell.get_or_try_init(|| async {
+            let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+            tokio::spawn(receive_loop(socket.clone(), self.inner.pending.clone()));
+            Ok::<_, Error>(socket)
+        })
+        .await
+        .cloned()
+    }
+
+    async fn throttle(&self) {
+        let mut next = self.inner.next_send.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            sleep_until(*next).await;
+        }
+        *next = std::cmp::max(now, *next) + SEND_INTERVAL;
+    }
+
+    /// Sends a single query and waits for the matching reply, returns the
+    /// parsed response together with the round trip time. When too many
+    /// queries are in flight it waits behind the ones with a higher priority.
+    pub async fn query(
+        &self,
+        addr: SocketAddr,
+        query_type: char,
+        priority: Priority,
+    ) -> Result<(QueryResponse, Duration), Error> {
+        let socket = self.socket(addr).await?;
+        let _permit = self.inner.acquire(priority).await;
+
+        let key = (addr, query_type as u8);
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let packet = query::build_packet(addr, query_type);
+        let (tx, rx) = oneshot::channel();
+        self.inner
+            .pending
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push_back(Waiter {
+                id,
+                request: packet.clone(),
+                tx,
+            });
+        let _pending = PendingGuard {
+            inner: &self.inner,
+            key,
+            id,
+        };
+
+        self.throttle().await;
+        let sent_at = Instant::now();
+        socket.send_to(&packet, addr).await?;
+
+        match timeout(REPLY_TIMEOUT, rx).await {
+            Ok(Ok((result, received_at))) => Ok((result?, received_at - sent_at)),
+            Ok(Err(_)) => Err(Error::other("query pool shut down")),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "query timed out")),
+        }
+    }
+
+    /// Runs every query type against every endpoint and calls `on_result`
+    /// as soon as each one finishes, returns once all of them are done.
+    pub async fn query_batch<F>(
+        &self,
+        endpoints: Vec<Endpoint>,
+        query_types: Vec<char>,
+        priority: Priority,
+        on_result: F,
+    ) where
+        F: Fn(BatchResult) + Send + Sync + 'static,
+    {
+        let on_result = Arc::new(on_result);
+        let mut tasks = JoinSet::new();
+
+        for endpoint in endpoints {
+            let pool = self.clone();
+            let query_types = query_types.clone();
+            let on_result = on_result.clone();
+
+            tasks.spawn(async move {
+                let backoff = &pool.inner.backoff;
+                let addr = match backoff.check(&endpoint.ip, endpoint.port) {
+                    Ok(_) => pool
+                        .inner
+                        .resolver
+                        .resolve(&endpoint.ip, endpoint.port)
+                        .await
+                        .and_then(|addresses| {
+                            resolver::preferred(&addresses)
+                                .ok_or_else(|| ResolveError::NoAddresses(endpoint.ip.clone()))
+                        })
+                        .map_err(Error::from),
+                    Err(e) => Err(e),
+                };
+                let addr = match addr {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        let error = e.to_string();
+                        backoff.record::<()>(&endpoint.ip, endpoint.port, &Err(e));
+                        for query_type in query_types {
+                            on_result(BatchResult {
+                                ip: endpoint.ip.clone(),
+                                port: endpoint.port,
+                                query_type,
+                                ping: None,
+                                response: None,
+                                error: Some(error.clone()),
+                                timed_out: false,
+                                changed: true,
+                            });
+                        }
+                        return;
+                    }
+                };
+
+                let mut queries = JoinSet::new();
+                for query_type in query_types {
+                    let pool = pool.clone();
+                    queries.spawn(async move {
+                        (query_type, pool.query(addr, query_type, priority).await)
+                    });
+                }
+
+                // one answer to any of the queries is enough to call the server up
+                let mut answered = false;
+                let mut failed = false;
+                while let Some(joined) = queries.join_next().await {
+                    let (query_type, result) = match joined {
+                        Ok(r) => r,
+                        Err(_) => continue,
+                    };
+
+                    let mut batch_result = BatchResult {
+                        ip: endpoint.ip.clone(),
+                        port: endpoint.port,
+                        query_type,
+                        ping: None,
+                        response: None,
+                        error: None,
+                        timed_out: false,
+                        changed: true,
+                    };
+
+                    match result {
+                        Ok((response, rtt)) => {
+                            if query_type == 'p' {
+                                batch_result.ping = Some(rtt.as_millis() as u32);
+                            }
+                            batch_result.response = Some(response);
+                            answered = true;
+                        }
+                        Err(e) => {
+                            if e.kind() == ErrorKind::InvalidData {
+                                answered = true;
+                            } else {
+                                failed = true;
+                            }
+                            batch_result.timed_out = e.kind() == ErrorKind::TimedOut;
+                            batch_result.error = Some(e.to_string());
+                        }
+                    }
+
+                    on_result(batch_result);
+                }
+
+                if answered || failed {
+                    backoff.report(&endpoint.ip, endpoint.port, answered);
+                }
+            });
+        }
+
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// Same as `query_batch`, but runs in the background and returns a handle
+    /// right away. Queries of a cancelled batch that are still queued or
+    /// waiting for a reply are dropped.
+    pub fn spawn_batch<F>(
+        &self,
+        endpoints: Vec<Endpoint>,
+        query_types: Vec<char>,
+        priority: Priority,
+        on_result: F,
+    ) -> u64
+    where
+        F: Fn(BatchResult) + Send + Sync + 'static,
+    {
+        let handle = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        // held until the task is registered, so it can't remove itself first
+        let mut batches = self.inner.batches.lock().unwrap();
+
+        let pool = self.clone();
+        let task = tokio::spawn(async move {
+            pool.query_batch(endpoints, query_types, priority, on_result)
+                .await;
+            pool.inner.batches.lock().unwrap().remove(&handle);
+        });
+
+        batches.insert(handle, task.abort_handle());
+        handle
+    }
+
+    /// Returns false when the batch already finished or never existed.
+    pub fn cancel(&self, handle: u64) -> bool {
+        match self.inner.batches.lock().unwrap().remove(&handle) {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// errors the socket keeps working after. windows reports icmp "port
+// unreachable" from earlier sends as a reset, and datagrams bigger than the
+// buffer as WSAEMSGSIZE
+fn is_transient(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+    ) || e.raw_os_error() == Some(10040)
+}
+
+async fn receive_loop(socket: Arc<UdpSocket>, pending: Pending) {
+    let mut buf = vec![0; query::MAX_DATAGRAM_LEN];
+    loop {
+        let (amt, from) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) if is_transient(&e) => {
+                info!("[query_pool.rs] recv_from failed: {}", e);
+                // keeps an error that comes back right away from spinning
+                sleep(RECV_RETRY_DELAY).await;
+                continue;
+            }
+            Err(e) => {
+                info!("[query_pool.rs] recv_from failed for good: {}", e);
+                break;
+            }
+        };
+
+        if amt < query_parser::HEADER_LEN {
+            continue;
+        }
+
+        let key = (from, buf[10]);
+        let waiter = {
+            let mut pending = pending.lock().unwrap();
+            let waiter = pending.get_mut(&key).and_then(|waiters| {
+                // several pings to the same server can be in flight, the nonce
+                // tells which one this reply belongs to
+                let position = waiters
+                    .iter()
+                    .position(|w| query_parser::validate_header(&buf[..amt], &w.request).is_ok())?;
+                waiters.remove(position)
+            });
+            if pending.get(&key).is_some_and(|w| w.is_empty()) {
+                pending.remove(&key);
+            }
+            waiter
+        };
+
+        if let Some(waiter) = waiter {
+            let _ = waiter
+                .tx
+                .send((query_parser::parse_response(&buf[..amt]), Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{Behaviour, MockConfig, MockServer};
+
+    #[tokio::test]
+    async fn batch_against_mock_servers() {
+        let mut servers = Vec::new();
+        for i in 0..3 {
+            let mut config = MockConfig::default();
+            config.info.hostname = format!("server {}", i);
+            servers.push(MockServer::start(config).await);
+        }
+        let silent = MockServer::start(MockConfig {
+            behaviours: HashMap::from([('i', Behaviour::Drop)]),
+            ..Default::default()
+        })
+        .await;
+
+        let mut endpoints: Vec<Endpoint> = servers
+            .iter()
+            .map(|s| Endpoint {
+                ip: "127.0.0.1".to_string(),
+                port: s.port(),
+            })
+            .collect();
+        endpoints.push(Endpoint {
+            ip: "127.0.0.1".to_string(),
+            port: silent.port(),
+        });
+
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let collected = results.clone();
+        QueryPool::new(QueryBackoff::new(), Resolver::new())
+            .query_batch(endpoints, vec!['i', 'p'], Priority::Background, move |r| {
+                collected.lock().unwrap().push(r)
+            })
+            .await;
+
+        let results = results.lock().unwrap();
+        assert_eq!(results.len(), 8);
+
+        for (i, server) in servers.iter().enumerate() {
+            let info = results
+                .iter()
+                .find(|r| r.port == server.port() && r.query_type == 'i')
+                .unwrap();
+            match &info.response {
+                Some(QueryResponse::Info(info)) => {
+                    assert_eq!(info.hostname, format!("server {}", i))
+                }
+                _ => panic!("no info from server {}", i),
+            }
+
+            let ping = results
+                .iter()
+                .find(|r| r.port == server.port() && r.query_type == 'p')
+                .unwrap();
+            assert!(ping.ping.is_some());
+        }
+
+        let silent_info = results
+            .iter()
+            .find(|r| r.port == silent.port() && r.query_type == 'i')
+            .unwrap();
+        assert!(silent_info.response.is_none());
+        assert!(silent_info.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn concurrent_pings_get_their_own_pong() {
+        let server = MockServer::start(MockConfig::default()).await;
+        let pool = QueryPool::new(QueryBackoff::new(), Resolver::new());
+
+        let mut pings = JoinSet::new();
+        for _ in 0..10 {
+            let pool = pool.clone();
+            let addr = server.addr;
+            pings.spawn(async move { pool.query(addr, 'p', Priority::Visible).await });
+        }
+
+        while let Some(joined) = pings.join_next().await {
+            let (response, _) = joined.unwrap().unwrap();
+            assert!(matches!(response, QueryResponse::Pong));
+        }
+        assert!(pool.inner.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn queued_queries_go_out_by_priority() {
+        let pool = QueryPool::new(QueryBackoff::new(), Resolver::new());
+        let mut permits = Vec::new();
+        for _ in 0..MAX_IN_FLIGHT {
+            permits.push(pool.inner.acquire(Priority::Background).await);
+        }
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiting = JoinSet::new();
+        for priority in [Priority::Background, Priority::Visible, Priority::Selected] {
+            let pool = pool.clone();
+            let order = order.clone();
+            waiting.spawn(async move {
+                let _permit = pool.inner.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+            });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // a cancelled query gives up its place in the queue
+        let cancelled = waiting.spawn({
+            let pool = pool.clone();
+            async move {
+                let _permit = pool.inner.acquire(Priority::Selected).await;
+                panic!("cancelled query got a slot");
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        cancelled.abort();
+
+        permits.pop();
+        while waiting.join_next().await.is_some() {}
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec![Priority::Selected, Priority::Visible, Priority::Background]
+        );
+
+        drop(permits);
+        assert_eq!(pool.inner.scheduler.lock().unwrap().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_batch_stops_waiting() {
+        let server = MockServer::start(MockConfig {
+            delay: Duration::from_millis(500),
+            ..Default::default()
+        })
+        .await;
+        let pool = QueryPool::new(QueryBackoff::new(), Resolver::new());
+        let results = Arc::new(Mutex::new(Vec::new()));
+        let collected = results.clone();
+
+        let handle = pool.spawn_batch(
+            vec![Endpoint {
+                ip: "127.0.0.1".to_string(),
+                port: server.port(),
+            }],
+            vec!['i'],
+            Priority::Background,
+            move |r| collected.lock().unwrap().push(r),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!pool.inner.pending.lock().unwrap().is_empty());
+
+        assert!(pool.cancel(handle));
+        assert!(!pool.cancel(handle));
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(results.lock().unwrap().is_empty());
+        assert!(pool.inner.pending.lock().unwrap().is_empty());
+        assert_eq!(pool.inner.scheduler.lock().unwrap().in_flight, 0);
+    }
+}
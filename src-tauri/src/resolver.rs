@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::lookup_host;
+
+// the system resolver doesn't tell us record ttls, so these are fixed
+const POSITIVE_TTL: Duration = Duration::from_secs(5 * 60);
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+struct CacheEntry {
+    result: Result<Vec<IpAddr>, ResolveError>,
+    expires: Instant,
+}
+
+/// Hostname lookups, cached for a while. Clones share the same cache.
+#[derive(Clone)]
+pub struct Resolver {
+    // keyed by lowercase hostname, ports don't matter for lookups
+    cache: Arc<Mutex<HashMap<String, CacheEntry>>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolveError {
+    EmptyHost,
+    InvalidPort(i32),
+    Lookup { host: String, reason: String },
+    NoAddresses(String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::EmptyHost => write!(f, "no host given"),
+            ResolveError::InvalidPort(port) => write!(f, "{} is not a valid port", port),
+            ResolveError::Lookup { host, reason } => {
+                write!(f, "host lookup for {} failed: {}", host, reason)
+            }
+            ResolveError::NoAddresses(host) => write!(f, "{} has no addresses", host),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl From<ResolveError> for std::io::Error {
+    fn from(e: ResolveError) -> Self {
+        let kind = match e {
+            ResolveError::EmptyHost | ResolveError::InvalidPort(_) => {
+                std::io::ErrorKind::InvalidInput
+            }
+            ResolveError::Lookup { .. } | ResolveError::NoAddresses(_) => {
+                std::io::ErrorKind::NotFound
+            }
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+/// Parses IPv4 and IPv6 literals, IPv6 may be wrapped in brackets like "[::1]".
+pub fn parse_ip(host: &str) -> Option<IpAddr> {
+    host.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Resolves an ip or hostname to every address it points to, in the order
+    /// the system resolver returned them. Hostname lookups, failed ones
+    /// included, are cached for a while.
+    pub async fn resolve(&self, host: &str, port: i32) -> Result<Vec<SocketAddr>, ResolveError> {
+        let port = match u16::try_from(port) {
+            Ok(p) if p != 0 => p,
+            _ => return Err(ResolveError::InvalidPort(port)),
+        };
+
+        let host = host.trim();
+        if host.is_empty() {
+            return Err(ResolveError::EmptyHost);
+        }
+
+        if let Some(ip) = parse_ip(host) {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+
+        let key = host.to_lowercase();
+        let ips = match self.cached(&key) {
+            Some(result) => result?,
+            None => {
+                let result = lookup(host, port).await;
+                let ttl = if result.is_ok() {
+                    POSITIVE_TTL
+                } else {
+                    NEGATIVE_TTL
+                };
+                self.cache.lock().unwrap().insert(
+                    key,
+                    CacheEntry {
+                        result: result.clone(),
+                        expires: Instant::now() + ttl,
+                    },
+                );
+                result?
+            }
+        };
+
+        Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    // the cached lookup of `key`, unless there's none or it expired
+    fn cached(&self, key: &str) -> Option<Result<Vec<IpAddr>, ResolveError>> {
+        match self.cache.lock().unwrap().get(key) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.result.clone()),
+            _ => None,
+        }
+    }
+
+    /// Drops the cached lookup of `host`, or the whole cache when it's `None`.
+    pub fn invalidate(&self, host: Option<&str>) {
+        let mut cache = self.cache.lock().unwrap();
+        match host {
+            Some(host) => {
+                cache.remove(&host.trim().to_lowercase());
+            }
+            None => cache.clear(),
+        }
+    }
+}
+
+async fn lookup(host: &str, port: u16) -> Result<Vec<IpAddr>, ResolveError> {
+    let mut ips: Vec<IpAddr> = Vec::new();
+    let resolved = lookup_host((host, port))
+        .await
+        .map_err(|e| ResolveError::Lookup {
+            host: host.to_string(),
+            reason: e.to_string(),
+        })?;
+    for address in resolved {
+        if !ips.contains(&address.ip()) {
+            ips.push(address.ip());
+        }
+    }
+
+    if ips.is_empty() {
+        return Err(ResolveError::NoAddresses(host.to_string()));
+    }
+    Ok(ips)
+}
+
+/// First IPv4 address if there's any, otherwise the first address. Used where
+/// only one address can be picked, like the game's command line.
+pub fn preferred(addresses: &[SocketAddr]) -> Option<SocketAddr> {
+    addresses
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or_else(|| addresses.first())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn insert(
+        resolver: &Resolver,
+        host: &str,
+        result: Result<Vec<IpAddr>, ResolveError>,
+        expires: Instant,
+    ) {
+        resolver
+            .cache
+            .lock()
+            .unwrap()
+            .insert(host.to_string(), CacheEntry { result, expires });
+    }
+
+    #[tokio::test]
+    async fn resolves_literals() {
+        let resolver = Resolver::new();
+        let v4 = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert_eq!(
+            resolver.resolve(" 127.0.0.1 ", 7777).await.unwrap(),
+            vec![SocketAddr::new(v4, 7777)]
+        );
+        assert_eq!(
+            resolver.resolve("::1", 7777).await.unwrap(),
+            vec![SocketAddr::new(v6, 7777)]
+        );
+        assert_eq!(
+            resolver.resolve("[::1]", 7777).await.unwrap(),
+            vec![SocketAddr::new(v6, 7777)]
+        );
+        assert_eq!(parse_ip("example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_input() {
+        let resolver = Resolver::new();
+        assert!(matches!(
+            resolver.resolve("  ", 7777).await,
+            Err(ResolveError::EmptyHost)
+        ));
+        for port in [0, -1, 65536] {
+            assert!(matches!(
+                resolver.resolve("127.0.0.1", port).await,
+                Err(ResolveError::InvalidPort(p)) if p == port
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_lookups_until_they_expire() {
+        let resolver = Resolver::new();
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let later = Instant::now() + Duration::from_secs(60);
+        let earlier = Instant::now() - Duration::from_secs(1);
+
+        // cached answers are used without looking anything up
+        insert(&resolver, "good.resolver.test", Ok(vec![ip]), later);
+        assert_eq!(
+            resolver.resolve("Good.Resolver.Test", 7777).await.unwrap(),
+            vec![SocketAddr::new(ip, 7777)]
+        );
+        insert(
+            &resolver,
+            "bad.resolver.test",
+            Err(ResolveError::NoAddresses("bad.resolver.test".to_string())),
+            later,
+        );
+        assert!(matches!(
+            resolver.resolve("bad.resolver.test", 7777).await,
+            Err(ResolveError::NoAddresses(_))
+        ));
+
+        // both kinds expire
+        insert(&resolver, "good.resolver.test", Ok(vec![ip]), earlier);
+        assert!(resolver.cached("good.resolver.test").is_none());
+        insert(
+            &resolver,
+            "bad.resolver.test",
+            Err(ResolveError::NoAddresses("bad.resolver.test".to_string())),
+            earlier,
+        );
+        assert!(resolver.cached("bad.resolver.test").is_none());
+
+        insert(&resolver, "good.resolver.test", Ok(vec![ip]), later);
+        insert(&resolver, "other.resolver.test", Ok(vec![ip]), later);
+        resolver.invalidate(Some(" GOOD.resolver.test "));
+        assert!(resolver.cached("good.resolver.test").is_none());
+        assert!(resolver.cached("other.resolver.test").is_some());
+
+        resolver.invalidate(None);
+        assert!(resolver.cached("other.resolver.test").is_none());
+    }
+}
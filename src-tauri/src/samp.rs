@@ -73,8 +73,28 @@ pub fn get_nickname() -> String {
     }
 }
 
+#[cfg(not(target_os = "windows"))]
+pub fn get_rcon_password(_ip: &str, _port: i32) -> Option<String> {
+    None
+}
+
 #[cfg(target_os = "windows")]
 pub fn get_samp_favorite_list() -> String {
+    serde_json::to_string(&get_samp_user_data()).unwrap()
+}
+
+/// RCON password saved for this server in SA-MP's USERDATA.DAT, if there's one.
+#[cfg(target_os = "windows")]
+pub fn get_rcon_password(ip: &str, port: i32) -> Option<String> {
+    get_samp_user_data()
+        .favorite_servers
+        .into_iter()
+        .find(|server| server.ip == ip && server.port as i32 == port && !server.rcon.is_empty())
+        .map(|server| server.rcon)
+}
+
+#[cfg(target_os = "windows")]
+fn get_samp_user_data() -> SAMPUserData {
     let mut samp_user_data: SAMPUserData = SAMPUserData {
         file_id: "none".to_string(),
         file_version: 0,
@@ -157,5 +177,5 @@ pub fn get_samp_favorite_list() -> String {
         }
     }
 
-    serde_json::to_string(&samp_user_data).unwrap()
+    samp_user_data
 }
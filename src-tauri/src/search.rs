@@ -0,0 +1,244 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// how much a match in each field is worth, a mode or language alone shouldn't
+// outrank a server named after what was typed
+const HOSTNAME_WEIGHT: f32 = 1.0;
+const GAME_MODE_WEIGHT: f32 = 0.7;
+const LANGUAGE_WEIGHT: f32 = 0.5;
+
+const EXACT: f32 = 1.0;
+const PREFIX: f32 = 0.8;
+const SUBSTRING: f32 = 0.6;
+const FUZZY: f32 = 0.5;
+// terms shorter than this only match exactly, by prefix or as a substring,
+// one typo away from a two letter word is almost anything
+const MIN_FUZZY_LEN: usize = 3;
+
+// russian, ukrainian and belarusian letters the way players usually spell
+// them in latin
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' => "g",
+        'ґ' => "g",
+        'д' => "d",
+        'е' | 'ё' | 'э' | 'є' => "e",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' | 'ї' => "i",
+        'й' => "y",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' | 'ў' => "u",
+        'ф' => "f",
+        'х' => "h",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "sch",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'ю' => "yu",
+        'я' => "ya",
+        // latin letters that don't decompose into a base letter and an accent
+        'đ' => "d",
+        'ł' => "l",
+        'ø' => "o",
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'þ' => "th",
+        'ı' => "i",
+        _ => return None,
+    })
+}
+
+/// Lowercases `text`, drops accents and spells cyrillic in latin, so
+/// "Россия", "Rossiya" and "ROSSIYA" all come out the same, and so do
+/// "Việt Nam" and "viet nam".
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match transliterate(c) {
+            Some(latin) => folded.push_str(latin),
+            None => folded.extend(Some(c).into_iter().nfd().filter(|c| !is_combining_mark(*c))),
+        }
+    }
+    folded
+}
+
+fn words(folded: &str) -> Vec<String> {
+    folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[derive(Clone, Default, Debug, PartialEq)]
+struct Indexed {
+    words: Vec<String>,
+    // the words run together, so "rp" is found in "[RU]RolePlay|RP"
+    compact: String,
+}
+
+impl Indexed {
+    fn new(text: &str) -> Self {
+        let words = words(&fold(text));
+        Self {
+            compact: words.concat(),
+            words,
+        }
+    }
+
+    fn score(&self, term: &str) -> f32 {
+        let term_len = term.chars().count();
+        let max_distance = if term_len >= 8 { 2 } else { 1 };
+
+        let mut best = 0.0;
+        for word in &self.words {
+            let score = if word == term {
+                EXACT
+            } else if word.starts_with(term) {
+                PREFIX
+            } else if term_len >= MIN_FUZZY_LEN
+                && word.chars().count().abs_diff(term_len) <= max_distance
+            {
+                let distance = strsim::damerau_levenshtein(word, term);
+                if distance <= max_distance {
+                    FUZZY * (1.0 - distance as f32 / (term_len + 1) as f32)
+                } else {
+                    0.0
+                }
+            } else {
+                0.0
+            };
+            if score == EXACT {
+                return score;
+            }
+            best = f32::max(best, score);
+        }
+
+        if best < SUBSTRING && self.compact.contains(term) {
+            best = SUBSTRING;
+        }
+        best
+    }
+}
+
+/// The folded words of a server's hostname, mode and language, worked out
+/// once when they change rather than on every search.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct SearchEntry {
+    hostname: Indexed,
+    game_mode: Indexed,
+    language: Indexed,
+}
+
+impl SearchEntry {
+    pub fn new(hostname: &str, game_mode: &str, language: &str) -> Self {
+        Self {
+            hostname: Indexed::new(hostname),
+            game_mode: Indexed::new(game_mode),
+            language: Indexed::new(language),
+        }
+    }
+}
+
+/// What was typed in the search bar, split into folded terms.
+pub struct Query {
+    terms: Vec<String>,
+}
+
+impl Query {
+    pub fn new(query: &str) -> Self {
+        Self {
+            terms: words(&fold(query)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// How well `entry` matches, higher is better. Every term has to match
+    /// one of the fields for the entry to match at all.
+    pub fn score(&self, entry: &SearchEntry) -> Option<f32> {
+        let mut total = 0.0;
+        for term in &self.terms {
+            let score = f32::max(
+                entry.hostname.score(term) * HOSTNAME_WEIGHT,
+                f32::max(
+                    entry.game_mode.score(term) * GAME_MODE_WEIGHT,
+                    entry.language.score(term) * LANGUAGE_WEIGHT,
+                ),
+            );
+            if score == 0.0 {
+                return None;
+            }
+            total += score;
+        }
+        Some(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, hostname: &str) -> Option<f32> {
+        Query::new(query).score(&SearchEntry::new(hostname, "", ""))
+    }
+
+    #[test]
+    fn folds_accents_and_cyrillic() {
+        assert_eq!(fold("Россия RolePlay"), "rossiya roleplay");
+        assert_eq!(fold("Việt Nam Đà Nẵng"), "viet nam da nang");
+        assert_eq!(fold("São João"), "sao joao");
+        assert_eq!(fold("Київ"), "kiiv");
+    }
+
+    #[test]
+    fn ranks_matches() {
+        let exact = score("rossiya", "[RU] Россия RP").unwrap();
+        let prefix = score("ross", "[RU] Россия RP").unwrap();
+        let substring = score("rp", "[RU]RolePlay|RP").unwrap();
+        let typo = score("rosiya", "[RU] Россия RP").unwrap();
+        assert!(exact > prefix && prefix > typo);
+        assert!(substring > 0.0);
+
+        // every term has to match somewhere
+        assert_eq!(score("rossiya drift", "[RU] Россия RP"), None);
+        assert_eq!(score("xyz", "[RU] Россия RP"), None);
+        // no fuzzy matching for very short terms
+        assert_eq!(score("ab", "cd"), None);
+
+        assert!(score("vietnam", "Việt Nam").is_some());
+        assert!(score("виет", "Viet Nam Roleplay").is_some());
+    }
+
+    #[test]
+    fn weighs_fields() {
+        let query = Query::new("drift");
+        let hostname = query
+            .score(&SearchEntry::new("Drift Paradise", "Freeroam", "English"))
+            .unwrap();
+        let mode = query
+            .score(&SearchEntry::new("Paradise", "Drift", "English"))
+            .unwrap();
+        assert!(hostname > mode);
+        assert!(Query::new("english")
+            .score(&SearchEntry::new("Paradise", "Drift", "English"))
+            .is_some());
+    }
+}
@@ -0,0 +1,433 @@
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::io::{Error, ErrorKind};
+
+use crate::filter_expr::{self, Filter};
+use crate::search::Query;
+use crate::server_store::StoredServer;
+
+// keeps a pathological pattern from eating memory, regexes typed into the
+// search bar are tiny anyway
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Players,
+    Ping,
+    Name,
+    Mode,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Sort {
+    pub key: SortKey,
+    pub order: Order,
+}
+
+/// Inclusive bounds, either side may be left open.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct Range {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+}
+
+impl Range {
+    fn is_open(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct Criteria {
+    // searched for in hostnames, modes and languages, see search
+    pub query: String,
+    pub hostname_regex: Option<String>,
+    pub gamemode_regex: Option<String>,
+    pub omp_only: bool,
+    pub non_empty: bool,
+    pub unpassworded: bool,
+    pub partners_only: bool,
+    // keywords of the selected languages, any of them has to be part of the
+    // server's language
+    pub languages: Vec<String>,
+    pub players: Range,
+    // servers that haven't been pinged yet only pass an open range
+    pub ping: Range,
+    // filter expression, see filter_expr
+    pub expression: Option<String>,
+    // first key decides, the following ones break ties
+    pub sort: Vec<Sort>,
+}
+
+fn compile(pattern: &Option<String>) -> Result<Option<Regex>, Error> {
+    let pattern = match pattern.as_deref().map(str::trim) {
+        Some(p) if !p.is_empty() => p,
+        _ => return Ok(None),
+    };
+
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map(Some)
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid regex {}: {}", pattern, e),
+            )
+        })
+}
+
+// criteria with everything that only has to be done once worked out
+struct Matcher<'a> {
+    criteria: &'a Criteria,
+    query: Query,
+    languages: Vec<String>,
+    hostname: Option<Regex>,
+    gamemode: Option<Regex>,
+    expression: Option<Filter>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(criteria: &'a Criteria) -> Result<Self, Error> {
+        Ok(Self {
+            criteria,
+            query: Query::new(&criteria.query),
+            languages: criteria
+                .languages
+                .iter()
+                .map(|l| l.to_lowercase())
+                .collect(),
+            hostname: compile(&criteria.hostname_regex)?,
+            gamemode: compile(&criteria.gamemode_regex)?,
+            expression: match criteria.expression.as_deref().map(str::trim) {
+                Some(e) if !e.is_empty() => Some(
+                    filter_expr::parse(e)
+                        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?,
+                ),
+                _ => None,
+            },
+        })
+    }
+
+    // None when the server is filtered out, otherwise how well it fits the
+    // query, 0 without one
+    fn matches(&self, server: &StoredServer) -> Option<f32> {
+        let criteria = self.criteria;
+        if server.ip.is_empty() || server.hostname.is_empty() {
+            return None;
+        }
+
+        if (criteria.omp_only && !server.using_omp)
+            || (criteria.non_empty && server.player_count == 0)
+            || (criteria.unpassworded && server.has_password)
+            || (criteria.partners_only && !server.partner)
+        {
+            return None;
+        }
+
+        if !criteria.players.contains(server.player_count as u32) {
+            return None;
+        }
+        if !criteria.ping.is_open() && !server.ping.is_some_and(|p| criteria.ping.contains(p)) {
+            return None;
+        }
+
+        if !self.languages.is_empty() {
+            let language = server.language.to_lowercase();
+            if !self.languages.iter().any(|l| language.contains(l.as_str())) {
+                return None;
+            }
+        }
+
+        if self
+            .hostname
+            .as_ref()
+            .is_some_and(|r| !r.is_match(&server.hostname))
+        {
+            return None;
+        }
+        if self
+            .gamemode
+            .as_ref()
+            .is_some_and(|r| !r.is_match(&server.game_mode))
+        {
+            return None;
+        }
+        if self.expression.as_ref().is_some_and(|f| !f.matches(server)) {
+            return None;
+        }
+
+        if self.query.is_empty() {
+            return Some(0.0);
+        }
+        self.query.score(&server.search)
+    }
+}
+
+fn compare_key(a: &StoredServer, b: &StoredServer, sort: &Sort) -> Ordering {
+    let ordering = match sort.key {
+        SortKey::Players => a.player_count.cmp(&b.player_count),
+        SortKey::Name => a.hostname.to_lowercase().cmp(&b.hostname.to_lowercase()),
+        SortKey::Mode => a.game_mode.to_lowercase().cmp(&b.game_mode.to_lowercase()),
+        SortKey::Ping => match (a.ping, b.ping) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            // unknown pings go last whatever the order
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+    };
+
+    match sort.order {
+        Order::Ascending => ordering,
+        Order::Descending => ordering.reverse(),
+    }
+}
+
+/// Ids of the `servers` matching `criteria`, in the order it asks for. Ties
+/// go to the better search match, then to the lower id, so the same list
+/// always comes out the same way.
+pub fn filter<'a, I>(servers: I, criteria: &Criteria) -> Result<Vec<String>, Error>
+where
+    I: IntoIterator<Item = (&'a String, &'a StoredServer)>,
+{
+    let matcher = Matcher::new(criteria)?;
+    let mut matched: Vec<_> = servers
+        .into_iter()
+        .filter_map(|(id, server)| Some((id, server, matcher.matches(server)?)))
+        .collect();
+
+    matched.sort_by(|(a_id, a, a_score), (b_id, b, b_score)| {
+        criteria
+            .sort
+            .iter()
+            .map(|sort| compare_key(a, b, sort))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| b_score.total_cmp(a_score).then_with(|| a_id.cmp(b_id)))
+    });
+
+    Ok(matched.into_iter().map(|(id, _, _)| id.clone()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn server(hostname: &str, game_mode: &str, players: u16, ping: Option<u32>) -> StoredServer {
+        StoredServer {
+            ip: "127.0.0.1".to_string(),
+            hostname: hostname.to_string(),
+            game_mode: game_mode.to_string(),
+            language: "English".to_string(),
+            player_count: players,
+            ping,
+            ..Default::default()
+        }
+    }
+
+    fn servers() -> HashMap<String, StoredServer> {
+        let mut servers = HashMap::new();
+        servers.insert(
+            "a".to_string(),
+            server("Alpha Roleplay", "RP", 10, Some(50)),
+        );
+        servers.insert(
+            "b".to_string(),
+            server("bravo deathmatch", "DM", 0, Some(20)),
+        );
+        servers.insert(
+            "c".to_string(),
+            StoredServer {
+                has_password: true,
+                using_omp: true,
+                partner: true,
+                language: "Русский".to_string(),
+                ..server("Charlie Roleplay", "RP", 30, None)
+            },
+        );
+        servers.insert(
+            "d".to_string(),
+            server("Delta Freeroam", "Freeroam", 10, Some(80)),
+        );
+        servers.values_mut().for_each(StoredServer::index);
+        servers
+    }
+
+    fn run(criteria: Criteria) -> Vec<String> {
+        filter(&servers(), &criteria).unwrap()
+    }
+
+    #[test]
+    fn flags_and_query() {
+        assert_eq!(run(Criteria::default()), vec!["a", "b", "c", "d"]);
+        assert_eq!(
+            run(Criteria {
+                query: "ROLEPLAY".to_string(),
+                ..Default::default()
+            }),
+            vec!["a", "c"]
+        );
+        assert_eq!(
+            run(Criteria {
+                non_empty: true,
+                unpassworded: true,
+                ..Default::default()
+            }),
+            vec!["a", "d"]
+        );
+        assert_eq!(
+            run(Criteria {
+                omp_only: true,
+                partners_only: true,
+                languages: vec!["русский".to_string(), "RU".to_string()],
+                ..Default::default()
+            }),
+            vec!["c"]
+        );
+    }
+
+    #[test]
+    fn ranks_search_results() {
+        let search = |query: &str| {
+            run(Criteria {
+                query: query.to_string(),
+                ..Default::default()
+            })
+        };
+
+        // a hostname match beats a mode match
+        assert_eq!(search("rp roleplay"), vec!["a", "c"]);
+        assert_eq!(search("freeroam"), vec!["d"]);
+        assert_eq!(search("charlie rp"), vec!["c"]);
+        // typed in latin, written in cyrillic
+        assert_eq!(search("russkiy"), vec!["c"]);
+        // one typo away
+        assert_eq!(search("deathmach"), vec!["b"]);
+        assert_eq!(search("rolplay"), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            run(Criteria {
+                players: Range {
+                    min: Some(10),
+                    max: Some(20),
+                },
+                ..Default::default()
+            }),
+            vec!["a", "d"]
+        );
+        // c was never pinged
+        assert_eq!(
+            run(Criteria {
+                ping: Range {
+                    min: None,
+                    max: Some(60),
+                },
+                ..Default::default()
+            }),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn regexes() {
+        assert_eq!(
+            run(Criteria {
+                hostname_regex: Some("^(alpha|delta) ".to_string()),
+                gamemode_regex: Some("rp|freeroam".to_string()),
+                ..Default::default()
+            }),
+            vec!["a", "d"]
+        );
+
+        assert_eq!(
+            run(Criteria {
+                expression: Some("players >= 10 && mode == \"rp\" && ping < 100".to_string()),
+                ..Default::default()
+            }),
+            vec!["a"]
+        );
+
+        let e = filter(
+            &servers(),
+            &Criteria {
+                hostname_regex: Some("(unclosed".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+        let e = filter(
+            &servers(),
+            &Criteria {
+                expression: Some("players >".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn sorts_by_several_keys() {
+        let sort = |keys: &[(SortKey, Order)]| {
+            run(Criteria {
+                sort: keys
+                    .iter()
+                    .map(|&(key, order)| Sort { key, order })
+                    .collect(),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(
+            sort(&[
+                (SortKey::Players, Order::Descending),
+                (SortKey::Name, Order::Descending)
+            ]),
+            vec!["c", "d", "a", "b"]
+        );
+        assert_eq!(
+            sort(&[
+                (SortKey::Mode, Order::Ascending),
+                (SortKey::Players, Order::Ascending)
+            ]),
+            vec!["b", "d", "a", "c"]
+        );
+        // names compare without case
+        assert_eq!(
+            sort(&[(SortKey::Name, Order::Ascending)]),
+            vec!["a", "b", "c", "d"]
+        );
+
+        // unknown pings stay last either way
+        assert_eq!(
+            sort(&[(SortKey::Ping, Order::Ascending)]),
+            vec!["b", "a", "d", "c"]
+        );
+        assert_eq!(
+            sort(&[(SortKey::Ping, Order::Descending)]),
+            vec!["d", "a", "b", "c"]
+        );
+    }
+}
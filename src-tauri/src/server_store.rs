@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::sync::{Arc, Mutex};
+
+use crate::list_sources::ListedServer;
+use crate::query::{ExtraInfoPacket, PLAYER_LIST_LIMIT};
+use crate::query_pool::Endpoint;
+use crate::search::SearchEntry;
+use crate::server_filter::{self, Criteria};
+use crate::subscriptions::ServerDiff;
+
+/// What the backend knows about a server of the internet list, enough to
+/// filter and sort it without asking the frontend.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct StoredServer {
+    pub ip: String,
+    pub port: i32,
+    pub hostname: String,
+    pub game_mode: String,
+    pub language: String,
+    pub has_password: bool,
+    pub player_count: u16,
+    pub max_players: u16,
+    pub using_omp: bool,
+    pub partner: bool,
+    // None until the server answered a ping, and again once it stops answering
+    pub ping: Option<u32>,
+    pub rules: HashMap<String, String>,
+    pub extra_info: Option<ExtraInfoPacket>,
+    // kept up to date with the hostname, mode and language by `index`
+    pub search: SearchEntry,
+}
+
+impl StoredServer {
+    /// Refreshes the search entry after the hostname, mode or language
+    /// changed.
+    pub fn index(&mut self) {
+        self.search = SearchEntry::new(&self.hostname, &self.game_mode, &self.language);
+    }
+}
+
+/// Same as the frontend builds its keys, `ip:port`.
+pub fn id(ip: &str, port: i32) -> String {
+    format!("{}:{}", ip, port)
+}
+
+// the frontend tells the same way from the rules
+fn is_omp(rules: &[(String, String)]) -> bool {
+    rules
+        .iter()
+        .any(|(name, value)| name == "allow_DL" || (name == "version" && value.contains("omp ")))
+}
+
+pub struct ServerStore {
+    // shared with searches still going through a snapshot, updates copy a
+    // server before changing it if one is
+    servers: Mutex<HashMap<String, Arc<StoredServer>>>,
+}
+
+impl ServerStore {
+    pub fn new() -> Self {
+        Self {
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the stored list with `servers`. Pings and open.mp extra info
+    /// are kept for servers that are still on it, lists don't carry them, and
+    /// so are rules when the list has none.
+    pub fn replace(&self, servers: &[ListedServer]) {
+        let mut stored = self.servers.lock().unwrap();
+        let previous = std::mem::take(&mut *stored);
+
+        for listed in servers {
+            let server = &listed.server;
+            let id = id(&server.ip, server.port);
+            let previous = previous.get(&id);
+            let rules = match previous {
+                Some(p) if server.rules.is_empty() => p.rules.clone(),
+                _ => server.rules.clone(),
+            };
+            let mut stored_server = StoredServer {
+                ip: server.ip.clone(),
+                port: server.port,
+                hostname: server.hostname.clone(),
+                game_mode: server.game_mode.clone(),
+                language: server.language.clone(),
+                has_password: server.has_password,
+                player_count: server.player_count,
+                max_players: server.max_players,
+                using_omp: server.using_omp,
+                partner: server.partner,
+                ping: previous.and_then(|s| s.ping),
+                rules,
+                extra_info: previous.and_then(|s| s.extra_info.clone()),
+                ..Default::default()
+            };
+            // the list rarely changes names, most entries can keep theirs
+            match previous {
+                Some(p)
+                    if p.hostname == stored_server.hostname
+                        && p.game_mode == stored_server.game_mode
+                        && p.language == stored_server.language =>
+                {
+                    stored_server.search = p.search.clone()
+                }
+                _ => stored_server.index(),
+            }
+            stored.insert(id, Arc::new(stored_server));
+        }
+    }
+
+    /// Applies a subscription update. Servers that aren't on the list, like
+    /// favorites added by hand, are left to the frontend.
+    pub fn apply(&self, ip: &str, port: i32, diff: &ServerDiff) {
+        let mut stored = self.servers.lock().unwrap();
+        let server = match stored.get_mut(&id(ip, port)) {
+            Some(server) => Arc::make_mut(server),
+            None => return,
+        };
+
+        if let Some(password) = diff.password {
+            server.has_password = password;
+        }
+        if let Some(players) = diff.players {
+            server.player_count = players;
+        }
+        if let Some(max_players) = diff.max_players {
+            server.max_players = max_players;
+        }
+        // diffs only carry what changed, so a reindex is only paid for when
+        // a name did
+        if let Some(hostname) = &diff.hostname {
+            server.hostname = hostname.clone();
+        }
+        if let Some(gamemode) = &diff.gamemode {
+            server.game_mode = gamemode.clone();
+        }
+        if let Some(language) = &diff.language {
+            server.language = language.clone();
+        }
+        if diff.hostname.is_some() || diff.gamemode.is_some() || diff.language.is_some() {
+            server.index();
+        }
+        if let Some(rules) = &diff.rules {
+            server.using_omp = is_omp(rules);
+            server.rules = rules.iter().cloned().collect();
+        }
+        if let Some(extra_info) = &diff.extra_info {
+            server.extra_info = Some(extra_info.clone());
+        }
+        if let Some(ping) = diff.ping {
+            server.ping = Some(ping);
+        }
+        if diff.online == Some(false) {
+            server.ping = None;
+        }
+    }
+
+    /// The `limit` most populated servers that still answer player list
+    /// queries, busiest first.
+    pub fn most_populated(&self, limit: usize) -> Vec<Endpoint> {
+        let stored = self.servers.lock().unwrap();
+        let mut servers: Vec<&StoredServer> = stored
+            .values()
+            .map(Arc::as_ref)
+            .filter(|s| s.player_count > 0 && s.player_count <= PLAYER_LIST_LIMIT)
+            .collect();
+        servers.sort_by(|a, b| {
+            b.player_count
+                .cmp(&a.player_count)
+                .then_with(|| id(&a.ip, a.port).cmp(&id(&b.ip, b.port)))
+        });
+        servers
+            .into_iter()
+            .take(limit)
+            .map(|s| Endpoint {
+                ip: s.ip.clone(),
+                port: s.port,
+            })
+            .collect()
+    }
+
+    /// Ids of the stored servers matching `criteria`, in order. Scoring
+    /// happens on a snapshot, updates don't wait for it.
+    pub fn filter(&self, criteria: &Criteria) -> Result<Vec<String>, Error> {
+        let servers: Vec<(String, Arc<StoredServer>)> = self
+            .servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, server)| (id.clone(), server.clone()))
+            .collect();
+        server_filter::filter(
+            servers.iter().map(|(id, server)| (id, server.as_ref())),
+            criteria,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::master_list::MasterServer;
+
+    fn listed(ip: &str, hostname: &str) -> ListedServer {
+        ListedServer {
+            server: MasterServer {
+                ip: ip.to_string(),
+                port: 7777,
+                hostname: hostname.to_string(),
+                game_mode: "-".to_string(),
+                language: "-".to_string(),
+                has_password: false,
+                player_count: 0,
+                max_players: 50,
+                version: "-".to_string(),
+                using_omp: false,
+                partner: false,
+                rules: HashMap::new(),
+            },
+            sources: vec!["open.mp".to_string()],
+        }
+    }
+
+    #[test]
+    fn keeps_up_with_lists_and_updates() {
+        let store = ServerStore::new();
+        store.replace(&[listed("10.0.0.1", "first"), listed("10.0.0.2", "second")]);
+
+        store.apply(
+            "10.0.0.1",
+            7777,
+            &ServerDiff {
+                ping: Some(40),
+                players: Some(12),
+                rules: Some(vec![("version".to_string(), "omp 1.0.0".to_string())]),
+                ..Default::default()
+            },
+        );
+        // not on the list, nothing to update
+        store.apply(
+            "10.0.0.3",
+            7777,
+            &ServerDiff {
+                players: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let criteria = Criteria {
+            omp_only: true,
+            non_empty: true,
+            ..Default::default()
+        };
+        assert_eq!(store.filter(&criteria).unwrap(), vec!["10.0.0.1:7777"]);
+        let popular = store.most_populated(5);
+        assert_eq!(popular.len(), 1);
+        assert_eq!(
+            (popular[0].ip.as_str(), popular[0].port),
+            ("10.0.0.1", 7777)
+        );
+
+        // searches follow hostname updates
+        store.apply(
+            "10.0.0.2",
+            7777,
+            &ServerDiff {
+                hostname: Some("Россия".to_string()),
+                ..Default::default()
+            },
+        );
+        let criteria = Criteria {
+            query: "rossiya".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(store.filter(&criteria).unwrap(), vec!["10.0.0.2:7777"]);
+
+        // a fresh list keeps the ping it doesn't carry itself
+        store.replace(&[listed("10.0.0.1", "first")]);
+        let servers = store.servers.lock().unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers["10.0.0.1:7777"].ping, Some(40));
+        assert_eq!(servers["10.0.0.1:7777"].player_count, 0);
+        drop(servers);
+
+        store.apply(
+            "10.0.0.1",
+            7777,
+            &ServerDiff {
+                online: Some(false),
+                ..Default::default()
+            },
+        );
+        assert_eq!(store.servers.lock().unwrap()["10.0.0.1:7777"].ping, None);
+    }
+}
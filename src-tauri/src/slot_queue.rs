@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+
+use crate::injector;
+use crate::query;
+use crate::query_backoff::QueryBackoff;
+use crate::query_pool::Endpoint;
+use crate::resolver::Resolver;
+
+// a full server gets asked for its info once per interval for as long as we
+// wait, anything faster than this is rude
+const MIN_INTERVAL: u64 = 5;
+
+fn default_interval() -> u64 {
+    10
+}
+
+/// What to do once a slot opens.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OnSlot {
+    Notify,
+    // the same arguments the inject command takes
+    Join {
+        name: String,
+        exe: String,
+        dll: String,
+        password: String,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct QueueRequest {
+    pub ip: String,
+    pub port: i32,
+    // seconds between two info queries
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    // seconds to give up after, without one we wait until cancelled
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    pub on_slot: OnSlot,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum QueueState {
+    Waiting { players: u16, max_players: u16 },
+    // the server didn't answer this time, we keep trying
+    Unreachable { error: String },
+    // a slot opened and we were only asked to tell
+    Open { players: u16, max_players: u16 },
+    Joined,
+    // error is what the inject command would have returned
+    JoinFailed { error: String },
+    TimedOut,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QueueEvent {
+    pub ip: String,
+    pub port: i32,
+    #[serde(flatten)]
+    pub state: QueueState,
+}
+
+// polls `ip:port` until it has a free slot or `timeout` has passed, and acts
+// on the slot the way `on_slot` says
+#[allow(clippy::too_many_arguments)]
+async fn run<F>(
+    backoff: &QueryBackoff,
+    resolver: &Resolver,
+    ip: &str,
+    port: i32,
+    on_slot: &OnSlot,
+    interval: Duration,
+    timeout: Option<Duration>,
+    on_state: F,
+) where
+    F: Fn(QueueState),
+{
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        // the user asked to wait on this server, so it's polled at our own
+        // pace no matter how long everyone else has to leave it alone
+        backoff.retry_now(ip, port);
+        let info = backoff
+            .guard(ip, port, || async {
+                query::Query::new(resolver, ip, port).await?.info().await
+            })
+            .await;
+
+        match info {
+            Ok(info) if info.players < info.max_players => {
+                let state = match on_slot {
+                    OnSlot::Notify => QueueState::Open {
+                        players: info.players,
+                        max_players: info.max_players,
+                    },
+                    OnSlot::Join {
+                        name,
+                        exe,
+                        dll,
+                        password,
+                    } => match injector::run_samp(resolver, name, ip, port, exe, dll, password)
+                        .await
+                    {
+                        Ok(_) => QueueState::Joined,
+                        Err(error) => QueueState::JoinFailed { error },
+                    },
+                };
+                on_state(state);
+                return;
+            }
+            Ok(info) => on_state(QueueState::Waiting {
+                players: info.players,
+                max_players: info.max_players,
+            }),
+            Err(e) => on_state(QueueState::Unreachable {
+                error: e.to_string(),
+            }),
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() + interval > deadline) {
+            on_state(QueueState::TimedOut);
+            return;
+        }
+        sleep(interval).await;
+    }
+}
+
+/// Servers we're waiting on a free slot of, one queue per server.
+pub struct SlotQueue {
+    tasks: Mutex<HashMap<(String, i32), JoinHandle<()>>>,
+}
+
+impl SlotQueue {
+    pub fn new() -> Self {
+        Self {
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts waiting for a slot on the requested server, reporting every
+    /// poll through `on_event`. Queueing again for the same server replaces
+    /// the previous queue.
+    pub fn start<F>(
+        &self,
+        backoff: QueryBackoff,
+        resolver: Resolver,
+        request: QueueRequest,
+        on_event: F,
+    ) where
+        F: Fn(QueueEvent) + Send + Sync + 'static,
+    {
+        let key = (request.ip.clone(), request.port);
+        let task = tokio::spawn(async move {
+            let QueueRequest {
+                ip,
+                port,
+                interval,
+                timeout,
+                on_slot,
+            } = request;
+
+            run(
+                &backoff,
+                &resolver,
+                &ip,
+                port,
+                &on_slot,
+                Duration::from_secs(interval.max(MIN_INTERVAL)),
+                timeout.map(Duration::from_secs),
+                |state| {
+                    on_event(QueueEvent {
+                        ip: ip.clone(),
+                        port,
+                        state,
+                    })
+                },
+            )
+            .await;
+        });
+
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| !task.is_finished());
+        if let Some(previous) = tasks.insert(key, task) {
+            previous.abort();
+        }
+    }
+
+    pub fn cancel(&self, ip: &str, port: i32) -> bool {
+        match self.tasks.lock().unwrap().remove(&(ip.to_string(), port)) {
+            Some(task) => {
+                let waiting = !task.is_finished();
+                task.abort();
+                waiting
+            }
+            None => false,
+        }
+    }
+
+    /// Servers that are still being waited on.
+    pub fn queued(&self) -> Vec<Endpoint> {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| !task.is_finished());
+        tasks
+            .keys()
+            .map(|(ip, port)| Endpoint {
+                ip: ip.clone(),
+                port: *port,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockConfig, MockServer};
+    use std::sync::Arc;
+
+    async fn full_server() -> MockServer {
+        let server = MockServer::start(MockConfig::default()).await;
+        {
+            let mut config = server.config.lock().unwrap();
+            config.info.players = 2;
+            config.info.max_players = 2;
+        }
+        server
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_free_slot() {
+        let server = full_server().await;
+        let states = Arc::new(Mutex::new(Vec::new()));
+
+        let config = server.config.clone();
+        let seen = states.clone();
+        run(
+            &QueryBackoff::new(),
+            &Resolver::new(),
+            "127.0.0.1",
+            server.port(),
+            &OnSlot::Notify,
+            Duration::from_millis(50),
+            None,
+            move |state| {
+                // somebody leaves after the first poll
+                config.lock().unwrap().info.players = 1;
+                seen.lock().unwrap().push(state);
+            },
+        )
+        .await;
+
+        assert_eq!(
+            *states.lock().unwrap(),
+            vec![
+                QueueState::Waiting {
+                    players: 2,
+                    max_players: 2
+                },
+                QueueState::Open {
+                    players: 1,
+                    max_players: 2
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn polls_backed_off_servers() {
+        let server = full_server().await;
+        let backoff = QueryBackoff::new();
+        for _ in 0..3 {
+            backoff.report("127.0.0.1", server.port(), false);
+        }
+        let states = Mutex::new(Vec::new());
+
+        run(
+            &backoff,
+            &Resolver::new(),
+            "127.0.0.1",
+            server.port(),
+            &OnSlot::Notify,
+            Duration::from_millis(50),
+            Some(Duration::from_millis(20)),
+            |state| states.lock().unwrap().push(state),
+        )
+        .await;
+
+        assert_eq!(
+            states.into_inner().unwrap()[0],
+            QueueState::Waiting {
+                players: 2,
+                max_players: 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_timeout() {
+        let server = full_server().await;
+        let states = Mutex::new(Vec::new());
+
+        run(
+            &QueryBackoff::new(),
+            &Resolver::new(),
+            "127.0.0.1",
+            server.port(),
+            &OnSlot::Notify,
+            Duration::from_millis(50),
+            Some(Duration::from_millis(120)),
+            |state| states.lock().unwrap().push(state),
+        )
+        .await;
+
+        let states = states.into_inner().unwrap();
+        assert_eq!(states.last(), Some(&QueueState::TimedOut));
+        assert!(states.len() >= 3);
+    }
+
+    #[tokio::test]
+    async fn cancels_queues() {
+        let server = full_server().await;
+        let queue = SlotQueue::new();
+        queue.start(
+            QueryBackoff::new(),
+            Resolver::new(),
+            QueueRequest {
+                ip: "127.0.0.1".to_string(),
+                port: server.port(),
+                interval: MIN_INTERVAL,
+                timeout: None,
+                on_slot: OnSlot::Notify,
+            },
+            |_| {},
+        );
+
+        assert_eq!(queue.queued().len(), 1);
+        assert!(queue.cancel("127.0.0.1", server.port()));
+        assert!(!queue.cancel("127.0.0.1", server.port()));
+        assert!(queue.queued().is_empty());
+    }
+}
@@ -0,0 +1,346 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::query::{DetailedPlayer, ExtraInfoPacket, Player, QueryResponse};
+use crate::query_pool::{BatchResult, Endpoint, Priority, QueryPool};
+
+// anything faster than this is almost certainly a mistake on the frontend
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fields of a server that changed since the previous refresh, everything
+/// else is left out.
+#[derive(Serialize, Clone, Default, PartialEq, Debug)]
+pub struct ServerDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub online: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub players: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_players: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamemode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<(String, String)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_list: Option<Vec<Player>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detailed_player_list: Option<Vec<DetailedPlayer>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_info: Option<ExtraInfoPacket>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ServerUpdate {
+    pub subscription: String,
+    pub ip: String,
+    pub port: i32,
+    pub diff: ServerDiff,
+}
+
+// stores `value` and hands it back if it's different from what was stored
+fn update<T: PartialEq + Clone>(slot: &mut Option<T>, value: T) -> Option<T> {
+    if slot.as_ref() == Some(&value) {
+        return None;
+    }
+    *slot = Some(value.clone());
+    Some(value)
+}
+
+impl ServerDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == ServerDiff::default()
+    }
+
+    /// Treats `self` as the last known state of a server, applies `result`
+    /// to it and returns only what changed.
+    pub fn apply(&mut self, result: &BatchResult) -> ServerDiff {
+        let mut diff = ServerDiff::default();
+
+        let response = match &result.response {
+            Some(response) => response,
+            None => {
+                // only an info query going unanswered says much about the
+                // server being down, not being asked at all says nothing
+                if result.query_type == 'i' && result.timed_out {
+                    diff.online = update(&mut self.online, false);
+                    // everyone left as far as consumers are concerned, the
+                    // same list coming back has to count as a change
+                    self.player_list = None;
+                    self.detailed_player_list = None;
+                }
+                return diff;
+            }
+        };
+
+        match response {
+            QueryResponse::Info(info) => {
+                diff.online = update(&mut self.online, true);
+                diff.password = update(&mut self.password, info.password);
+                diff.players = update(&mut self.players, info.players);
+                diff.max_players = update(&mut self.max_players, info.max_players);
+                diff.hostname = update(&mut self.hostname, info.hostname.clone());
+                diff.gamemode = update(&mut self.gamemode, info.gamemode.clone());
+                diff.language = update(&mut self.language, info.language.clone());
+            }
+            QueryResponse::Pong => {
+                if let Some(ping) = result.ping {
+                    diff.ping = update(&mut self.ping, ping);
+                }
+            }
+            QueryResponse::Rules(rules) => diff.rules = update(&mut self.rules, rules.clone()),
+            QueryResponse::Players(players) => {
+                diff.player_list = update(&mut self.player_list, players.clone())
+            }
+            QueryResponse::DetailedPlayers(players) => {
+                diff.detailed_player_list = update(&mut self.detailed_player_list, players.clone())
+            }
+            QueryResponse::ExtraInfo(extra_info) => {
+                diff.extra_info = update(&mut self.extra_info, extra_info.clone())
+            }
+            QueryResponse::RconOutput(_) => {}
+        }
+
+        diff
+    }
+}
+
+/// Named sets of servers the backend keeps refreshing on its own, reporting
+/// changes through `on_update` as they come in. Info refreshes are reported
+/// even when nothing changed, with an empty diff.
+pub struct Subscriptions {
+    tasks: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self {
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts refreshing `endpoints` every `interval`. A subscription with the
+    /// same name is replaced, queries it still had running included, so the
+    /// frontend can just subscribe again when a list changes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn subscribe<F>(
+        &self,
+        name: &str,
+        pool: QueryPool,
+        endpoints: Vec<Endpoint>,
+        query_types: Vec<char>,
+        priority: Priority,
+        interval: Duration,
+        on_update: F,
+    ) where
+        F: Fn(ServerUpdate) + Send + Sync + 'static,
+    {
+        let interval = interval.max(MIN_INTERVAL);
+        let name = name.to_string();
+        let on_update = Arc::new(on_update);
+        let states: Arc<Mutex<HashMap<(String, i32), ServerDiff>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+
+        let subscription = name.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let states = states.clone();
+                let on_update = on_update.clone();
+                let subscription = subscription.clone();
+
+                let endpoints = endpoints.clone();
+                let query_types = query_types.clone();
+                pool.query_batch(endpoints, query_types, priority, move |result| {
+                    let diff = states
+                        .lock()
+                        .unwrap()
+                        .entry((result.ip.clone(), result.port))
+                        .or_default()
+                        .apply(&result);
+
+                    // whoever keeps history needs to know the server was
+                    // still the same, not just that it changed
+                    let refreshed =
+                        result.query_type == 'i' && (result.response.is_some() || result.timed_out);
+                    if !diff.is_empty() || refreshed {
+                        on_update(ServerUpdate {
+                            subscription: subscription.clone(),
+                            ip: result.ip,
+                            port: result.port,
+                            diff,
+                        });
+                    }
+                })
+                .await;
+
+                // counted from the end of a refresh, so slow ones never overlap
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        if let Some(previous) = self.tasks.lock().unwrap().insert(name, task) {
+            previous.abort();
+        }
+    }
+
+    pub fn unsubscribe(&self, name: &str) -> bool {
+        match self.tasks.lock().unwrap().remove(name) {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockConfig, MockServer};
+    use crate::query::InfoPacket;
+    use crate::query_backoff::QueryBackoff;
+    use crate::resolver::Resolver;
+    use tokio::sync::mpsc;
+
+    fn result(query_type: char, response: Option<QueryResponse>) -> BatchResult {
+        BatchResult {
+            ip: "127.0.0.1".to_string(),
+            port: 7777,
+            query_type,
+            ping: None,
+            timed_out: response.is_none(),
+            response,
+            error: None,
+            changed: true,
+        }
+    }
+
+    fn info(players: u16) -> QueryResponse {
+        QueryResponse::Info(InfoPacket {
+            players,
+            hostname: "server".to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn diff_only_has_changed_fields() {
+        let mut state = ServerDiff::default();
+
+        let diff = state.apply(&result('i', Some(info(5))));
+        assert_eq!(diff.online, Some(true));
+        assert_eq!(diff.players, Some(5));
+        assert_eq!(diff.hostname.as_deref(), Some("server"));
+
+        let diff = state.apply(&result('i', Some(info(6))));
+        assert_eq!(
+            diff,
+            ServerDiff {
+                players: Some(6),
+                ..Default::default()
+            }
+        );
+
+        assert!(state.apply(&result('i', Some(info(6)))).is_empty());
+    }
+
+    #[test]
+    fn failed_info_marks_server_offline() {
+        let mut state = ServerDiff::default();
+        state.apply(&result('i', Some(info(5))));
+
+        // other query types failing doesn't change anything
+        assert!(state.apply(&result('r', None)).is_empty());
+
+        // neither does not getting to ask, e.g. while backed off
+        let backed_off = BatchResult {
+            timed_out: false,
+            ..result('i', None)
+        };
+        assert!(state.apply(&backed_off).is_empty());
+
+        let diff = state.apply(&result('i', None));
+        assert_eq!(diff.online, Some(false));
+        assert_eq!(state.players, Some(5));
+    }
+
+    #[test]
+    fn players_come_back_with_the_server() {
+        let players = || {
+            QueryResponse::Players(vec![Player {
+                name: "John_Doe".to_string(),
+                score: 0,
+            }])
+        };
+        let mut state = ServerDiff::default();
+        state.apply(&result('i', Some(info(1))));
+        assert!(state
+            .apply(&result('c', Some(players())))
+            .player_list
+            .is_some());
+        assert!(state.apply(&result('c', Some(players()))).is_empty());
+
+        state.apply(&result('i', None));
+        let diff = state.apply(&result('i', Some(info(1))));
+        assert_eq!(diff.online, Some(true));
+        // the same players as before it went down
+        assert!(state
+            .apply(&result('c', Some(players())))
+            .player_list
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn subscription_reports_changes() {
+        let server = MockServer::start(MockConfig::default()).await;
+        let subscriptions = Subscriptions::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        subscriptions.subscribe(
+            "test",
+            QueryPool::new(QueryBackoff::new(), Resolver::new()),
+            vec![Endpoint {
+                ip: "127.0.0.1".to_string(),
+                port: server.port(),
+            }],
+            vec!['i'],
+            Priority::Visible,
+            MIN_INTERVAL,
+            move |update| {
+                let _ = tx.send(update);
+            },
+        );
+
+        let update = rx.recv().await.unwrap();
+        assert_eq!(update.subscription, "test");
+        assert_eq!(update.diff.players, Some(2));
+
+        server.config.lock().unwrap().info.players = 3;
+        let update = rx.recv().await.unwrap();
+        assert_eq!(
+            update.diff,
+            ServerDiff {
+                players: Some(3),
+                ..Default::default()
+            }
+        );
+
+        // refreshes that changed nothing still come in
+        let update = rx.recv().await.unwrap();
+        assert!(update.diff.is_empty());
+
+        assert!(subscriptions.unsubscribe("test"));
+        assert!(!subscriptions.unsubscribe("test"));
+    }
+}
@@ -20,6 +20,7 @@ import i18n from "./locales";
 import { useGenericPersistentState } from "./states/genericStates";
 import { useTheme } from "./states/theme";
 import { debounce } from "./utils/debounce";
+import { watchFriends } from "./utils/friends";
 import {
   fetchServers,
   fetchUpdateInfo,
@@ -52,7 +53,8 @@ const App = () => {
     invoke("toggle_drpc", {
       toggle: shouldUpdateDiscordStatus,
     });
-    fetchServers();
+    // the most populated servers friends are looked for on come from the list
+    fetchServers().then(() => watchFriends());
     fetchUpdateInfo();
     generateLanguageFilters();
 
@@ -1,19 +1,31 @@
+import { invoke } from "@tauri-apps/api";
 import api from "../api/config";
 import { UpdateInfo } from "../states/app";
-import { mapAPIResponseServerListToAppStructure } from "../utils/helpers";
+import { mapMasterListToAppStructure } from "../utils/helpers";
 import { Log } from "../utils/logger";
-import { APIResponseServer, Server } from "../utils/types";
+import {
+  CustomList,
+  FriendSettings,
+  HistoryPoint,
+  HistoryResolution,
+  HourOfDay,
+  OnSlot,
+  PlayerHit,
+  PlayerSearchMode,
+  SavedFilter,
+  Server,
+  ServerLists,
+} from "../utils/types";
 
+// the backend takes care of caching, of falling back to the last list it got
+// when the api is down and of merging in the lists users added
 export const getCachedList = async () => {
   return new Promise<{ success: boolean; servers: Server[] }>((resolve, _) => {
-    api
-      .get("/servers/full")
-      .then((response) => {
-        const list: APIResponseServer[] = response.data;
-        if (Array.isArray(list)) {
-          const restructuredList = mapAPIResponseServerListToAppStructure(list);
-          resolve({ success: true, servers: restructuredList });
-        }
+    invoke<ServerLists>("fetch_server_lists")
+      .then((lists) => {
+        lists.errors.forEach((e) => Log.debug(e));
+        const restructuredList = mapMasterListToAppStructure(lists.servers);
+        resolve({ success: true, servers: restructuredList });
       })
       .catch((e) => {
         Log.debug(e);
@@ -22,6 +34,83 @@ export const getCachedList = async () => {
   });
 };
 
+export const getListSources = () => invoke<CustomList[]>("get_list_sources");
+
+// replaces the list with the same name, resolves with all registered lists
+export const addListSource = (list: CustomList) =>
+  invoke<CustomList[]>("add_list_source", { list });
+
+export const removeListSource = (name: string) =>
+  invoke<boolean>("remove_list_source", { name });
+
+// rejects with a FilterExpressionError if the expression doesn't parse
+export const checkFilterExpression = (expression: string) =>
+  invoke<void>("check_filter_expression", { expression });
+
+export const getSavedFilters = () => invoke<SavedFilter[]>("get_saved_filters");
+
+// replaces the filter with the same name, resolves with all saved filters
+export const saveFilter = (filter: SavedFilter) =>
+  invoke<SavedFilter[]>("save_filter", { filter });
+
+export const removeSavedFilter = (name: string) =>
+  invoke<boolean>("remove_saved_filter", { name });
+
+// looks players up on every server whose player list we've queried, closest
+// names first
+export const searchPlayers = (
+  query: string,
+  mode: PlayerSearchMode = "fuzzy",
+  limit?: number
+) => invoke<PlayerHit[]>("search_players", { query, mode, limit });
+
+// how many players the server had since `since` (unix millis), oldest first
+export const getPopulationHistory = (
+  server: Server,
+  since?: number,
+  resolution: HistoryResolution = "hourly"
+) =>
+  invoke<HistoryPoint[]>("get_population_history", {
+    ip: server.ip,
+    port: server.port,
+    since,
+    resolution,
+  });
+
+// averages for each hour of the day in local time, ordered by hour
+export const getBusiestHours = (server: Server, days?: number) =>
+  invoke<HourOfDay[]>("get_busiest_hours", {
+    ip: server.ip,
+    port: server.port,
+    utcOffset: -new Date().getTimezoneOffset(),
+    days,
+  });
+
+export const getFriendSettings = () =>
+  invoke<FriendSettings>("get_friend_settings");
+
+// resolves with the settings as they were saved, cleaned up and within limits
+export const saveFriendSettings = (settings: FriendSettings) =>
+  invoke<FriendSettings>("save_friend_settings", { settings });
+
+// polls the server until it has a free slot, progress comes as "slot-queue"
+// events. Queueing again for the same server replaces the previous queue
+export const queueForSlot = (
+  server: Server,
+  onSlot: OnSlot,
+  timeout?: number
+) =>
+  invoke<void>("queue_for_slot", {
+    request: { ip: server.ip, port: server.port, timeout, on_slot: onSlot },
+  });
+
+// resolves with whether the server was still being waited on
+export const cancelSlotQueue = (server: Server) =>
+  invoke<boolean>("cancel_slot_queue", { ip: server.ip, port: server.port });
+
+export const getSlotQueue = () =>
+  invoke<{ ip: string; port: number }[]>("get_slot_queue");
+
 export const getUpdateInfo = async () => {
   return new Promise<{ success: boolean; info: UpdateInfo | undefined }>(
     (resolve, _) => {
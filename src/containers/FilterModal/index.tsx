@@ -1,16 +1,96 @@
 import { t } from "i18next";
-import { Pressable, ScrollView, View } from "react-native";
+import { useEffect, useState } from "react";
+import {
+  Pressable,
+  ScrollView,
+  StyleSheet,
+  TextInput,
+  TouchableOpacity,
+  View,
+} from "react-native";
+import {
+  checkFilterExpression,
+  getSavedFilters,
+  removeSavedFilter,
+  saveFilter,
+} from "../../api/apis";
 import CheckBox from "../../components/CheckBox";
 import Text from "../../components/Text";
 import { useGenericTempState } from "../../states/genericStates";
 import { useTheme } from "../../states/theme";
 import { sc } from "../../utils/sizeScaler";
 import { languageFilters } from "../../utils/helpers";
+import { Log } from "../../utils/logger";
+import { FilterExpressionError, SavedFilter } from "../../utils/types";
 
 const FiltersModal = () => {
   const { theme, themeType } = useTheme();
   const { showFilterMenu, searchData, setSearchData } = useGenericTempState();
   const { ompOnly, nonEmpty, unpassworded, languages } = searchData;
+  const [expression, setExpression] = useState(searchData.expression);
+  const [expressionError, setExpressionError] = useState("");
+  const [savedFilters, setSavedFilters] = useState<SavedFilter[]>([]);
+  const [filterName, setFilterName] = useState("");
+
+  useEffect(() => {
+    getSavedFilters()
+      .then((filters) => setSavedFilters(filters))
+      .catch((e) => Log.debug(e));
+  }, []);
+
+  // only expressions that parse make it to the lists, so a half typed one
+  // doesn't empty them
+  const applyExpression = async (text: string) => {
+    setExpression(text);
+    if (!text.trim().length) {
+      setExpressionError("");
+      setSearchData("expression", "");
+      return;
+    }
+
+    try {
+      await checkFilterExpression(text);
+      setExpressionError("");
+      setSearchData("expression", text);
+    } catch (e) {
+      const error = e as FilterExpressionError;
+      setExpressionError(
+        t("filter_expression_error", {
+          message: error.message,
+          position: error.position,
+        })
+      );
+    }
+  };
+
+  const saveExpression = async () => {
+    if (!filterName.trim().length || !expression.trim().length) return;
+
+    try {
+      const filters = await saveFilter({ name: filterName, expression });
+      setSavedFilters(filters);
+      setFilterName("");
+    } catch (e) {
+      Log.debug(e);
+    }
+  };
+
+  const removeFilter = async (name: string) => {
+    try {
+      await removeSavedFilter(name);
+      setSavedFilters(savedFilters.filter((f) => f.name !== name));
+    } catch (e) {
+      Log.debug(e);
+    }
+  };
+
+  const inputStyle = [
+    styles.input,
+    {
+      color: theme.textPrimary,
+      backgroundColor: theme.textInputBackgroundColor,
+    },
+  ];
 
   return (
     <View
@@ -37,7 +117,7 @@ const FiltersModal = () => {
           top: sc(46),
           left: 0,
           width: sc(250),
-          height: sc(400),
+          height: sc(620),
           padding: sc(10),
           paddingBottom: sc(11),
           backgroundColor: theme.secondary,
@@ -95,6 +175,74 @@ const FiltersModal = () => {
             </Text>
           </Pressable>
         </View>
+        <View style={{ marginTop: sc(10) }}>
+          <Text semibold size={2} color={theme.textPrimary}>
+            {t("filter_expression")}:
+          </Text>
+          <TextInput
+            placeholder={t("filter_expression_placeholder")}
+            placeholderTextColor={theme.textPlaceholder}
+            value={expression}
+            onChangeText={(text) => applyExpression(text)}
+            style={[inputStyle, { marginTop: sc(6) }]}
+          />
+          {expressionError.length ? (
+            <Text
+              size={1}
+              color={"red"}
+              numberOfLines={2}
+              style={{ marginTop: sc(4) }}
+            >
+              {expressionError}
+            </Text>
+          ) : null}
+          <Text
+            semibold
+            size={2}
+            color={theme.textPrimary}
+            style={{ marginTop: sc(10) }}
+          >
+            {t("saved_filters")}:
+          </Text>
+          {savedFilters.map((filter) => (
+            <View key={filter.name} style={styles.row}>
+              <Pressable
+                style={{ flex: 1 }}
+                onPress={() => applyExpression(filter.expression)}
+              >
+                <Text size={2} color={theme.textPrimary}>
+                  {filter.name}
+                </Text>
+              </Pressable>
+              <TouchableOpacity
+                style={[styles.button, { backgroundColor: "red" }]}
+                onPress={() => removeFilter(filter.name)}
+              >
+                <Text semibold color={"#FFFFFF"} size={1}>
+                  {t("remove")}
+                </Text>
+              </TouchableOpacity>
+            </View>
+          ))}
+          <View style={styles.row}>
+            <TextInput
+              placeholder={t("filter_name")}
+              placeholderTextColor={theme.textPlaceholder}
+              value={filterName}
+              onChangeText={(text) => setFilterName(text)}
+              onSubmitEditing={() => saveExpression()}
+              style={[inputStyle, { flex: 1 }]}
+            />
+            <TouchableOpacity
+              style={[styles.button, { backgroundColor: theme.primary }]}
+              onPress={() => saveExpression()}
+            >
+              <Text semibold color={"#FFFFFF"} size={1}>
+                {t("save")}
+              </Text>
+            </TouchableOpacity>
+          </View>
+        </View>
         <View style={{ flex: 1, width: "100%", marginTop: sc(10) }}>
           <Text semibold size={2} color={theme.textPrimary}>
             {t("settings_lang_tab_title")}:
@@ -158,4 +306,29 @@ const FiltersModal = () => {
   );
 };
 
+const styles = StyleSheet.create({
+  row: {
+    flexDirection: "row",
+    alignItems: "center",
+    width: "100%",
+    marginTop: sc(6),
+  },
+  input: {
+    paddingHorizontal: sc(8),
+    height: sc(30),
+    borderRadius: sc(5),
+    outlineStyle: "none",
+    fontFamily: "Proxima Nova Regular",
+    fontSize: sc(15),
+  },
+  button: {
+    height: sc(28),
+    paddingHorizontal: sc(10),
+    borderRadius: sc(5),
+    marginLeft: sc(8),
+    justifyContent: "center",
+    alignItems: "center",
+  },
+});
+
 export default FiltersModal;
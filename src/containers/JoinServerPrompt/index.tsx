@@ -26,6 +26,7 @@ import {
 } from "../../utils/helpers";
 import { Log } from "../../utils/logger";
 import { sc } from "../../utils/sizeScaler";
+import { joinWhenSlotOpens } from "../../utils/slotQueue";
 import { usePersistentServers } from "../../states/servers";
 import { SAMPDLLVersions } from "../../utils/types";
 
@@ -49,6 +50,12 @@ const JoinServerPrompt = () => {
     return undefined;
   }, [server, perServerSettings]);
 
+  // a full server can't be joined right away, we wait for a slot instead
+  const full =
+    server !== undefined &&
+    server.maxPlayers > 0 &&
+    server.playerCount >= server.maxPlayers;
+
   useEffect(() => {
     if (settings) {
       if (settings.nickname !== undefined) {
@@ -204,7 +211,7 @@ const JoinServerPrompt = () => {
           }}
           onPress={() => {
             if (server) {
-              startGame(
+              (full ? joinWhenSlotOpens : startGame)(
                 server,
                 perServerNickname.length ? perServerNickname : nickName,
                 gtasaPath,
@@ -215,7 +222,7 @@ const JoinServerPrompt = () => {
           }}
         >
           <Text semibold color={"#FFFFFF"} size={2}>
-            {t("connect")}
+            {full ? t("join_when_slot_opens") : t("connect")}
           </Text>
         </TouchableOpacity>
         <View
@@ -3,12 +3,13 @@ import { FlatList, ListRenderItemInfo, StyleSheet, View } from "react-native";
 import Text from "../../../components/Text";
 import { useTheme } from "../../../states/theme";
 import { sc } from "../../../utils/sizeScaler";
-import { Player } from "../../../utils/types";
+import { Player, Server } from "../../../utils/types";
 import { useServers } from "../../../states/servers";
 import { useMemo } from "react";
 
 interface IProps {
   players: Player[];
+  status?: Server["playerListStatus"];
 }
 
 const PlayerList = (props: IProps) => {
@@ -46,6 +47,11 @@ const PlayerList = (props: IProps) => {
     return "";
   }, [selected?.omp, themeType]);
 
+  const hasDetails = useMemo(
+    () => props.players.some((player) => player.ping !== undefined),
+    [props.players]
+  );
+
   const renderPlayer = ({
     item: player,
     index,
@@ -57,9 +63,19 @@ const PlayerList = (props: IProps) => {
       >
         <View style={[styles.commonFieldContainer, styles.nameFieldContainer]}>
           <Text style={{ fontSize: sc(16) }} color={theme.textPrimary}>
+            {player.id !== undefined ? `(${player.id}) ` : ""}
             {player.name}
           </Text>
         </View>
+        {hasDetails && (
+          <View
+            style={[styles.commonFieldContainer, styles.pingFieldContainer]}
+          >
+            <Text style={{ fontSize: sc(14) }} color={theme.textSecondary}>
+              {player.ping}
+            </Text>
+          </View>
+        )}
         <View
           style={[
             styles.commonFieldContainer,
@@ -102,6 +118,19 @@ const PlayerList = (props: IProps) => {
             {t("player")}
           </Text>
         </View>
+        {hasDetails && (
+          <View
+            style={[styles.commonFieldContainer, styles.pingFieldContainer]}
+          >
+            <Text
+              semibold
+              style={{ fontSize: sc(17) }}
+              color={theme.textSecondary}
+            >
+              {t("ping")}
+            </Text>
+          </View>
+        )}
         <View style={[styles.commonFieldContainer, styles.scoreFieldContainer]}>
           <Text
             semibold
@@ -121,11 +150,17 @@ const PlayerList = (props: IProps) => {
           height: bannerUrl.length ? "46%" : "51%",
         }}
       >
-        <FlatList
-          id={themeType === "dark" ? "scroll" : "scroll-light"}
-          data={props.players}
-          renderItem={renderPlayer}
-        />
+        {props.status && props.status !== "complete" ? (
+          <Text style={{ fontSize: sc(15) }} color={theme.textSecondary}>
+            {t(`player_list_${props.status}`)}
+          </Text>
+        ) : (
+          <FlatList
+            id={themeType === "dark" ? "scroll" : "scroll-light"}
+            data={props.players}
+            renderItem={renderPlayer}
+          />
+        )}
       </View>
     </>
   );
@@ -143,6 +178,10 @@ const styles = StyleSheet.create({
     flex: 1,
     alignItems: "flex-start",
   },
+  pingFieldContainer: {
+    width: sc(60),
+    alignItems: "flex-end",
+  },
   scoreFieldContainer: {
     paddingRight: 5,
     alignItems: "flex-end",
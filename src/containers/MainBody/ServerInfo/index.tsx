@@ -57,7 +57,10 @@ const ServerInfo = () => {
 
   return (
     <View style={styles.serverInfoView}>
-      <PlayerList players={selected ? selected.players : []} />
+      <PlayerList
+        players={selected ? selected.players : []}
+        status={selected ? selected.playerListStatus : undefined}
+      />
       <View
         style={{
           width: "100%",
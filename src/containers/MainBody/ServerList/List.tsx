@@ -1,18 +1,44 @@
+import { useEffect, useRef } from "react";
 import { StyleProp, StyleSheet, View, ViewStyle } from "react-native";
 import BigList from "react-native-big-list";
 import { useTheme } from "../../../states/theme";
+import {
+  subscribeVisibleServers,
+  unsubscribeServers,
+} from "../../../utils/query";
 import { sc } from "../../../utils/sizeScaler";
-import { Server } from "../../../utils/types";
+import { ListType, Server } from "../../../utils/types";
 import ListHeader from "./ListHeader";
 
+// scrolling changes visible rows constantly, only resubscribe once it settles
+const VISIBLE_ROWS_DEBOUNCE = 300;
+
 interface IProps {
   data: Server[];
+  listType: ListType;
   renderItem: (item: Server, index: number) => JSX.Element;
   containerStyle?: StyleProp<ViewStyle>;
 }
 
 const List = (props: IProps) => {
   const { themeType } = useTheme();
+  const visibleTimer = useRef<ReturnType<typeof setTimeout> | undefined>(
+    undefined
+  );
+
+  useEffect(() => {
+    return () => {
+      clearTimeout(visibleTimer.current);
+      unsubscribeServers(props.listType, true);
+    };
+  }, [props.listType]);
+
+  const onVisibleServersChanged = (servers: Server[]) => {
+    clearTimeout(visibleTimer.current);
+    visibleTimer.current = setTimeout(() => {
+      subscribeVisibleServers(servers, props.listType);
+    }, VISIBLE_ROWS_DEBOUNCE);
+  };
 
   return (
     <View style={styles.mainContainer}>
@@ -22,6 +48,11 @@ const List = (props: IProps) => {
         contentContainerStyle={props.containerStyle}
         data={props.data}
         renderItem={(info) => props.renderItem(info.item, info.index)}
+        onViewableItemsChanged={({ viewableItems }) =>
+          onVisibleServersChanged(
+            viewableItems.map((viewable) => viewable.item as Server)
+          )
+        }
         headerHeight={0}
         itemHeight={sc(39)}
         renderFooter={null}
@@ -19,6 +19,7 @@ import { useJoinServerPrompt } from "../../../states/joinServerPrompt";
 import { usePersistentServers, useServers } from "../../../states/servers";
 // import { fetchServers } from "../../../utils/helpers";
 import { useTheme } from "../../../states/theme";
+import { discoverLanServers } from "../../../utils/query";
 import { sc } from "../../../utils/sizeScaler";
 
 interface IProps {
@@ -316,6 +317,16 @@ const SearchBar = (props: IProps) => {
             onPress={() => clearRecentlyJoined()}
           />
         )}
+        {listType === "lan" && (
+          <ActionIcon
+            title={t("rescan_lan")}
+            icon={images.icons.refresh}
+            iconSize={sc(20)}
+            iconColor={theme.textSecondary}
+            buttonColor={theme.itemBackgroundColor}
+            onPress={() => discoverLanServers()}
+          />
+        )}
         <ActionIcon
           svg
           title={t("add_server")}
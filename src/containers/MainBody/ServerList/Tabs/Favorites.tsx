@@ -50,6 +50,7 @@ const Favorites = () => {
   return (
     <List
       data={list}
+      listType="favorites"
       renderItem={(item, index) => (
         <ServerItem
           isSelected={
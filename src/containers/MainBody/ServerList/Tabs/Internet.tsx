@@ -1,8 +1,12 @@
-import { useEffect, useMemo } from "react";
+import { useEffect, useState } from "react";
 import { useQuery } from "../../../../hooks/query";
 import { useGenericTempState } from "../../../../states/genericStates";
 import { useServers } from "../../../../states/servers";
-import { sortAndSearchInServerList } from "../../../../utils/helpers";
+import {
+  filterServersInBackend,
+  sortAndSearchInServerList,
+} from "../../../../utils/helpers";
+import { Log } from "../../../../utils/logger";
 import { Server } from "../../../../utils/types";
 import List from "../List";
 import ServerItem from "./../Item";
@@ -19,8 +23,21 @@ const Internet = () => {
     };
   }, []);
 
-  const list = useMemo(() => {
-    return sortAndSearchInServerList(servers, searchData);
+  const [list, setList] = useState<Server[]>([]);
+
+  useEffect(() => {
+    // only the latest search counts, older ones may finish after it
+    let current = true;
+    filterServersInBackend(servers, searchData)
+      .then((filtered) => current && setList(filtered))
+      .catch((e) => {
+        Log.debug(e);
+        if (current) setList(sortAndSearchInServerList(servers, searchData));
+      });
+
+    return () => {
+      current = false;
+    };
   }, [
     searchData.query,
     searchData.ompOnly,
@@ -31,6 +48,7 @@ const Internet = () => {
     searchData.sortName,
     searchData.sortMode,
     searchData.languages,
+    searchData.expression,
     servers,
   ]);
 
@@ -43,6 +61,7 @@ const Internet = () => {
   return (
     <List
       data={list}
+      listType="internet"
       renderItem={(item, index) => (
         <ServerItem
           isSelected={
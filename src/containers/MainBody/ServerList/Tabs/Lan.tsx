@@ -0,0 +1,69 @@
+import { useEffect, useMemo } from "react";
+import { useQuery } from "../../../../hooks/query";
+import { useGenericTempState } from "../../../../states/genericStates";
+import { useServers } from "../../../../states/servers";
+import { sortAndSearchInServerList } from "../../../../utils/helpers";
+import {
+  discoverLanServers,
+  unsubscribeServers,
+} from "../../../../utils/query";
+import { Server } from "../../../../utils/types";
+import List from "../List";
+import ServerItem from "./../Item";
+
+const Lan = () => {
+  const { startQuery, stopQuery } = useQuery();
+  const { selected, lanServers, setSelected } = useServers();
+  const { searchData } = useGenericTempState();
+
+  useEffect(() => {
+    discoverLanServers();
+    return () => {
+      stopQuery();
+      setSelected(undefined);
+      unsubscribeServers("lan");
+    };
+  }, []);
+
+  const list = useMemo(() => {
+    return sortAndSearchInServerList(lanServers, searchData);
+  }, [
+    searchData.query,
+    searchData.ompOnly,
+    searchData.nonEmpty,
+    searchData.unpassworded,
+    searchData.sortPing,
+    searchData.sortPlayer,
+    searchData.sortName,
+    searchData.sortMode,
+    searchData.languages,
+    lanServers,
+  ]);
+
+  const onSelect = (server: Server) => {
+    stopQuery();
+    setSelected(server);
+    startQuery(server, "lan");
+  };
+
+  return (
+    <List
+      data={list}
+      listType="lan"
+      renderItem={(item, index) => (
+        <ServerItem
+          isSelected={
+            selected
+              ? selected.ip === item.ip && selected.port === item.port
+              : false
+          }
+          server={item}
+          index={index}
+          onSelect={(server) => onSelect(server)}
+        />
+      )}
+    />
+  );
+};
+
+export default Lan;
@@ -56,6 +56,7 @@ const RecentlyJoined = () => {
   return (
     <List
       data={list}
+      listType="recentlyjoined"
       renderItem={(item, index) => (
         <ServerItem
           isSelected={
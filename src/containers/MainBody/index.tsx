@@ -8,6 +8,7 @@ import ServerInfo from "./ServerInfo";
 import SearchBar from "./ServerList/SearchBar";
 import Favorites from "./ServerList/Tabs/Favorites";
 import Internet from "./ServerList/Tabs/Internet";
+import Lan from "./ServerList/Tabs/Lan";
 import Partners from "./ServerList/Tabs/Partners";
 import RecentlyJoined from "./ServerList/Tabs/RecentlyJoined";
 import FiltersModal from "../FilterModal";
@@ -22,6 +23,7 @@ const MainView = () => {
     else if (listType === "partners") return <Partners />;
     else if (listType === "internet") return <Internet />;
     else if (listType === "recentlyjoined") return <RecentlyJoined />;
+    else if (listType === "lan") return <Lan />;
   };
 
   return (
@@ -23,6 +23,7 @@ const NavBar = () => {
       label: t("recently_joined"),
       type: "recentlyjoined",
     },
+    { icon: images.icons.ip, label: t("lan"), type: "lan" },
   ];
 
   return (
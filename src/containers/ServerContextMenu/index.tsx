@@ -8,9 +8,12 @@ import { images } from "../../constants/images";
 import { useContextMenu } from "../../states/contextMenu";
 import { usePersistentServers } from "../../states/servers";
 import { useSettings } from "../../states/settings";
+import { useSlotQueue } from "../../states/slotQueue";
 import { useTheme } from "../../states/theme";
 import { startGame } from "../../utils/game";
+import { retryServer } from "../../utils/query";
 import { sc } from "../../utils/sizeScaler";
+import { leaveSlotQueue, notifyWhenSlotOpens } from "../../utils/slotQueue";
 
 const ContextMenu = () => {
   const { width, height } = useWindowDimensions();
@@ -19,10 +22,13 @@ const ContextMenu = () => {
   const { addToFavorites, removeFromFavorites, favorites } =
     usePersistentServers();
   const { nickName, gtasaPath } = useSettings();
+  const { queued } = useSlotQueue();
 
   const [connectBtnBgCol, setConnectBtnBgCol] = useState(theme.secondary);
   const [favBtnBgCol, setFavBtnBgCol] = useState(theme.secondary);
   const [cpyBtnBgCol, setCpyBtnBgCol] = useState(theme.secondary);
+  const [retryBtnBgCol, setRetryBtnBgCol] = useState(theme.secondary);
+  const [queueBtnBgCol, setQueueBtnBgCol] = useState(theme.secondary);
 
   const favorited = useMemo(() => {
     const find = favorites.find(
@@ -31,9 +37,18 @@ const ContextMenu = () => {
     return find !== undefined;
   }, [server, favorites]);
 
+  const full =
+    server && server.maxPlayers > 0 && server.playerCount >= server.maxPlayers;
+  const inQueue = useMemo(
+    () => server && queued.includes(`${server.ip}:${server.port}`),
+    [server, queued]
+  );
+
   const hideMenu = () => {
     setFavBtnBgCol(theme.secondary);
     setCpyBtnBgCol(theme.secondary);
+    setRetryBtnBgCol(theme.secondary);
+    setQueueBtnBgCol(theme.secondary);
     hide();
   };
 
@@ -164,6 +179,64 @@ Using open.mp: ${server.usingOmp ? "Yes" : "No"}`);
               {t("copy_server_info")}
             </Text>
           </Pressable>
+          {server.health ? (
+            <Pressable
+              onHoverIn={() => setRetryBtnBgCol(theme.primary)}
+              onHoverOut={() => setRetryBtnBgCol(theme.secondary)}
+              onPress={() => {
+                retryServer(server);
+                hideMenu();
+              }}
+              style={{
+                backgroundColor: retryBtnBgCol,
+                paddingLeft: 10,
+                paddingRight: 30,
+                paddingVertical: 7,
+                flexDirection: "row",
+                alignItems: "center",
+              }}
+            >
+              <Icon
+                style={{ marginRight: 5 }}
+                image={images.icons.refresh}
+                size={sc(17)}
+              />
+              <Text semibold size={1} color={theme.textPrimary}>
+                {t("retry_now")}
+              </Text>
+            </Pressable>
+          ) : null}
+          {full || inQueue ? (
+            <Pressable
+              onHoverIn={() => setQueueBtnBgCol(theme.primary)}
+              onHoverOut={() => setQueueBtnBgCol(theme.secondary)}
+              onPress={() => {
+                if (inQueue) {
+                  leaveSlotQueue(server);
+                } else {
+                  notifyWhenSlotOpens(server);
+                }
+                hideMenu();
+              }}
+              style={{
+                backgroundColor: queueBtnBgCol,
+                paddingLeft: 10,
+                paddingRight: 30,
+                paddingVertical: 7,
+                flexDirection: "row",
+                alignItems: "center",
+              }}
+            >
+              <Icon
+                style={{ marginRight: 5 }}
+                image={images.icons.users}
+                size={sc(17)}
+              />
+              <Text semibold size={1} color={theme.textPrimary}>
+                {inQueue ? t("leave_slot_queue") : t("notify_when_slot_opens")}
+              </Text>
+            </Pressable>
+          ) : null}
         </View>
       </View>
     );
@@ -1,4 +1,19 @@
-import { Pressable, StyleSheet, View } from "react-native";
+import { useEffect, useState } from "react";
+import {
+  Pressable,
+  ScrollView,
+  StyleSheet,
+  TextInput,
+  TouchableOpacity,
+  View,
+} from "react-native";
+import {
+  addListSource,
+  getFriendSettings,
+  getListSources,
+  removeListSource,
+  saveFriendSettings,
+} from "../../../api/apis";
 import CheckBox from "../../../components/CheckBox";
 import Text from "../../../components/Text";
 import { useGenericPersistentState } from "../../../states/genericStates";
@@ -6,11 +21,96 @@ import { useTheme } from "../../../states/theme";
 import { sc } from "../../../utils/sizeScaler";
 import { t } from "i18next";
 import { invoke } from "@tauri-apps/api";
+import { watchFriends } from "../../../utils/friends";
+import { fetchServers } from "../../../utils/helpers";
+import { Log } from "../../../utils/logger";
+import { CustomList, FriendSettings } from "../../../utils/types";
 
 const Advanced = () => {
-  const { theme } = useTheme();
+  const { theme, themeType } = useTheme();
   const { shouldUpdateDiscordStatus, toggleDiscordStatus } =
     useGenericPersistentState();
+  const [lists, setLists] = useState<CustomList[]>([]);
+  const [listName, setListName] = useState("");
+  const [listLocation, setListLocation] = useState("");
+  const [friendSettings, setFriendSettings] = useState<FriendSettings>();
+  const [friendName, setFriendName] = useState("");
+  const [friendInterval, setFriendInterval] = useState("");
+  const [friendPopular, setFriendPopular] = useState("");
+
+  useEffect(() => {
+    getListSources()
+      .then((lists) => setLists(lists))
+      .catch((e) => Log.debug(e));
+    getFriendSettings()
+      .then((settings) => {
+        setFriendSettings(settings);
+        setFriendInterval(settings.interval.toString());
+        setFriendPopular(settings.popular.toString());
+      })
+      .catch((e) => Log.debug(e));
+  }, []);
+
+  const updateFriendSettings = async (changes: Partial<FriendSettings>) => {
+    if (!friendSettings) return;
+
+    try {
+      const settings = await saveFriendSettings({
+        ...friendSettings,
+        ...changes,
+      });
+      setFriendSettings(settings);
+      // the backend keeps cadence and scope within limits
+      setFriendInterval(settings.interval.toString());
+      setFriendPopular(settings.popular.toString());
+      watchFriends();
+    } catch (e) {
+      Log.debug(e);
+    }
+  };
+
+  const addFriend = () => {
+    if (!friendSettings || !friendName.trim().length) return;
+
+    updateFriendSettings({
+      friends: [...friendSettings.friends, friendName],
+    });
+    setFriendName("");
+  };
+
+  const addList = async () => {
+    try {
+      const lists = await addListSource({
+        name: listName,
+        location: listLocation,
+        refresh_interval: 30 * 60,
+      });
+      setLists(lists);
+      setListName("");
+      setListLocation("");
+      fetchServers();
+    } catch (e) {
+      Log.debug(e);
+    }
+  };
+
+  const removeList = async (name: string) => {
+    try {
+      await removeListSource(name);
+      setLists(lists.filter((list) => list.name !== name));
+      fetchServers();
+    } catch (e) {
+      Log.debug(e);
+    }
+  };
+
+  const inputStyle = [
+    styles.pathInput,
+    {
+      color: theme.textPrimary,
+      backgroundColor: theme.textInputBackgroundColor,
+    },
+  ];
 
   return (
     <View
@@ -22,7 +122,8 @@ const Advanced = () => {
         flex: 1,
       }}
     >
-      <View
+      <ScrollView
+        id={themeType === "dark" ? "scroll" : "scroll-light"}
         style={{
           height: "100%",
           width: "100%",
@@ -49,8 +150,154 @@ const Advanced = () => {
             {t("settings_advanced_discord_status")}
           </Text>
         </Pressable>
-      </View>
-      <View style={styles.pathInputContainer}></View>
+        <Text
+          semibold
+          color={theme.textPrimary}
+          size={2}
+          style={{ marginTop: sc(15) }}
+        >
+          {t("settings_advanced_server_lists")}:
+        </Text>
+        {lists.map((list) => (
+          <View key={list.name} style={styles.pathInputContainer}>
+            <Text color={theme.textPrimary} size={2} style={{ flex: 1 }}>
+              {list.name} ({list.location})
+            </Text>
+            <TouchableOpacity
+              style={[styles.browseButton, { backgroundColor: "red" }]}
+              onPress={() => removeList(list.name)}
+            >
+              <Text semibold color={"#FFFFFF"} size={2}>
+                {t("remove")}
+              </Text>
+            </TouchableOpacity>
+          </View>
+        ))}
+        <View style={styles.pathInputContainer}>
+          <TextInput
+            placeholder={t("settings_advanced_server_list_name")}
+            placeholderTextColor={theme.textPlaceholder}
+            value={listName}
+            onChangeText={(text) => setListName(text)}
+            style={[inputStyle, { flex: 0.3, marginRight: sc(10) }]}
+          />
+          <TextInput
+            placeholder={t("settings_advanced_server_list_location")}
+            placeholderTextColor={theme.textPlaceholder}
+            value={listLocation}
+            onChangeText={(text) => setListLocation(text)}
+            onSubmitEditing={() => addList()}
+            style={inputStyle}
+          />
+          <TouchableOpacity
+            style={[styles.browseButton, { backgroundColor: theme.primary }]}
+            onPress={() => addList()}
+          >
+            <Text semibold color={"#FFFFFF"} size={2}>
+              {t("add")}
+            </Text>
+          </TouchableOpacity>
+        </View>
+        <Text
+          semibold
+          color={theme.textPrimary}
+          size={2}
+          style={{ marginTop: sc(15) }}
+        >
+          {t("settings_advanced_friends")}:
+        </Text>
+        {friendSettings?.friends.map((name) => (
+          <View key={name} style={styles.pathInputContainer}>
+            <Text color={theme.textPrimary} size={2} style={{ flex: 1 }}>
+              {name}
+            </Text>
+            <TouchableOpacity
+              style={[styles.browseButton, { backgroundColor: "red" }]}
+              onPress={() =>
+                updateFriendSettings({
+                  friends: friendSettings.friends.filter((f) => f !== name),
+                })
+              }
+            >
+              <Text semibold color={"#FFFFFF"} size={2}>
+                {t("remove")}
+              </Text>
+            </TouchableOpacity>
+          </View>
+        ))}
+        <View style={styles.pathInputContainer}>
+          <TextInput
+            placeholder={t("settings_advanced_friend_name")}
+            placeholderTextColor={theme.textPlaceholder}
+            value={friendName}
+            onChangeText={(text) => setFriendName(text)}
+            onSubmitEditing={() => addFriend()}
+            style={inputStyle}
+          />
+          <TouchableOpacity
+            style={[styles.browseButton, { backgroundColor: theme.primary }]}
+            onPress={() => addFriend()}
+          >
+            <Text semibold color={"#FFFFFF"} size={2}>
+              {t("add")}
+            </Text>
+          </TouchableOpacity>
+        </View>
+        <View style={styles.pathInputContainer}>
+          <Text color={theme.textPrimary} size={2} style={{ flex: 1 }}>
+            {t("settings_advanced_friends_interval")}
+          </Text>
+          <TextInput
+            value={friendInterval}
+            onChangeText={(text) => setFriendInterval(text)}
+            onBlur={() =>
+              updateFriendSettings({ interval: parseInt(friendInterval) || 0 })
+            }
+            style={[inputStyle, { flex: 0.3 }]}
+          />
+        </View>
+        <View style={styles.pathInputContainer}>
+          <Text color={theme.textPrimary} size={2} style={{ flex: 1 }}>
+            {t("settings_advanced_friends_popular")}
+          </Text>
+          <TextInput
+            value={friendPopular}
+            onChangeText={(text) => setFriendPopular(text)}
+            onBlur={() =>
+              updateFriendSettings({ popular: parseInt(friendPopular) || 0 })
+            }
+            style={[inputStyle, { flex: 0.3 }]}
+          />
+        </View>
+        <Pressable
+          style={[styles.pathInputContainer, { marginTop: sc(10) }]}
+          onPress={() =>
+            updateFriendSettings({ favorites: !friendSettings?.favorites })
+          }
+        >
+          <CheckBox
+            value={!!friendSettings?.favorites}
+            style={{ marginRight: sc(7) }}
+          />
+          <Text semibold color={theme.textPrimary} size={2}>
+            {t("settings_advanced_friends_favorites")}
+          </Text>
+        </Pressable>
+        <Pressable
+          style={[styles.pathInputContainer, { marginTop: sc(10) }]}
+          onPress={() =>
+            updateFriendSettings({ notify: !friendSettings?.notify })
+          }
+        >
+          <CheckBox
+            value={!!friendSettings?.notify}
+            style={{ marginRight: sc(7) }}
+          />
+          <Text semibold color={theme.textPrimary} size={2}>
+            {t("settings_advanced_friends_notify")}
+          </Text>
+        </Pressable>
+      </ScrollView>
     </View>
   );
 };
@@ -63,22 +310,21 @@ const styles = StyleSheet.create({
     marginTop: 7,
   },
   pathInput: {
-    paddingHorizontal: 5,
+    paddingHorizontal: sc(10),
     flex: 1,
-    backgroundColor: "#FFFFFF",
-    height: 29,
-    borderRadius: 8,
-    borderWidth: 2,
+    height: sc(38),
+    borderRadius: sc(5),
     outlineStyle: "none",
+    fontFamily: "Proxima Nova Regular",
+    fontSize: sc(17),
   },
   browseButton: {
-    height: 30,
-    paddingHorizontal: 10,
-    borderRadius: 8,
-    marginLeft: 5,
+    height: sc(36),
+    paddingHorizontal: sc(15),
+    borderRadius: sc(5),
+    marginLeft: sc(10),
     justifyContent: "center",
     alignItems: "center",
-    borderWidth: 2,
   },
   importButton: {
     marginTop: 10,
@@ -3,6 +3,7 @@ export default {
   internet: "Internet",
   partners: "Partners",
   recently_joined: "Recently Joined",
+  lan: "LAN",
   nickname: "Nickname",
   settings: "Settings",
   minimize: "Minimize",
@@ -11,6 +12,7 @@ export default {
   add_server_modal_description_1: "Add server manually to favorite list.",
   add_server_modal_description_2: "Example: 127.0.0.1:7777",
   add: "Add",
+  remove: "Remove",
   server: "Server",
   address: "Address",
   players: "Players",
@@ -26,6 +28,12 @@ export default {
   filter_only_omp_servers: "Only open.mp servers",
   filter_non_empty_servers: "Non-empty Servers",
   filter_unpassworded_servers: "Unpassworded Servers",
+  filter_expression: "Expression",
+  filter_expression_placeholder: 'players >= 50 && lang ~ "ru"',
+  filter_expression_error: "{{ message }} (column {{ position }})",
+  saved_filters: "Saved filters",
+  filter_name: "Filter name",
+  save: "Save",
   rule: "Rule",
   value: "Value",
   player: "Player",
@@ -39,6 +47,7 @@ export default {
   filter_servers: "Filter Servers",
   search_for_server_hostname_mode: "Search for server hostname/mode",
   clear_recently_joined_list: "Clear Recently Joined List",
+  rescan_lan: "Search LAN Again",
   refresh_servers: "Refresh Server List",
   play: "Play",
   remove_selected_server_from_favorites:
@@ -48,6 +57,7 @@ export default {
   hide_player_and_rule_list: "Hide player and rule list",
   show_player_and_rule_list: "Show player and rule list",
   copy_server_info: "Copy Server Info",
+  retry_now: "Retry Now",
   settings_gta_path_input_label:
     "GTA: San Andreas path (where SA-MP is also installed)",
   browse: "BROWSE",
@@ -90,9 +100,36 @@ export default {
   settings_lang_tab_title: "Languages",
   settings_advanced_tab_title: "Advanced",
   settings_advanced_discord_status: "Enable Discord status",
+  settings_advanced_server_lists: "Additional server lists",
+  settings_advanced_server_list_name: "Name",
+  settings_advanced_server_list_location: "URL or file path",
+  settings_advanced_friends: "Friends",
+  settings_advanced_friend_name: "Nickname",
+  settings_advanced_friends_interval: "Check every (seconds)",
+  settings_advanced_friends_popular: "Most populated servers to check",
+  settings_advanced_friends_favorites: "Check favorite servers",
+  settings_advanced_friends_notify: "Show system notifications",
+  friend_online_title: "{{ name }} is online",
+  friend_online_description: "Playing on {{ server }}, click to join",
+  friend_offline_title: "{{ name }} went offline",
+  friend_offline_description: "Left {{ server }}",
+  notify_when_slot_opens: "Notify When a Slot Opens",
+  leave_slot_queue: "Leave Queue",
+  join_when_slot_opens: "Join When a Slot Opens",
+  slot_open_title: "A slot opened",
+  slot_open_description: "{{ server }} has a free slot, click to join",
+  slot_queue_timed_out_title: "Stopped waiting",
+  slot_queue_timed_out_description: "{{ server }} stayed full",
+  slot_queue_unreachable_title: "Server isn't answering",
+  slot_queue_unreachable_description:
+    "{{ server }} stopped answering, still waiting for a slot",
   join_discord: "Join Discord Server",
   samp_version: "SA-MP Version",
   change_version: "Change Version",
   offline: "Offline",
   from_gtasa_folder: "From GTASA Folder",
+  player_list_withheld:
+    "This server has more than 100 players online, so it doesn't send its player list.",
+  player_list_truncated: "The server sent an incomplete player list.",
+  player_list_timed_out: "The server didn't respond with its player list.",
 };
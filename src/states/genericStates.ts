@@ -24,6 +24,7 @@ const useGenericTempState = create<GenericTempStates>()((set, get) => ({
     sortName: "none",
     sortMode: "none",
     languages: [],
+    expression: "",
   },
   listType: "favorites",
   showFilterMenu: (show) => set(() => ({ filterMenu: show })),
@@ -3,13 +3,16 @@ import { create } from "zustand";
 import { createJSONStorage, persist } from "zustand/middleware";
 import { PerServerSettings, SAMPDLLVersions, Server } from "../utils/types";
 import { useNotification } from "./notification";
-import { queryServer } from "../utils/query";
+import { queryServer, subscribeServers } from "../utils/query";
 
 interface ServersState {
   servers: Server[];
+  lanServers: Server[];
   selected: undefined | Server;
   setSelected: (server: undefined | Server) => void;
   setServers: (list: Server[]) => void;
+  setLanServers: (list: Server[]) => void;
+  addLanServer: (server: Server) => void;
   updateServer: (server: Server) => void;
 }
 
@@ -34,12 +37,22 @@ interface ServersPersistentState {
 
 const useServers = create<ServersState>()((set, get) => ({
   servers: [],
+  lanServers: [],
   selected: undefined,
   setSelected: (server) => set(() => ({ selected: server })),
   setServers: (list) => set(() => ({ servers: list })),
+  setLanServers: (list) => set(() => ({ lanServers: list })),
+  addLanServer: (server) =>
+    set(() => {
+      const list = get().lanServers.filter(
+        (srv) => srv.ip !== server.ip || srv.port !== server.port
+      );
+      return { lanServers: [...list, server] };
+    }),
   updateServer: (server) =>
     set(() => {
       const list = [...get().servers];
+      const lanList = [...get().lanServers];
 
       const index = list.findIndex(
         (srv) => srv.ip === server.ip && srv.port === server.port
@@ -48,7 +61,14 @@ const useServers = create<ServersState>()((set, get) => ({
         list[index] = { ...server };
       }
 
-      return { servers: list };
+      const lanIndex = lanList.findIndex(
+        (srv) => srv.ip === server.ip && srv.port === server.port
+      );
+      if (lanIndex !== -1) {
+        lanList[lanIndex] = { ...server };
+      }
+
+      return { servers: list, lanServers: lanList };
     }),
 }));
 
@@ -106,6 +126,7 @@ const usePersistentServers = create<ServersPersistentState>()(
           );
 
           queryServer(server, "favorites", "basic");
+          subscribeServers(cpy, "favorites");
 
           return { favorites: cpy };
         }),
@@ -118,6 +139,7 @@ const usePersistentServers = create<ServersPersistentState>()(
           if (findIndex !== -1) {
             cpy.splice(findIndex, 1);
           }
+          subscribeServers(cpy, "favorites");
           return { favorites: cpy };
         }),
       addToRecentlyJoined: (server) =>
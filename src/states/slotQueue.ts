@@ -0,0 +1,28 @@
+import { create } from "zustand";
+import { Server } from "../utils/types";
+
+interface SlotQueueState {
+  // "ip:port" of every server we're waiting on a free slot of
+  queued: string[];
+  add: (server: { ip: string; port: number }) => void;
+  remove: (server: { ip: string; port: number }) => void;
+  isQueued: (server: Server) => boolean;
+}
+
+const key = (server: { ip: string; port: number }) =>
+  `${server.ip}:${server.port}`;
+
+const useSlotQueue = create<SlotQueueState>()((set, get) => ({
+  queued: [],
+  add: (server) =>
+    set((state) => ({
+      queued: [...state.queued.filter((q) => q !== key(server)), key(server)],
+    })),
+  remove: (server) =>
+    set((state) => ({
+      queued: state.queued.filter((q) => q !== key(server)),
+    })),
+  isQueued: (server) => get().queued.includes(key(server)),
+}));
+
+export { useSlotQueue };
@@ -0,0 +1,116 @@
+import { invoke } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event";
+import {
+  isPermissionGranted,
+  requestPermission,
+  sendNotification,
+} from "@tauri-apps/api/notification";
+import { t } from "i18next";
+import { getFriendSettings } from "../api/apis";
+import { useJoinServerPrompt } from "../states/joinServerPrompt";
+import { useNotification } from "../states/notification";
+import { usePersistentServers, useServers } from "../states/servers";
+import { Log } from "./logger";
+import { FriendSettings, FriendStatus, Server } from "./types";
+
+let settings: FriendSettings | undefined;
+let watchedFavorites = "";
+
+const favoritesKey = (favorites: Server[]) =>
+  favorites.map((server) => `${server.ip}:${server.port}`).join(",");
+
+// friends can be on servers that aren't on any of our lists, those get joined
+// by address alone
+const findServer = (ip: string, port: number): Server => {
+  const { servers } = useServers.getState();
+  const { favorites, recentlyJoined } = usePersistentServers.getState();
+  const server = [...favorites, ...recentlyJoined, ...servers].find(
+    (server) => server.ip === ip && server.port === port
+  );
+  if (server) return server;
+
+  return {
+    ip,
+    port,
+    hostname: `${ip}:${port}`,
+    playerCount: 0,
+    maxPlayers: 0,
+    gameMode: "-",
+    language: "-",
+    hasPassword: false,
+    version: "-",
+    usingOmp: false,
+    partner: false,
+    ping: 0,
+    players: [],
+    password: "",
+    rules: {} as Server["rules"],
+  };
+};
+
+// (re)starts checking favorites and the most populated servers for friends,
+// has to be called again once the settings change
+export const watchFriends = async () => {
+  try {
+    settings = await getFriendSettings();
+    const { favorites } = usePersistentServers.getState();
+    watchedFavorites = favoritesKey(favorites);
+    await invoke<number>("watch_friends", {
+      favorites: favorites.map((server) => ({
+        ip: server.ip,
+        port: server.port,
+      })),
+    });
+
+    if (
+      settings.notify &&
+      settings.friends.length &&
+      !(await isPermissionGranted())
+    ) {
+      await requestPermission();
+    }
+  } catch (e) {
+    Log.debug("[friends.ts: watchFriends]", e);
+  }
+};
+
+// favorites get replaced all the time with fresh query data, only a change of
+// which servers they are is worth starting over for
+usePersistentServers.subscribe((state) => {
+  if (settings && favoritesKey(state.favorites) !== watchedFavorites) {
+    watchFriends();
+  }
+});
+
+listen<FriendStatus>("friend-status", async (event) => {
+  const { name, ip, port, online } = event.payload;
+  const server = findServer(ip, port);
+
+  const title = t(online ? "friend_online_title" : "friend_offline_title", {
+    name,
+  });
+  const description = t(
+    online ? "friend_online_description" : "friend_offline_description",
+    { server: server.hostname }
+  );
+
+  useNotification.getState().showNotification(
+    title,
+    description,
+    online
+      ? () => {
+          const { setServer, showPrompt } = useJoinServerPrompt.getState();
+          setServer(server);
+          showPrompt(true);
+        }
+      : undefined
+  );
+
+  try {
+    if (settings?.notify && (await isPermissionGranted())) {
+      sendNotification({ title, body: description });
+    }
+  } catch (e) {
+    Log.debug("[friends.ts: friend-status]", e);
+  }
+});
@@ -2,6 +2,7 @@ import { fs, invoke, path, process } from "@tauri-apps/api";
 import { message } from "@tauri-apps/api/dialog";
 import { exists } from "@tauri-apps/api/fs";
 import { t } from "i18next";
+import { queueForSlot } from "../api/apis";
 import { ResourceInfo, validFileChecksums } from "../constants/app";
 import { useJoinServerPrompt } from "../states/joinServerPrompt";
 import { useMessageBox } from "../states/messageModal";
@@ -43,17 +44,16 @@ export const checkResourceFilesAvailability = async () => {
   return Promise.all(promises);
 };
 
-export const startGame = async (
+// checks everything joining needs and returns the samp.dll to inject, or
+// undefined once the user has been told what's missing
+const prepareLaunch = async (
   server: Server,
   nickname: string,
   gtasaPath: string,
   password: string
 ) => {
-  const {
-    addToRecentlyJoined,
-    updateInFavoritesList,
-    updateInRecentlyJoinedList,
-  } = usePersistentServers.getState();
+  const { updateInFavoritesList, updateInRecentlyJoinedList } =
+    usePersistentServers.getState();
   const { updateServer } = useServers.getState();
   const { showMessageBox, hideMessageBox } = useMessageBox.getState();
   const { show: showSettings } = useSettingsModal.getState();
@@ -163,6 +163,54 @@ export const startGame = async (
   let sampDllPath =
     sampVersion === "custom" ? idealSAMPDllPath : ourSAMPDllPath;
 
+  return sampDllPath;
+};
+
+// errors come from the inject command, or from a slot queue that joined on
+// its own
+export const handleInjectError = (e: string) => {
+  const { showMessageBox, hideMessageBox } = useMessageBox.getState();
+
+  if (e == "need_admin") {
+    showMessageBox({
+      title: t("admin_permissions_required_modal_title"),
+      description: t("admin_permissions_required_modal_description"),
+      buttons: [
+        {
+          title: t("run_as_admin"),
+          onPress: async () => {
+            await invoke("rerun_as_admin").then(() => {
+              process.exit();
+            });
+          },
+        },
+        {
+          title: t("cancel"),
+          onPress: () => hideMessageBox(),
+        },
+      ],
+    });
+  }
+};
+
+export const startGame = async (
+  server: Server,
+  nickname: string,
+  gtasaPath: string,
+  password: string
+) => {
+  const { addToRecentlyJoined } = usePersistentServers.getState();
+
+  const sampDllPath = await prepareLaunch(
+    server,
+    nickname,
+    gtasaPath,
+    password
+  );
+  if (sampDllPath === undefined) {
+    return;
+  }
+
   invoke("inject", {
     name: nickname,
     ip: server.ip,
@@ -174,28 +222,35 @@ export const startGame = async (
     .then(() => {
       addToRecentlyJoined(server);
     })
-    .catch(async (e) => {
-      if (e == "need_admin") {
-        showMessageBox({
-          title: t("admin_permissions_required_modal_title"),
-          description: t("admin_permissions_required_modal_description"),
-          buttons: [
-            {
-              title: t("run_as_admin"),
-              onPress: async () => {
-                await invoke("rerun_as_admin").then(() => {
-                  process.exit();
-                });
-              },
-            },
-            {
-              title: t("cancel"),
-              onPress: () => hideMessageBox(),
-            },
-          ],
-        });
-      }
-    });
+    .catch(handleInjectError);
+};
+
+// same as startGame, but for a full server. The backend waits for a slot and
+// joins as soon as one opens, resolves with whether the server got queued
+export const queueToJoin = async (
+  server: Server,
+  nickname: string,
+  gtasaPath: string,
+  password: string
+) => {
+  const sampDllPath = await prepareLaunch(
+    server,
+    nickname,
+    gtasaPath,
+    password
+  );
+  if (sampDllPath === undefined) {
+    return false;
+  }
+
+  await queueForSlot(server, {
+    kind: "join",
+    name: nickname,
+    exe: gtasaPath,
+    dll: sampDllPath,
+    password: password,
+  });
+  return true;
 };
 
 export const checkDirectoryValidity = async (
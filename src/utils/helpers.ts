@@ -1,4 +1,4 @@
-import { shell } from "@tauri-apps/api";
+import { invoke, shell } from "@tauri-apps/api";
 import { getVersion } from "@tauri-apps/api/app";
 import { type } from "@tauri-apps/api/os";
 import { t } from "i18next";
@@ -7,43 +7,29 @@ import { useAppState } from "../states/app";
 import { useMessageBox } from "../states/messageModal";
 import { usePersistentServers, useServers } from "../states/servers";
 import { Log } from "./logger";
-import { queryServer } from "./query";
+import { subscribeServers } from "./query";
 import {
-  APIResponseServer,
+  FilterCriteria,
+  FilterSortKey,
+  ListedServer,
   Player,
   SAMPDLLVersions,
   SearchData,
   Server,
 } from "./types";
 
-const PARALLEL_SERVERS_TO_UPDATE_COUNT = 5;
-const PARALLEL_SERVERS_TO_UPDATE_TIMER_INTERVAL = 2000;
-
 export const languageFilters: {
   name: string;
   keywords: string[];
 }[] = [];
 
-export const mapAPIResponseServerListToAppStructure = (
-  list: APIResponseServer[]
-) => {
+export const mapMasterListToAppStructure = (list: ListedServer[]) => {
   const restructuredList: Server[] = list.map((server) => {
     return {
-      hostname: server.core.hn,
-      gameMode: server.core.gm,
-      ip: server.core.ip.split(":")[0],
-      port: parseInt(server.core.ip.split(":")[1]),
-      language: server.core.la,
-      hasPassword: server.core.pa,
-      playerCount: server.core.pc,
-      maxPlayers: server.core.pm,
-      version: server.core.vn,
-      rules: server.ru,
+      ...server,
       players: [] as Player[],
       ping: 0,
       password: "",
-      usingOmp: server.core.omp,
-      partner: server.core.pr,
     } as Server;
   });
 
@@ -55,23 +41,7 @@ export const fetchServers = async (cached: boolean = true) => {
     const { favorites } = usePersistentServers.getState();
     if (Array.isArray(favorites)) {
       // let's query servers from server list so players have updated data
-      for (
-        let i = 0;
-        i < favorites.length;
-        i += PARALLEL_SERVERS_TO_UPDATE_COUNT
-      ) {
-        setTimeout(() => {
-          for (
-            let offset = 0;
-            offset < PARALLEL_SERVERS_TO_UPDATE_COUNT;
-            offset++
-          ) {
-            if (favorites[i + offset]) {
-              queryServer(favorites[i + offset], "favorites", "basic");
-            }
-          }
-        }, 500 + (i % PARALLEL_SERVERS_TO_UPDATE_COUNT) * PARALLEL_SERVERS_TO_UPDATE_TIMER_INTERVAL);
-      }
+      subscribeServers(favorites, "favorites");
     }
 
     const response = await getCachedList();
@@ -80,22 +50,7 @@ export const fetchServers = async (cached: boolean = true) => {
     Log.debug(response);
     if (Array.isArray(response.servers)) {
       // let's query servers from server list so players have updated data
-      for (
-        let i = 0;
-        i < response.servers.length;
-        i += PARALLEL_SERVERS_TO_UPDATE_COUNT
-      ) {
-        setTimeout(() => {
-          for (
-            let offset = 0;
-            offset < PARALLEL_SERVERS_TO_UPDATE_COUNT;
-            offset++
-          ) {
-            if (response.servers[i + offset])
-              queryServer(response.servers[i + offset], "internet", "basic");
-          }
-        }, 500 + (i / PARALLEL_SERVERS_TO_UPDATE_COUNT) * PARALLEL_SERVERS_TO_UPDATE_TIMER_INTERVAL);
-      }
+      subscribeServers(response.servers, "internet");
     }
   }
 };
@@ -309,6 +264,61 @@ export const sortAndSearchInServerList = (
   return list;
 };
 
+export const searchDataToCriteria = (
+  searchData: SearchData,
+  checkForPartnership = false
+): FilterCriteria => {
+  const { sortPing, sortPlayer, sortName, sortMode } = searchData;
+
+  // same outcome as the sorts above, which run one after another so the last
+  // one decides. "descending" has always meant lowest first there
+  const sort: FilterCriteria["sort"] = [];
+  const sorts: [FilterSortKey, SearchData["sortPing"]][] = [
+    ["mode", sortMode],
+    ["name", sortName],
+    ["players", sortPlayer],
+    ["ping", sortPing],
+  ];
+  sorts.forEach(([key, type]) => {
+    if (type !== "none") {
+      sort.push({
+        key,
+        order: type === "descending" ? "ascending" : "descending",
+      });
+    }
+  });
+
+  return {
+    query: searchData.query,
+    omp_only: searchData.ompOnly,
+    non_empty: searchData.nonEmpty,
+    unpassworded: searchData.unpassworded,
+    partners_only: checkForPartnership,
+    languages: searchData.languages.flatMap(
+      (lang) => languageFilters.find((l) => l.name === lang)?.keywords ?? []
+    ),
+    expression: searchData.expression,
+    sort,
+  };
+};
+
+// the internet list is kept in the backend as well, filtering it there keeps
+// the search bar responsive with thousands of servers
+export const filterServersInBackend = async (
+  servers: Server[],
+  searchData: SearchData,
+  checkForPartnership = false
+) => {
+  const ids = await invoke<string[]>("filter_servers", {
+    criteria: searchDataToCriteria(searchData, checkForPartnership),
+  });
+
+  const byId = new Map(servers.map((s) => [`${s.ip}:${s.port}`, s]));
+  return ids
+    .map((id) => byId.get(id))
+    .filter((server): server is Server => server !== undefined);
+};
+
 const addLanguageFilter = (name: string, keywords: string[]) => {
   const findIndex = languageFilters.findIndex((l) => l.name === name);
   if (findIndex == -1) {
@@ -1,10 +1,26 @@
 import { invoke } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event";
+import { useGenericTempState } from "../states/genericStates";
 import { usePersistentServers, useServers } from "../states/servers";
 import { Log } from "./logger";
-import { ListType, Server } from "./types";
-
-const OMP_EXTRA_INFO_CHECK_DELAY = 5000; // 10 seconds;
-const ompExtraInfoLastCheck: { [x: string]: number } = {};
+import {
+  Cached,
+  LanDiscoveryOptions,
+  LanServer,
+  ListType,
+  PingStats,
+  PlayerListResult,
+  QueryExtraInfo,
+  QueryPriority,
+  QueryInfo,
+  Server,
+  ServerDiff,
+  ServerHealth,
+  ServerUpdate,
+} from "./types";
+
+// error the backend gives instead of querying a server that keeps timing out
+const BACKED_OFF = "backed_off";
 
 export const queryServer = (
   server: Server,
@@ -20,16 +36,7 @@ export const queryServer = (
 
     if (queryType === "all") {
       getServerPlayers(ip, port, listType);
-      if (ompExtraInfoLastCheck[`${ip}:${port}`]) {
-        if (
-          Date.now() - ompExtraInfoLastCheck[`${ip}:${port}`] >
-          OMP_EXTRA_INFO_CHECK_DELAY
-        ) {
-          getServerOmpExtraInfo(ip, port, listType);
-        }
-      } else {
-        getServerOmpExtraInfo(ip, port, listType);
-      }
+      getServerOmpExtraInfo(ip, port, listType);
     }
   } catch (error) {
     Log.debug("[query.ts: queryServer]", error);
@@ -38,35 +45,78 @@ export const queryServer = (
 
 const getServerInfo = async (ip: string, port: number, listType: ListType) => {
   try {
-    const serverInfo = await invoke<string>("request_server_info", {
-      ip: ip,
-      port: port,
-    });
+    const { data, changed } = await invoke<Cached<QueryInfo>>(
+      "request_server_info",
+      {
+        ip: ip,
+        port: port,
+      }
+    );
 
-    if (serverInfo === "no_data") {
-      return Log.debug(
-        "[query.ts: getServerInfo]",
-        "There was a problem getting server main info"
-      );
+    if (changed) {
+      applyServerInfo(ip, port, listType, data);
+    }
+  } catch (e) {
+    if (e === BACKED_OFF) {
+      return applyServerHealth(ip, port, listType);
     }
+    Log.debug("[query.ts: getServerInfo]", e);
+  }
+};
 
-    let queryObj = JSON.parse(serverInfo);
-    const data = {
-      hasPassword: queryObj.password,
-      playerCount: queryObj.players,
-      maxPlayers: queryObj.max_players,
-      hostname: queryObj.hostname,
-      gameMode: queryObj.gamemode,
-      language: queryObj.language,
-    };
+const applyServerInfo = (
+  ip: string,
+  port: number,
+  listType: ListType,
+  queryObj: QueryInfo
+) => {
+  const data = {
+    hasPassword: queryObj.password,
+    playerCount: queryObj.players,
+    maxPlayers: queryObj.max_players,
+    hostname: queryObj.hostname,
+    gameMode: queryObj.gamemode,
+    language: queryObj.language,
+  };
+
+  let server = getServerFromList(ip, port, listType);
+  if (server) {
+    server = { ...server, ...data, health: undefined };
+    updateServerEveryWhere(server);
+  }
+};
+
+const applyServerHealth = async (
+  ip: string,
+  port: number,
+  listType: ListType
+) => {
+  try {
+    const health = await invoke<ServerHealth>("get_server_health", {
+      ip: ip,
+      port: port,
+    });
 
     let server = getServerFromList(ip, port, listType);
     if (server) {
-      server = { ...server, ...data };
+      server = { ...server, health: health };
       updateServerEveryWhere(server);
     }
   } catch (e) {
-    Log.debug("[query.ts: getServerInfo]", e);
+    Log.debug("[query.ts: applyServerHealth]", e);
+  }
+};
+
+// skips the backoff of a server that hasn't been answering and queries it
+export const retryServer = async (
+  server: Server,
+  listType: ListType = "internet"
+) => {
+  try {
+    await invoke("retry_server_now", { ip: server.ip, port: server.port });
+    queryServer(server, listType);
+  } catch (e) {
+    Log.debug("[query.ts: retryServer]", e);
   }
 };
 
@@ -76,30 +126,38 @@ const getServerPlayers = async (
   listType: ListType
 ) => {
   try {
-    const serverPlayers = await invoke<string>("request_server_players", {
-      ip: ip,
-      port: port,
-    });
-
-    if (serverPlayers === "no_data") {
-      return Log.debug(
-        "[query.ts: getServerPlayers]",
-        "There was a problem getting server player list"
+    // 'd' gives us ids and pings too, but plenty of servers refuse it
+    let result = await invoke<Cached<PlayerListResult>>(
+      "request_server_detailed_players",
+      {
+        ip: ip,
+        port: port,
+      }
+    ).catch(() => undefined);
+
+    if (!result || result.data.status === "timed_out") {
+      result = await invoke<Cached<PlayerListResult>>(
+        "request_server_players",
+        {
+          ip: ip,
+          port: port,
+        }
       );
     }
 
-    let server = getServerFromList(ip, port, listType);
+    const { data, changed } = result;
+    if (!changed) {
+      return;
+    }
 
+    let server = getServerFromList(ip, port, listType);
     if (server) {
-      let queryObj = JSON.parse(serverPlayers);
-
-      if (queryObj.error) {
-        server = { ...server };
-        updateServerEveryWhere(server);
-      } else if (Array.isArray(queryObj)) {
-        server = { ...server, players: [...queryObj] };
-        updateServerEveryWhere(server);
-      }
+      server = {
+        ...server,
+        players: data.status === "complete" ? [...data.data] : [],
+        playerListStatus: data.status,
+      };
+      updateServerEveryWhere(server);
     }
   } catch (e) {
     Log.debug("[query.ts: getServerPlayers]", e);
@@ -108,44 +166,54 @@ const getServerPlayers = async (
 
 const getServerRules = async (ip: string, port: number, listType: ListType) => {
   try {
-    const serverRules = await invoke<string>("request_server_rules", {
-      ip: ip,
-      port: port,
-    });
+    const { data, changed } = await invoke<Cached<[string, string][]>>(
+      "request_server_rules",
+      {
+        ip: ip,
+        port: port,
+      }
+    );
 
-    if (serverRules === "no_data" || !Array.isArray(JSON.parse(serverRules))) {
-      return Log.debug(
-        "[query.ts: getServerRules]",
-        "There was a problem getting server rule list"
-      );
+    if (changed) {
+      applyServerRules(ip, port, listType, data);
     }
+  } catch (e) {
+    Log.debug("[query.ts: getServerRules]", e);
+  }
+};
 
-    let server = getServerFromList(ip, port, listType);
+const applyServerRules = (
+  ip: string,
+  port: number,
+  listType: ListType,
+  serverRules: [string, string][]
+) => {
+  let server = getServerFromList(ip, port, listType);
 
-    if (server) {
-      let queryObj = JSON.parse(serverRules);
-      const rules: Server["rules"] = {} as Server["rules"];
+  if (server) {
+    server = { ...server, ...parseServerRules(serverRules) };
+    updateServerEveryWhere(server);
+  }
+};
 
-      queryObj.forEach((rule: [string, string]) => {
-        rules[rule[0]] = rule[1];
-      });
+const parseServerRules = (serverRules: [string, string][]) => {
+  const rules: Server["rules"] = {} as Server["rules"];
 
-      let isOmp = false;
+  serverRules.forEach((rule: [string, string]) => {
+    rules[rule[0]] = rule[1];
+  });
 
-      if (rules["allow_DL"]) {
-        isOmp = true;
-      } else {
-        if (rules.version && rules.version.includes("omp ")) {
-          isOmp = true;
-        }
-      }
+  let isOmp = false;
 
-      server = { ...server, rules: rules, usingOmp: isOmp };
-      updateServerEveryWhere(server);
+  if (rules["allow_DL"]) {
+    isOmp = true;
+  } else {
+    if (rules.version && rules.version.includes("omp ")) {
+      isOmp = true;
     }
-  } catch (e) {
-    Log.debug("[query.ts: getServerRules]", e);
   }
+
+  return { rules: rules, usingOmp: isOmp };
 };
 
 const getServerOmpExtraInfo = async (
@@ -153,85 +221,226 @@ const getServerOmpExtraInfo = async (
   port: number,
   listType: ListType
 ) => {
-  ompExtraInfoLastCheck[`${ip}:${port}`] = Date.now();
-
   try {
-    const serverOmpExtraInfo = await invoke<string>(
+    const { data, changed } = await invoke<Cached<QueryExtraInfo>>(
       "request_server_omp_extra_info",
       {
         ip: ip,
         port: port,
       }
     );
-
-    console.log(serverOmpExtraInfo);
+    if (!changed) {
+      return;
+    }
 
     let server = getServerFromList(ip, port, listType);
     if (server) {
-      if (serverOmpExtraInfo === "no_data") {
-        return;
-      }
-
-      const data = JSON.parse(serverOmpExtraInfo);
       if (data) {
-        server = {
-          ...server,
-          omp: {
-            bannerLight:
-              data.light_banner_url && data.light_banner_url.length
-                ? data.light_banner_url
-                : undefined,
-            bannerDark:
-              data.dark_banner_url && data.dark_banner_url.length
-                ? data.dark_banner_url
-                : undefined,
-            discordInvite:
-              data.discord_link && data.discord_link.length
-                ? data.discord_link
-                : undefined,
-          },
-        };
+        server = { ...server, omp: parseOmpExtraInfo(data) };
         updateServerEveryWhere(server);
       }
     }
   } catch (e) {}
 };
 
+const parseOmpExtraInfo = (data: QueryExtraInfo): Server["omp"] => {
+  return {
+    bannerLight:
+      data.light_banner_url && data.light_banner_url.length
+        ? data.light_banner_url
+        : undefined,
+    bannerDark:
+      data.dark_banner_url && data.dark_banner_url.length
+        ? data.dark_banner_url
+        : undefined,
+    discordInvite:
+      data.discord_link && data.discord_link.length
+        ? data.discord_link
+        : undefined,
+  };
+};
+
 const getServerPing = async (ip: string, port: number, listType: ListType) => {
   try {
-    const serverPing = await invoke<string>("ping_server", {
-      ip: ip,
-      port: port,
-    });
+    const { data: stats, changed } = await invoke<Cached<PingStats>>(
+      "ping_server",
+      {
+        ip: ip,
+        port: port,
+      }
+    );
+    if (!changed) {
+      return;
+    }
 
-    let server = getServerFromList(ip, port, listType);
-    if (server) {
-      let ping = server.ping;
+    // 9999 is what the list shows as "-" for servers that didn't answer
+    applyServerPing(ip, port, listType, stats.median ?? 9999, stats);
+  } catch (e) {}
+};
 
-      if (typeof serverPing === "number") {
-        if (serverPing !== 9999) {
+const applyServerPing = (
+  ip: string,
+  port: number,
+  listType: ListType,
+  serverPing: number,
+  pingStats?: PingStats
+) => {
+  let server = getServerFromList(ip, port, listType);
+  if (server) {
+    let ping = server.ping;
+
+    if (typeof serverPing === "number") {
+      if (serverPing !== 9999) {
+        ping = serverPing;
+      } else {
+        if (server.ping === 0) {
           ping = serverPing;
-        } else {
-          if (server.ping === 0) {
-            ping = serverPing;
-          }
         }
       }
-
-      server = {
-        ...server,
-        ping: ping,
-      };
-      updateServerEveryWhere(server);
     }
-  } catch (e) {}
+
+    server = {
+      ...server,
+      ping: ping,
+      pingStats: pingStats ?? server.pingStats,
+    };
+    updateServerEveryWhere(server);
+  }
+};
+
+// how often the backend refreshes each list on its own
+const LIST_REFRESH_INTERVAL = 60 * 1000;
+const VISIBLE_REFRESH_INTERVAL = 10 * 1000;
+
+// subscriptions are named after the list they keep up to date, subscribing
+// the same name again replaces the previous set of servers and cancels
+// whatever was still running for it
+export const subscribeServers = (
+  servers: Server[],
+  listType: ListType,
+  interval: number = LIST_REFRESH_INTERVAL,
+  visibleOnly: boolean = false
+) => {
+  let priority: QueryPriority = "background";
+  if (visibleOnly) {
+    priority = "visible";
+  } else if (listType === "favorites") {
+    priority = "favorite";
+  }
+
+  invoke("subscribe_servers", {
+    name: visibleOnly ? `visible:${listType}` : listType,
+    servers: servers.map((server) => ({ ip: server.ip, port: server.port })),
+    queryTypes: ["i", "p", "r"],
+    priority: priority,
+    interval: interval,
+  }).catch((e) => Log.debug("[query.ts: subscribeServers]", e));
+};
+
+// rows on screen get refreshed more often, and ahead of everything else
+export const subscribeVisibleServers = (
+  servers: Server[],
+  listType: ListType
+) => subscribeServers(servers, listType, VISIBLE_REFRESH_INTERVAL, true);
+
+export const unsubscribeServers = (
+  listType: ListType,
+  visibleOnly: boolean = false
+) => {
+  invoke("unsubscribe_servers", {
+    name: visibleOnly ? `visible:${listType}` : listType,
+  }).catch((e) => Log.debug("[query.ts: unsubscribeServers]", e));
+};
+
+listen<ServerUpdate>("server-updated", (event) => {
+  const { subscription, ip, port, diff } = event.payload;
+  const listType = subscription.replace("visible:", "") as ListType;
+  applyServerDiff(ip, port, listType, diff);
+});
+
+const mapLanServer = ({ ip, port, ping, info }: LanServer): Server => ({
+  ip,
+  port,
+  hostname: info.hostname,
+  playerCount: info.players,
+  maxPlayers: info.max_players,
+  gameMode: info.gamemode,
+  language: info.language,
+  hasPassword: info.password,
+  version: "-",
+  usingOmp: false,
+  partner: false,
+  ping,
+  players: [],
+  password: "",
+  rules: {} as Server["rules"],
+});
+
+// sweeps the local subnets, servers show up in the lan tab as they answer and
+// are kept up to date like any other list once the sweep is done
+export const discoverLanServers = async (options?: LanDiscoveryOptions) => {
+  const { setLanServers, addLanServer } = useServers.getState();
+  setLanServers([]);
+
+  const unlisten = await listen<LanServer>("lan-server-found", (event) =>
+    addLanServer(mapLanServer(event.payload))
+  );
+  try {
+    await invoke<number>("discover_lan_servers", { options: options });
+  } catch (e) {
+    Log.debug("[query.ts: discoverLanServers]", e);
+  } finally {
+    unlisten();
+  }
+
+  // the tab may have been left while the sweep was still running
+  if (useGenericTempState.getState().listType === "lan") {
+    subscribeServers(useServers.getState().lanServers, "lan");
+  }
+};
+
+const applyServerDiff = (
+  ip: string,
+  port: number,
+  listType: ListType,
+  diff: ServerDiff
+) => {
+  let server = getServerFromList(ip, port, listType);
+  if (!server) {
+    return;
+  }
+
+  const data: Partial<Server> = {};
+  if (diff.password !== undefined) data.hasPassword = diff.password;
+  if (diff.players !== undefined) data.playerCount = diff.players;
+  if (diff.max_players !== undefined) data.maxPlayers = diff.max_players;
+  if (diff.hostname !== undefined) data.hostname = diff.hostname;
+  if (diff.gamemode !== undefined) data.gameMode = diff.gamemode;
+  if (diff.language !== undefined) data.language = diff.language;
+  if (diff.ping !== undefined) data.ping = diff.ping;
+  if (diff.rules) Object.assign(data, parseServerRules(diff.rules));
+  if (diff.player_list) data.players = diff.player_list;
+  if (diff.detailed_player_list) data.players = diff.detailed_player_list;
+  if (diff.extra_info) data.omp = parseOmpExtraInfo(diff.extra_info);
+
+  if (diff.online === true) {
+    data.health = undefined;
+  } else if (diff.online === false) {
+    // 9999 is what the list shows as "-" for servers that didn't answer
+    data.ping = 9999;
+    applyServerHealth(ip, port, listType);
+  }
+
+  server = { ...server, ...data };
+  updateServerEveryWhere(server);
 };
 
 const getListBasedOnType = (listType: ListType) => {
-  const { servers } = useServers.getState();
+  const { servers, lanServers } = useServers.getState();
   const { favorites, recentlyJoined } = usePersistentServers.getState();
 
   if (listType === "internet" || listType === "partners") return servers;
+  else if (listType === "lan") return lanServers;
   else if (listType === "favorites") return favorites;
   else if (listType === "recentlyjoined") return recentlyJoined;
   else return servers;
@@ -257,3 +466,19 @@ const updateServerEveryWhere = (server: Server) => {
     }
   }
 };
+
+// password falls back to the one saved in SA-MP's favorite list, errors are
+// "no_rcon_password", "invalid_rcon_password" or a network error message
+export const sendRconCommand = async (
+  ip: string,
+  port: number,
+  command: string,
+  password?: string
+) => {
+  return invoke<string[]>("send_rcon_command", {
+    ip: ip,
+    port: port,
+    password: password,
+    command: command,
+  });
+};
@@ -0,0 +1,106 @@
+import { listen } from "@tauri-apps/api/event";
+import { t } from "i18next";
+import { cancelSlotQueue, queueForSlot } from "../api/apis";
+import { useJoinServerPrompt } from "../states/joinServerPrompt";
+import { useNotification } from "../states/notification";
+import { usePersistentServers } from "../states/servers";
+import { useSlotQueue } from "../states/slotQueue";
+import { handleInjectError, queueToJoin } from "./game";
+import { Log } from "./logger";
+import { QueueEvent, Server } from "./types";
+
+// servers queued in this session, the events only carry an address
+const queuedServers: { [key: string]: Server } = {};
+// queued servers that stopped answering, so we only say so once
+const unreachable = new Set<string>();
+
+export const notifyWhenSlotOpens = async (server: Server) => {
+  try {
+    await queueForSlot(server, { kind: "notify" });
+    queuedServers[`${server.ip}:${server.port}`] = server;
+    useSlotQueue.getState().add(server);
+  } catch (e) {
+    Log.debug("[slotQueue.ts: notifyWhenSlotOpens]", e);
+  }
+};
+
+export const joinWhenSlotOpens = async (
+  server: Server,
+  nickname: string,
+  gtasaPath: string,
+  password: string
+) => {
+  try {
+    if (!(await queueToJoin(server, nickname, gtasaPath, password))) {
+      return;
+    }
+    queuedServers[`${server.ip}:${server.port}`] = server;
+    useSlotQueue.getState().add(server);
+  } catch (e) {
+    Log.debug("[slotQueue.ts: joinWhenSlotOpens]", e);
+  }
+};
+
+export const leaveSlotQueue = async (server: Server) => {
+  useSlotQueue.getState().remove(server);
+  try {
+    await cancelSlotQueue(server);
+  } catch (e) {
+    Log.debug("[slotQueue.ts: leaveSlotQueue]", e);
+  }
+};
+
+listen<QueueEvent>("slot-queue", (event) => {
+  const { ip, port } = event.payload;
+  const key = `${ip}:${port}`;
+  const server = queuedServers[key];
+  const hostname = server ? server.hostname : key;
+  const { showNotification } = useNotification.getState();
+
+  switch (event.payload.state) {
+    case "waiting":
+      unreachable.delete(key);
+      return;
+    case "unreachable":
+      // still waiting, but the user should know it might be for nothing
+      if (!unreachable.has(key)) {
+        unreachable.add(key);
+        showNotification(
+          t("slot_queue_unreachable_title"),
+          t("slot_queue_unreachable_description", { server: hostname })
+        );
+      }
+      return;
+    case "open":
+      showNotification(
+        t("slot_open_title"),
+        t("slot_open_description", { server: hostname }),
+        server
+          ? () => {
+              const { setServer, showPrompt } = useJoinServerPrompt.getState();
+              setServer(server);
+              showPrompt(true);
+            }
+          : undefined
+      );
+      break;
+    case "joined":
+      if (server) {
+        usePersistentServers.getState().addToRecentlyJoined(server);
+      }
+      break;
+    case "join_failed":
+      handleInjectError(event.payload.error);
+      break;
+    case "timed_out":
+      showNotification(
+        t("slot_queue_timed_out_title"),
+        t("slot_queue_timed_out_description", { server: hostname })
+      );
+      break;
+  }
+
+  useSlotQueue.getState().remove({ ip, port });
+  unreachable.delete(key);
+  delete queuedServers[key];
+});
@@ -7,9 +7,17 @@ export type Rule =
   | "weburl"
   | "worldtime";
 
-export type ListType = "favorites" | "internet" | "partners" | "recentlyjoined";
+export type ListType =
+  | "favorites"
+  | "internet"
+  | "partners"
+  | "recentlyjoined"
+  | "lan";
 export type SortType = "none" | "ascending" | "descending";
 
+// when the backend is busy, queries wait behind those of a more urgent priority
+export type QueryPriority = "selected" | "visible" | "favorite" | "background";
+
 export interface Server {
   ip: string;
   port: number;
@@ -23,7 +31,12 @@ export interface Server {
   usingOmp: boolean;
   partner: boolean;
   ping: number;
+  pingStats?: PingStats;
   players: Player[];
+  playerListStatus?: PlayerListResult["status"];
+  health?: ServerHealth;
+  // names of the lists the server was found on
+  sources?: string[];
   password: string;
   omp?: {
     bannerLight?: string;
@@ -56,9 +69,163 @@ export interface Server {
   };
 }
 
+export type PlayerListResult =
+  | { status: "complete"; data: Player[] }
+  | { status: "withheld"; data: { players: number } }
+  | { status: "truncated" }
+  | { status: "timed_out" };
+
+// only set while a server isn't answering, times are in milliseconds
+export interface ServerHealth {
+  failures: number;
+  last_success?: number;
+  retry_in?: number;
+}
+
+export interface PingStats {
+  sent: number;
+  received: number;
+  loss: number;
+  min?: number;
+  max?: number;
+  median?: number;
+  jitter?: number;
+}
+
 export interface Player {
+  id?: number;
   name: string;
   score: number;
+  ping?: number;
+}
+
+// interval is in seconds, popular is how many of the most populated internet
+// servers are checked besides favorites
+export interface FriendSettings {
+  friends: string[];
+  interval: number;
+  favorites: boolean;
+  popular: number;
+  notify: boolean;
+}
+
+export interface FriendStatus {
+  name: string;
+  ip: string;
+  port: number;
+  online: boolean;
+}
+
+// what the backend does once a full server has a free slot, join takes the
+// same arguments as the inject command
+export type OnSlot =
+  | { kind: "notify" }
+  | {
+      kind: "join";
+      name: string;
+      exe: string;
+      dll: string;
+      password: string;
+    };
+
+export type QueueState =
+  | { state: "waiting"; players: number; max_players: number }
+  | { state: "unreachable"; error: string }
+  | { state: "open"; players: number; max_players: number }
+  | { state: "joined" }
+  | { state: "join_failed"; error: string }
+  | { state: "timed_out" };
+
+export type QueueEvent = { ip: string; port: number } & QueueState;
+
+// averages over a stretch of a server's history, players are averaged over
+// the time the server was online and uptime goes from 0 to 1
+export interface PopulationStats {
+  players: number;
+  peak: number;
+  ping: number | null;
+  uptime: number;
+  samples: number;
+}
+
+// time is unix millis, the start of the hour for hourly points
+export type HistoryPoint = { time: number } & PopulationStats;
+
+export type HourOfDay = { hour: number } & PopulationStats;
+
+export type HistoryResolution = "raw" | "hourly";
+
+export type PlayerSearchMode = "exact" | "prefix" | "fuzzy";
+
+// a player seen on a server, last_seen is unix millis and is now for players
+// that are still online
+export interface PlayerHit {
+  name: string;
+  ip: string;
+  port: number;
+  online: boolean;
+  last_seen: number;
+}
+
+export interface QueryInfo {
+  password: boolean;
+  players: number;
+  max_players: number;
+  hostname: string;
+  gamemode: string;
+  language: string;
+}
+
+// a server that answered the lan sweep, found through the "lan-server-found"
+// event
+export interface LanServer {
+  ip: string;
+  port: number;
+  ping: number;
+  info: QueryInfo;
+}
+
+// ports are inclusive, timeout is how long to wait for replies in milliseconds
+export interface LanDiscoveryOptions {
+  first_port?: number;
+  last_port?: number;
+  timeout?: number;
+}
+
+export interface QueryExtraInfo {
+  discord_link: string;
+  light_banner_url: string;
+  dark_banner_url: string;
+}
+
+// only the fields that changed since the previous refresh are set
+export interface ServerDiff {
+  online?: boolean;
+  password?: boolean;
+  players?: number;
+  max_players?: number;
+  hostname?: string;
+  gamemode?: string;
+  language?: string;
+  ping?: number;
+  rules?: [string, string][];
+  player_list?: Player[];
+  detailed_player_list?: Player[];
+  extra_info?: QueryExtraInfo;
+}
+
+export interface ServerUpdate {
+  subscription: string;
+  ip: string;
+  port: number;
+  diff: ServerDiff;
+}
+
+// responses of single query commands, changed is false when the backend
+// returned the same data as the previous time
+export interface Cached<T> {
+  data: T;
+  changed: boolean;
 }
 
 export interface SearchData {
@@ -71,24 +238,69 @@ export interface SearchData {
   sortName: SortType;
   sortMode: SortType;
   languages: string[];
+  // e.g. `players >= 50 && lang ~ "ru"`, only the internet and partners
+  // lists are filtered by it, the backend evaluates it
+  expression: string;
 }
 
-export interface APIResponseServer {
-  core: {
-    gm: string;
-    hn: string;
-    ip: string;
-    la: string;
-    pa: boolean;
-    pc: number;
-    pm: number;
-    vn: string;
-    omp: boolean;
-    pr: boolean;
-  };
-  ru: {
-    [x: string]: string;
-  };
+export type FilterSortKey = "players" | "ping" | "name" | "mode";
+
+// what the backend filters and sorts the internet list by, the first sort
+// key decides and the following ones break ties
+export interface FilterCriteria {
+  query?: string;
+  hostname_regex?: string;
+  gamemode_regex?: string;
+  omp_only?: boolean;
+  non_empty?: boolean;
+  unpassworded?: boolean;
+  partners_only?: boolean;
+  languages?: string[];
+  players?: { min?: number; max?: number };
+  ping?: { min?: number; max?: number };
+  expression?: string;
+  sort?: { key: FilterSortKey; order: "ascending" | "descending" }[];
+}
+
+// servers as the backend gives them after validating and merging the lists,
+// servers only known by address from plain text lists have placeholder info
+export interface ListedServer {
+  ip: string;
+  port: number;
+  hostname: string;
+  gameMode: string;
+  language: string;
+  hasPassword: boolean;
+  playerCount: number;
+  maxPlayers: number;
+  version: string;
+  usingOmp: boolean;
+  partner: boolean;
+  rules: Server["rules"];
+  sources: string[];
+}
+
+export interface ServerLists {
+  servers: ListedServer[];
+  errors: { source: string; error: string }[];
+}
+
+// position is the column the expression went wrong at, starting at 1
+export interface FilterExpressionError {
+  position: number;
+  message: string;
+}
+
+export interface SavedFilter {
+  name: string;
+  expression: string;
+}
+
+// a user registered list, location is either an url or a local file path
+export interface CustomList {
+  name: string;
+  location: string;
+  refresh_interval: number;
 }
 
 export type SAMPDLLVersions =